@@ -0,0 +1,486 @@
+//! Bridges built from magnetic components, as in day 24: the search for the
+//! best bridge under an objective, and enumerations of all bridges.
+
+use failure::Error;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Add;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Port {
+    pub side1: usize,
+    pub side2: usize,
+}
+
+impl Port {
+    pub fn value(&self) -> usize {
+        self.side1 + self.side2
+    }
+
+    pub fn other(&self, pin: usize) -> usize {
+        if self.side1 == pin {
+            self.side2
+        } else if self.side2 == pin {
+            self.side1
+        } else {
+            panic!("{} does not match {}", pin, self)
+        }
+    }
+
+    pub fn has_pin(&self, pin: usize) -> bool {
+        self.side1 == pin || self.side2 == pin
+    }
+}
+
+impl FromStr for Port {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mut parts = s.split('/');
+        let side1 = parts.next().unwrap().parse()?;
+        let side2 = parts.next().unwrap().parse()?;
+        Ok(Port { side1, side2 })
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.side1, self.side2)
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Bridge {
+    pub ports: Vec<Port>,
+    pub value: usize,
+}
+
+impl Bridge {
+    pub fn new(ports: Vec<Port>) -> Bridge {
+        let value = Bridge::compute_value(&ports);
+        Bridge { ports, value }
+    }
+
+    fn compute_value(ports: &[Port]) -> usize {
+        ports.iter().map(Port::value).sum()
+    }
+
+    pub fn length(&self) -> usize {
+        self.ports.len()
+    }
+}
+
+/// Shows each port turned so that its matching pins face each other.
+impl fmt::Display for Bridge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut pin = 0;
+        for (num, port) in self.ports.iter().enumerate() {
+            let next_pin = port.other(pin);
+            write!(f, "{}/{}", pin, next_pin)?;
+            pin = next_pin;
+            if num != self.ports.len() - 1 {
+                write!(f, "--")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A way of scoring bridges, where the score of a bridge is the sum of the
+/// scores of its ports.
+///
+/// Since the sum is compared with `Ord`, adding the same score to two scores
+/// must not change their order. Scores of ports must also be at least `zero`:
+/// the solver always adds components with the same pin on both sides, and only
+/// tries the best of the components that end a bridge, which both assume that
+/// adding a component never makes a bridge worse.
+pub trait Objective {
+    type Score: Copy + Ord + Add<Output = Self::Score>;
+
+    fn zero(&self) -> Self::Score;
+    fn score(&self, port: &Port) -> Self::Score;
+}
+
+/// Maximise the strength of the bridge.
+pub struct Strength;
+
+impl Objective for Strength {
+    type Score = usize;
+
+    fn zero(&self) -> usize {
+        0
+    }
+    fn score(&self, port: &Port) -> usize {
+        port.value()
+    }
+}
+
+/// Maximise the number of ports in the bridge.
+pub struct Length;
+
+impl Objective for Length {
+    type Score = usize;
+
+    fn zero(&self) -> usize {
+        0
+    }
+    fn score(&self, _port: &Port) -> usize {
+        1
+    }
+}
+
+/// Maximise the number of ports in the bridge, using the strength to break ties.
+pub struct LengthThenStrength;
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct LengthAndStrength {
+    pub length: usize,
+    pub strength: usize,
+}
+
+impl Add for LengthAndStrength {
+    type Output = LengthAndStrength;
+
+    fn add(self, rhs: LengthAndStrength) -> Self::Output {
+        LengthAndStrength {
+            length: self.length + rhs.length,
+            strength: self.strength + rhs.strength,
+        }
+    }
+}
+
+impl Objective for LengthThenStrength {
+    type Score = LengthAndStrength;
+
+    fn zero(&self) -> LengthAndStrength {
+        LengthAndStrength {
+            length: 0,
+            strength: 0,
+        }
+    }
+    fn score(&self, port: &Port) -> LengthAndStrength {
+        LengthAndStrength {
+            length: 1,
+            strength: port.value(),
+        }
+    }
+}
+
+/// Set of used components, one bit per index in the component list.
+pub type Mask = u128;
+
+pub const MAX_COMPONENTS: usize = 128;
+
+/// The components indexed by pin, with pins renumbered densely so that per-pin
+/// data can be stored in vectors.
+pub struct ComponentGraph<'a> {
+    ports: &'a [Port],
+    pins: HashMap<usize, usize>,
+    ends: Vec<(usize, usize)>,
+    by_pin: Vec<Vec<usize>>,
+    pin_masks: Vec<Mask>,
+    previous_copy: Vec<Option<usize>>,
+}
+
+impl<'a> ComponentGraph<'a> {
+    pub fn new(ports: &'a [Port]) -> Result<ComponentGraph<'a>, Error> {
+        ensure!(
+            ports.len() <= MAX_COMPONENTS,
+            "At most {} components are supported, got {}",
+            MAX_COMPONENTS,
+            ports.len()
+        );
+        let mut pins = HashMap::new();
+        pins.insert(0, 0);
+        let mut ends = Vec::new();
+        for port in ports {
+            let next_pin = pins.len();
+            let side1 = *pins.entry(port.side1).or_insert(next_pin);
+            let next_pin = pins.len();
+            let side2 = *pins.entry(port.side2).or_insert(next_pin);
+            ends.push((side1, side2));
+        }
+
+        let mut by_pin = vec![Vec::new(); pins.len()];
+        let mut pin_masks = vec![0; pins.len()];
+        for (index, &(side1, side2)) in ends.iter().enumerate() {
+            by_pin[side1].push(index);
+            pin_masks[side1] |= 1 << index;
+            if side2 != side1 {
+                by_pin[side2].push(index);
+                pin_masks[side2] |= 1 << index;
+            }
+        }
+        // Identical components are interchangeable, so only the first unused
+        // copy of a component is ever tried.
+        let previous_copy = ports
+            .iter()
+            .enumerate()
+            .map(|(index, port)| ports[..index].iter().rposition(|p| p == port))
+            .collect();
+        Ok(ComponentGraph {
+            ports,
+            pins,
+            ends,
+            by_pin,
+            pin_masks,
+            previous_copy,
+        })
+    }
+
+    pub fn start_pin(&self) -> usize {
+        self.pins[&0]
+    }
+
+    fn other(&self, index: usize, pin: usize) -> usize {
+        let (side1, side2) = self.ends[index];
+        if side1 == pin { side2 } else { side1 }
+    }
+
+    /// The components that can extend a bridge ending in pin, skipping identical copies.
+    fn extensions(&self, pin: usize, used: Mask) -> Vec<usize> {
+        self.by_pin[pin]
+            .iter()
+            .cloned()
+            .filter(|&index| {
+                used & (1 << index) == 0 &&
+                    self.previous_copy[index].map_or(true, |copy| used & (1 << copy) != 0)
+            })
+            .collect()
+    }
+
+    /// The used set extended with every component that can no longer be reached from pin.
+    fn normalize(&self, pin: usize, used: Mask) -> Mask {
+        let mut reachable: Mask = 0;
+        let mut seen_pins = vec![false; self.by_pin.len()];
+        let mut pins = vec![pin];
+        seen_pins[pin] = true;
+        while let Some(pin) = pins.pop() {
+            let mut new_ports = self.pin_masks[pin] & !used & !reachable;
+            reachable |= new_ports;
+            while new_ports != 0 {
+                let index = new_ports.trailing_zeros() as usize;
+                new_ports &= new_ports - 1;
+                let other = self.other(index, pin);
+                if !seen_pins[other] {
+                    seen_pins[other] = true;
+                    pins.push(other);
+                }
+            }
+        }
+        !reachable
+    }
+
+    pub fn bridges<'b>(&'b self) -> Bridges<'b> {
+        let start = self.start_pin();
+        Bridges {
+            graph: self,
+            stack: vec![
+                BridgeFrame {
+                    pin: start,
+                    used: 0,
+                    extensions: self.extensions(start, 0),
+                    next: 0,
+                },
+            ],
+            ports: Vec::new(),
+        }
+    }
+}
+
+struct BridgeFrame {
+    pin: usize,
+    used: Mask,
+    extensions: Vec<usize>,
+    next: usize,
+}
+
+/// Lazy depth-first enumeration of every non-empty bridge, together with
+/// whether the bridge is maximal (can not be extended any further).
+pub struct Bridges<'a> {
+    graph: &'a ComponentGraph<'a>,
+    stack: Vec<BridgeFrame>,
+    ports: Vec<Port>,
+}
+
+impl<'a> Iterator for Bridges<'a> {
+    type Item = (Bridge, bool);
+
+    fn next(&mut self) -> Option<(Bridge, bool)> {
+        loop {
+            let (index, pin, used) = {
+                let frame = self.stack.last_mut()?;
+                if frame.next < frame.extensions.len() {
+                    frame.next += 1;
+                    (frame.extensions[frame.next - 1], frame.pin, frame.used)
+                } else {
+                    self.stack.pop();
+                    self.ports.pop();
+                    continue;
+                }
+            };
+            let pin = self.graph.other(index, pin);
+            let used = used | (1 << index);
+            let extensions = self.graph.extensions(pin, used);
+            let maximal = extensions.is_empty();
+            self.ports.push(self.graph.ports[index]);
+            self.stack.push(BridgeFrame {
+                pin,
+                used,
+                extensions,
+                next: 0,
+            });
+            return Some((Bridge::new(self.ports.clone()), maximal));
+        }
+    }
+}
+
+/// The bridges that no other bridge beats in both length and strength, by increasing length.
+pub fn pareto_frontier<I: Iterator<Item = Bridge>>(bridges: I) -> Vec<Bridge> {
+    let mut best_by_length: HashMap<usize, Bridge> = HashMap::new();
+    for bridge in bridges {
+        let better = best_by_length
+            .get(&bridge.length())
+            .map_or(true, |best| bridge.value > best.value);
+        if better {
+            best_by_length.insert(bridge.length(), bridge);
+        }
+    }
+
+    let mut candidates: Vec<Bridge> = best_by_length.into_iter().map(|(_, b)| b).collect();
+    candidates.sort_by_key(|b| b.length());
+    let mut frontier: Vec<Bridge> = Vec::new();
+    for bridge in candidates.into_iter().rev() {
+        if frontier.last().map_or(true, |longer| bridge.value > longer.value) {
+            frontier.push(bridge);
+        }
+    }
+    frontier.reverse();
+    frontier
+}
+
+/// Finds the best bridge for an objective by searching over the sets of used
+/// components, memoising the best continuation from each (next pin, used
+/// components) pair.
+pub struct BridgeSolver<'a, O: Objective> {
+    graph: &'a ComponentGraph<'a>,
+    objective: O,
+    memo: HashMap<(usize, Mask), (O::Score, Option<usize>)>,
+}
+
+impl<'a, O: Objective> BridgeSolver<'a, O> {
+    pub fn new(graph: &'a ComponentGraph<'a>, objective: O) -> BridgeSolver<'a, O> {
+        BridgeSolver {
+            graph,
+            objective,
+            memo: HashMap::new(),
+        }
+    }
+
+    fn extensions(&self, pin: usize, used: Mask) -> Vec<usize> {
+        let graph = self.graph;
+        let mut extensions = Vec::new();
+        let mut best_dead_end: Option<usize> = None;
+        for index in graph.extensions(pin, used) {
+            let other = graph.other(index, pin);
+            if other == pin {
+                // A component with the same pin on both sides can always be added
+                // right away, since it does not change which components fit next.
+                debug_assert!(
+                    self.objective.score(&graph.ports[index]) >= self.objective.zero(),
+                    "Negative score for {}",
+                    graph.ports[index]
+                );
+                return vec![index];
+            }
+            if graph.pin_masks[other] & !used & !(1 << index) == 0 {
+                // Components leading to a pin without any other unused component end
+                // the bridge, so only the best of them needs to be tried.
+                let score = self.objective.score(&graph.ports[index]);
+                debug_assert!(
+                    score >= self.objective.zero(),
+                    "Negative score for {}",
+                    graph.ports[index]
+                );
+                if best_dead_end.map_or(true, |best| {
+                    score > self.objective.score(&graph.ports[best])
+                })
+                {
+                    best_dead_end = Some(index);
+                }
+            } else {
+                extensions.push(index);
+            }
+        }
+        extensions.extend(best_dead_end);
+        extensions
+    }
+
+    /// The best score for continuing a bridge ending in pin, and the component to continue with.
+    ///
+    /// Components that can no longer be reached are marked as used first, so that
+    /// more states share the same memoised result.
+    fn best(&mut self, pin: usize, used: Mask) -> (O::Score, Option<usize>) {
+        let used = self.graph.normalize(pin, used);
+        if let Some(&result) = self.memo.get(&(pin, used)) {
+            return result;
+        }
+        let mut result = (self.objective.zero(), None);
+        for index in self.extensions(pin, used) {
+            let (rest, _) = self.best(self.graph.other(index, pin), used | (1 << index));
+            let score = self.objective.score(&self.graph.ports[index]) + rest;
+            if result.1.is_none() || score > result.0 {
+                result = (score, Some(index));
+            }
+        }
+        self.memo.insert((pin, used), result);
+        result
+    }
+
+    pub fn solve(&mut self) -> Bridge {
+        let mut ports = Vec::new();
+        let mut pin = self.graph.start_pin();
+        let mut used: Mask = 0;
+        while let (_, Some(index)) = self.best(pin, used) {
+            ports.push(self.graph.ports[index]);
+            pin = self.graph.other(index, pin);
+            used |= 1 << index;
+        }
+        Bridge::new(ports)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ObjectiveKind {
+    Strength,
+    Length,
+    LengthThenStrength,
+}
+
+impl FromStr for ObjectiveKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "strength" => Ok(ObjectiveKind::Strength),
+            "length" => Ok(ObjectiveKind::Length),
+            "length-strength" => Ok(ObjectiveKind::LengthThenStrength),
+            _ => bail!(
+                "Unknown objective \"{}\", expected strength, length or length-strength",
+                s
+            ),
+        }
+    }
+}
+
+pub fn find_bridge(graph: &ComponentGraph, objective: ObjectiveKind) -> Bridge {
+    match objective {
+        ObjectiveKind::Strength => BridgeSolver::new(graph, Strength).solve(),
+        ObjectiveKind::Length => BridgeSolver::new(graph, Length).solve(),
+        ObjectiveKind::LengthThenStrength => BridgeSolver::new(graph, LengthThenStrength).solve(),
+    }
+}
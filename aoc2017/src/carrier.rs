@@ -0,0 +1,459 @@
+use failure::Error;
+
+use std::char;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+pub type Scalar = i32;
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Position {
+    pub x: Scalar,
+    pub y: Scalar,
+}
+
+impl Position {
+    pub fn new(x: Scalar, y: Scalar) -> Position {
+        Position { x, y }
+    }
+
+    /// The neighbouring position in direction, or None if it can not be represented.
+    pub fn step(&self, direction: Direction) -> Option<Position> {
+        let (x, y) = match direction {
+            Direction::North => (Some(self.x), self.y.checked_sub(1)),
+            Direction::South => (Some(self.x), self.y.checked_add(1)),
+            Direction::West => (self.x.checked_sub(1), Some(self.y)),
+            Direction::East => (self.x.checked_add(1), Some(self.y)),
+        };
+        Some(Position { x: x?, y: y? })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Direction {
+    North,
+    South,
+    East,
+    West,
+}
+
+impl Direction {
+    /// An arrow pointing in the direction.
+    pub fn arrow(&self) -> char {
+        match *self {
+            Direction::North => '^',
+            Direction::South => 'v',
+            Direction::East => '>',
+            Direction::West => '<',
+        }
+    }
+
+    pub fn back(&self) -> Direction {
+        match *self {
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+        }
+    }
+
+    pub fn turn(&self, turn: Turn) -> Direction {
+        use self::Direction::*;
+        match (*self, turn) {
+            (North, Turn::Left) => West,
+            (North, Turn::Right) => East,
+            (South, Turn::Left) => East,
+            (South, Turn::Right) => West,
+            (East, Turn::Left) => North,
+            (East, Turn::Right) => South,
+            (West, Turn::Left) => South,
+            (West, Turn::Right) => North,
+            (_, Turn::Same) => *self,
+            (_, Turn::Back) => self.back(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Turn {
+    Left,
+    Right,
+    Same,
+    Back,
+}
+
+impl FromStr for Turn {
+    type Err = Error;
+
+    /// Accepts both the full names and the single letters L, R, N and U used for
+    /// Langton's ant variants.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_ref() {
+            "left" | "l" => Ok(Turn::Left),
+            "right" | "r" => Ok(Turn::Right),
+            "same" | "n" => Ok(Turn::Same),
+            "back" | "u" => Ok(Turn::Back),
+            _ => bail!("Unknown turn \"{}\"", s),
+        }
+    }
+}
+
+impl fmt::Display for Turn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Turn::Left => "left",
+            Turn::Right => "right",
+            Turn::Same => "same",
+            Turn::Back => "back",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What the carrier does on a node in a given state.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Rule {
+    pub name: String,
+    pub symbol: char,
+    pub turn: Turn,
+    pub next: usize,
+}
+
+impl Rule {
+    /// The ANSI foreground colour code used when rendering nodes in this state.
+    ///
+    /// The states of the puzzle get fixed colours, other states cycle through a
+    /// palette.
+    pub fn colour(&self, state: usize) -> u8 {
+        const PALETTE: [u8; 6] = [32, 35, 36, 33, 31, 34];
+        match self.name.as_ref() {
+            "clean" => 90,
+            "weakened" => 33,
+            "infected" => 31,
+            "flagged" => 34,
+            _ if state == 0 => 90,
+            _ => PALETTE[(state - 1) % PALETTE.len()],
+        }
+    }
+}
+
+/// The rule table of a carrier, indexed by node state.
+///
+/// In text form, each line is `name symbol turn next`, where symbol is the
+/// character used for the state in maps, and next is the name of the state the
+/// node gets after the carrier has visited it. The first state is the state of
+/// all nodes not given in the map. Empty lines and lines starting with `//` are
+/// ignored.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Rules for a Langton's ant variant, with one state per turn.
+    ///
+    /// The states are named and shown by their index, and each state moves on to
+    /// the next one, wrapping around at the end.
+    pub fn langtons_ant(turns: &str) -> Result<Rules, Error> {
+        let turns = turns
+            .chars()
+            .map(|turn| turn.to_string().parse())
+            .collect::<Result<Vec<Turn>, Error>>()?;
+        ensure!(
+            !turns.is_empty() && turns.len() <= 36,
+            "Langton's ant needs between 1 and 36 turns, got {}",
+            turns.len()
+        );
+        let rules = turns
+            .iter()
+            .enumerate()
+            .map(|(index, &turn)| Rule {
+                name: index.to_string(),
+                symbol: char::from_digit(index as u32, 36).unwrap(),
+                turn,
+                next: (index + 1) % turns.len(),
+            })
+            .collect();
+        Ok(Rules { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn rule(&self, state: usize) -> &Rule {
+        &self.rules[state]
+    }
+
+    pub fn state(&self, name: &str) -> Option<usize> {
+        self.rules.iter().position(|rule| rule.name == name)
+    }
+
+    pub fn state_for_symbol(&self, symbol: char) -> Option<usize> {
+        self.rules.iter().position(|rule| rule.symbol == symbol)
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<Rule> {
+        self.rules.iter()
+    }
+}
+
+impl FromStr for Rules {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = Vec::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            ensure!(
+                words.len() == 4,
+                "Line {}: expected \"name symbol turn next\", found \"{}\"",
+                number + 1,
+                line
+            );
+            let mut symbol = words[1].chars();
+            let symbol = match (symbol.next(), symbol.next()) {
+                (Some(symbol), None) => symbol,
+                _ => bail!("Line {}: symbol \"{}\" is not a single character", number + 1, words[1]),
+            };
+            let turn = match words[2].parse::<Turn>() {
+                Ok(turn) => turn,
+                Err(error) => bail!("Line {}: {}", number + 1, error),
+            };
+            lines.push((number + 1, words[0], symbol, turn, words[3]));
+        }
+        ensure!(!lines.is_empty(), "No rules given");
+
+        let mut rules: Vec<Rule> = Vec::new();
+        for &(number, name, symbol, turn, next) in &lines {
+            ensure!(
+                rules.iter().all(|rule| rule.name != name),
+                "Line {}: state {} is defined twice",
+                number,
+                name
+            );
+            ensure!(
+                rules.iter().all(|rule| rule.symbol != symbol),
+                "Line {}: symbol {} is used twice",
+                number,
+                symbol
+            );
+            let next = match lines.iter().position(|&(_, name, _, _, _)| name == next) {
+                Some(next) => next,
+                None => bail!("Line {}: state {} is not defined", number, next),
+            };
+            rules.push(Rule {
+                name: name.to_string(),
+                symbol,
+                turn,
+                next,
+            });
+        }
+
+        Ok(Rules { rules })
+    }
+}
+
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for rule in &self.rules {
+            writeln!(
+                f,
+                "{} {} {} {}",
+                rule.name,
+                rule.symbol,
+                rule.turn,
+                self.rules[rule.next].name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// An unbounded grid of node states, where unvisited nodes are in state 0.
+///
+/// Only visited nodes are stored, so memory use grows with the number of distinct
+/// nodes the carrier has been on.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Grid {
+    states: HashMap<Position, usize>,
+    pub min_x: Scalar,
+    pub min_y: Scalar,
+    pub max_x: Scalar,
+    pub max_y: Scalar,
+}
+
+impl Grid {
+    pub fn new() -> Grid {
+        Grid::default()
+    }
+
+    /// Parse a map of node symbols, returning the center of the map and the grid.
+    ///
+    /// A `.` stands for the first state unless some state uses it as its symbol.
+    pub fn parse(map: &str, rules: &Rules) -> Result<(Position, Grid), Error> {
+        let mut max_col = 0;
+        let mut max_row = 0;
+        let mut grid = Grid::new();
+        for (row, line) in map.lines().enumerate() {
+            for (col, ch) in line.chars().enumerate() {
+                let position = Position::new(col as Scalar, row as Scalar);
+                let state = match rules.state_for_symbol(ch) {
+                    Some(state) => state,
+                    None if ch == '.' => 0,
+                    None => bail!("Unknown node symbol {} at line {}", ch, row + 1),
+                };
+                grid.set(position, state);
+                max_col = max(max_col, col);
+            }
+            max_row = max(max_row, row);
+        }
+
+        let center = Position::new(max_col as Scalar / 2, max_row as Scalar / 2);
+
+        Ok((center, grid))
+    }
+
+    /// The number of nodes stored.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// The number of nodes that can be stored without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.states.capacity()
+    }
+
+    fn update_bounds(&mut self, position: Position) {
+        self.min_x = min(self.min_x, position.x);
+        self.min_y = min(self.min_y, position.y);
+        self.max_x = max(self.max_x, position.x);
+        self.max_y = max(self.max_y, position.y);
+    }
+
+    pub fn set(&mut self, position: Position, state: usize) {
+        self.update_bounds(position);
+        self.states.insert(position, state);
+    }
+
+    pub fn get(&self, position: Position) -> usize {
+        *self.states.get(&position).unwrap_or(&0)
+    }
+
+    /// The number of nodes in each state, among the nodes that have been set.
+    pub fn census(&self, states: usize) -> Vec<usize> {
+        let mut result = vec![0; states];
+        for &state in self.states.values() {
+            result[state] += 1;
+        }
+        result
+    }
+}
+
+/// A virus carrier walking a grid, changing the state of each node it visits
+/// according to a rule table.
+#[derive(Debug, Clone)]
+pub struct Carrier {
+    pub rules: Rules,
+    pub grid: Grid,
+    pub position: Position,
+    pub direction: Direction,
+    /// The number of times a node has been changed into each state.
+    pub counts: Vec<u64>,
+    pub steps: u64,
+}
+
+impl Carrier {
+    pub fn new(rules: Rules, grid: Grid, position: Position) -> Carrier {
+        let counts = vec![0; rules.len()];
+        Carrier {
+            rules,
+            grid,
+            position,
+            direction: Direction::North,
+            counts,
+            steps: 0,
+        }
+    }
+
+    /// Perform one burst of activity.
+    ///
+    /// Fails if the carrier would move outside the range of `Scalar`.
+    pub fn step(&mut self) -> Result<(), Error> {
+        let (turn, next) = {
+            let rule = self.rules.rule(self.grid.get(self.position));
+            (rule.turn, rule.next)
+        };
+        self.direction = self.direction.turn(turn);
+        self.grid.set(self.position, next);
+        self.counts[next] += 1;
+        self.position = match self.position.step(self.direction) {
+            Some(position) => position,
+            None => bail!(
+                "Carrier at ({}, {}) moved {:?} off the edge of the grid after {} steps",
+                self.position.x,
+                self.position.y,
+                self.direction,
+                self.steps
+            ),
+        };
+        self.steps += 1;
+        Ok(())
+    }
+
+    pub fn run(&mut self, steps: u64) -> Result<(), Error> {
+        for _ in 0..steps {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// The grid within radius nodes of the carrier as ANSI coloured text.
+    ///
+    /// Each frame starts by clearing the screen, followed by a status line, so
+    /// that a sequence of frames plays back as an animation. The carrier is shown
+    /// as an arrow in the direction it faces, in reverse video.
+    pub fn render(&self, radius: Scalar) -> String {
+        let mut result = format!(
+            "\x1b[2J\x1b[H{} bursts, at ({}, {}) facing {:?}",
+            self.steps, self.position.x, self.position.y, self.direction
+        );
+        for (rule, count) in self.rules.iter().zip(self.counts.iter()) {
+            result.push_str(&format!(", {} {}", rule.name, count));
+        }
+        result.push('\n');
+        let (x, y) = (self.position.x, self.position.y);
+        for y in y.saturating_sub(radius)..y.saturating_add(radius).saturating_add(1) {
+            for x in x.saturating_sub(radius)..x.saturating_add(radius).saturating_add(1) {
+                let position = Position::new(x, y);
+                let state = self.grid.get(position);
+                let rule = self.rules.rule(state);
+                if position == self.position {
+                    result.push_str(&format!(
+                        "\x1b[1;7;{}m{}\x1b[0m ",
+                        rule.colour(state),
+                        self.direction.arrow()
+                    ));
+                } else {
+                    result.push_str(&format!("\x1b[{}m{}\x1b[0m ", rule.colour(state), rule.symbol));
+                }
+            }
+            result.push('\n');
+        }
+        result
+    }
+}
@@ -0,0 +1,253 @@
+use std::u32;
+
+const NIL: u32 = u32::MAX;
+
+/// Handle to a value stored in an `ImplicitTreap`.
+///
+/// Handles are handed out sequentially from 0 in insertion order, and stay valid
+/// for the lifetime of the treap.
+pub type NodeId = usize;
+
+#[derive(Debug, Clone)]
+struct Node {
+    left: u32,
+    right: u32,
+    parent: u32,
+    size: u32,
+}
+
+/// A sequence supporting insertion at arbitrary positions and position look-ups
+/// in expected O(log n) time.
+///
+/// The tree is keyed implicitly on the position in the sequence, using subtree
+/// sizes. Node priorities are derived from a hash of the node id, so the shape of
+/// the tree is deterministic.
+#[derive(Debug, Clone)]
+pub struct ImplicitTreap<T> {
+    nodes: Vec<Node>,
+    values: Vec<T>,
+    root: u32,
+}
+
+fn priority(node: u32) -> u64 {
+    // SplitMix64 finalizer
+    let mut z = u64::from(node).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl<T> ImplicitTreap<T> {
+    pub fn new() -> ImplicitTreap<T> {
+        ImplicitTreap::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> ImplicitTreap<T> {
+        ImplicitTreap {
+            nodes: Vec::with_capacity(capacity),
+            values: Vec::with_capacity(capacity),
+            root: NIL,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Insert value so that it ends up at position, shifting later values one step.
+    ///
+    /// Panics if position is larger than the length of the sequence.
+    pub fn insert(&mut self, position: usize, value: T) -> NodeId {
+        assert!(position <= self.len());
+        assert!(self.len() < NIL as usize, "Too many values in treap");
+        let node = self.nodes.len() as u32;
+        self.nodes.push(Node {
+            left: NIL,
+            right: NIL,
+            parent: NIL,
+            size: 1,
+        });
+        self.values.push(value);
+
+        let root = self.root;
+        self.root = self.insert_node(root, position as u32, node);
+        self.nodes[self.root as usize].parent = NIL;
+        node as NodeId
+    }
+
+    /// The value at position, if any.
+    pub fn get(&self, position: usize) -> Option<&T> {
+        self.node_at(position).map(|node| &self.values[node])
+    }
+
+    /// The node at position, if any.
+    pub fn node_at(&self, position: usize) -> Option<NodeId> {
+        if position >= self.len() {
+            return None;
+        }
+        let mut node = self.root;
+        let mut position = position as u32;
+        loop {
+            let left_size = self.size(self.nodes[node as usize].left);
+            if position < left_size {
+                node = self.nodes[node as usize].left;
+            } else if position == left_size {
+                return Some(node as NodeId);
+            } else {
+                position -= left_size + 1;
+                node = self.nodes[node as usize].right;
+            }
+        }
+    }
+
+    /// The value stored for node.
+    pub fn value(&self, node: NodeId) -> &T {
+        &self.values[node]
+    }
+
+    /// The current position of node in the sequence.
+    pub fn position(&self, node: NodeId) -> usize {
+        let mut node = node as u32;
+        let mut position = self.size(self.nodes[node as usize].left) as usize;
+        loop {
+            let parent = self.nodes[node as usize].parent;
+            if parent == NIL {
+                return position;
+            }
+            if self.nodes[parent as usize].right == node {
+                position += self.size(self.nodes[parent as usize].left) as usize + 1;
+            }
+            node = parent;
+        }
+    }
+
+    pub fn iter(&self) -> ImplicitTreapIterator<T> {
+        let mut iterator = ImplicitTreapIterator {
+            treap: self,
+            stack: Vec::new(),
+        };
+        iterator.push_left(self.root);
+        iterator
+    }
+
+    fn size(&self, node: u32) -> u32 {
+        if node == NIL {
+            0
+        } else {
+            self.nodes[node as usize].size
+        }
+    }
+
+    fn set_children(&mut self, node: u32, left: u32, right: u32) {
+        let size = self.size(left) + self.size(right) + 1;
+        {
+            let data = &mut self.nodes[node as usize];
+            data.left = left;
+            data.right = right;
+            data.size = size;
+        }
+        if left != NIL {
+            self.nodes[left as usize].parent = node;
+        }
+        if right != NIL {
+            self.nodes[right as usize].parent = node;
+        }
+    }
+
+    /// Insert node at position in the subtree rooted at tree, returning the new root.
+    fn insert_node(&mut self, tree: u32, position: u32, node: u32) -> u32 {
+        if tree == NIL {
+            return node;
+        }
+        if priority(node) > priority(tree) {
+            let (left, right) = self.split(tree, position);
+            self.set_children(node, left, right);
+            return node;
+        }
+        let (left, right) = {
+            let data = &self.nodes[tree as usize];
+            (data.left, data.right)
+        };
+        let left_size = self.size(left);
+        if position <= left_size {
+            let left = self.insert_node(left, position, node);
+            self.set_children(tree, left, right);
+        } else {
+            let right = self.insert_node(right, position - left_size - 1, node);
+            self.set_children(tree, left, right);
+        }
+        tree
+    }
+
+    /// Split tree into the first count values and the rest.
+    fn split(&mut self, tree: u32, count: u32) -> (u32, u32) {
+        if tree == NIL {
+            return (NIL, NIL);
+        }
+        let (left, right) = {
+            let data = &self.nodes[tree as usize];
+            (data.left, data.right)
+        };
+        let left_size = self.size(left);
+        if count <= left_size {
+            let (first, second) = self.split(left, count);
+            self.set_children(tree, second, right);
+            if first != NIL {
+                self.nodes[first as usize].parent = NIL;
+            }
+            (first, tree)
+        } else {
+            let (first, second) = self.split(right, count - left_size - 1);
+            self.set_children(tree, left, first);
+            if second != NIL {
+                self.nodes[second as usize].parent = NIL;
+            }
+            (tree, second)
+        }
+    }
+}
+
+impl<T> Default for ImplicitTreap<T> {
+    fn default() -> Self {
+        ImplicitTreap::new()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a ImplicitTreap<T> {
+    type Item = &'a T;
+    type IntoIter = ImplicitTreapIterator<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// In-order iterator over the values of an `ImplicitTreap`.
+#[derive(Clone)]
+pub struct ImplicitTreapIterator<'a, T: 'a> {
+    treap: &'a ImplicitTreap<T>,
+    stack: Vec<u32>,
+}
+
+impl<'a, T> ImplicitTreapIterator<'a, T> {
+    fn push_left(&mut self, mut node: u32) {
+        while node != NIL {
+            self.stack.push(node);
+            node = self.treap.nodes[node as usize].left;
+        }
+    }
+}
+
+impl<'a, T> Iterator for ImplicitTreapIterator<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.stack.pop()?;
+        let right = self.treap.nodes[node as usize].right;
+        self.push_left(right);
+        Some(&self.treap.values[node as usize])
+    }
+}
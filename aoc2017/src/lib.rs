@@ -1,6 +1,20 @@
+#[macro_use]
 extern crate failure;
 
 pub mod union_find;
 pub use union_find::UnionFind;
 
+pub mod implicit_treap;
+pub use implicit_treap::ImplicitTreap;
+
+pub mod spinlock;
+pub use spinlock::Spinlock;
+
+pub mod carrier;
+pub use carrier::Carrier;
+
 pub mod matrix;
+
+pub mod bridges;
+
+pub mod vm;
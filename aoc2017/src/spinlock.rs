@@ -0,0 +1,52 @@
+use implicit_treap::ImplicitTreap;
+
+/// A spinlock buffer where the value `i` is inserted `steps` positions after
+/// the value `i - 1`.
+///
+/// Since values are inserted in increasing order starting from 0, the node
+/// id handed out by the treap for a value is the value itself.
+pub struct Spinlock {
+    steps: usize,
+    buffer: ImplicitTreap<()>,
+    position: usize,
+}
+
+impl Spinlock {
+    /// A spinlock holding only the value 0, with room for values without
+    /// reallocating.
+    pub fn new(steps: usize, values: usize) -> Spinlock {
+        let mut buffer = ImplicitTreap::with_capacity(values);
+        buffer.insert(0, ());
+        Spinlock {
+            steps,
+            buffer,
+            position: 0,
+        }
+    }
+
+    pub fn insert_next(&mut self) {
+        let value_count = self.buffer.len();
+        self.position = (self.position + self.steps) % value_count + 1;
+        self.buffer.insert(self.position, ());
+    }
+
+    pub fn run_until(&mut self, last_value: usize) {
+        while self.buffer.len() <= last_value {
+            self.insert_next();
+        }
+    }
+
+    /// The value at position, wrapping around the buffer.
+    pub fn value_at(&self, position: usize) -> usize {
+        self.buffer.node_at(position % self.buffer.len()).unwrap()
+    }
+
+    /// The value following value, if value has been inserted.
+    pub fn value_after(&self, value: usize) -> Option<usize> {
+        if value < self.buffer.len() {
+            Some(self.value_at(self.buffer.position(value) + 1))
+        } else {
+            None
+        }
+    }
+}
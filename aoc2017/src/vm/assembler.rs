@@ -0,0 +1,223 @@
+//! A two-pass assembler and a disassembler for the duet assembly.
+//!
+//! On top of the plain instructions, the assembly language has
+//!
+//!  * comments, from `;` or `#` to the end of the line,
+//!  * labels, written `name:` before an instruction or on a line of their own,
+//!    which jumps can use as offset by quoting them, as in `jnz a 'name'`, and
+//!  * constants, defined as `.const name value`, which can be used wherever a
+//!    value is expected. Constant names must be longer than one character, to
+//!    tell them apart from registers.
+
+use failure::Error;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use vm::{Instruction, Opcode, Program, Value};
+
+#[derive(Fail, Debug)]
+#[fail(display = "Line {}: {}", line, message)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub message: String,
+}
+
+fn error(line: usize, message: String) -> Error {
+    Error::from(AssemblyError { line, message })
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|ch| ch.is_alphanumeric() || ch == '_')
+        && !name.chars().next().unwrap().is_digit(10)
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(|ch| ch == ';' || ch == '#') {
+        Some(start) => &line[..start],
+        None => line,
+    }
+}
+
+/// An instruction line, after labels and comments have been removed.
+struct Line<'a> {
+    number: usize,
+    words: Vec<&'a str>,
+}
+
+/// Resolve one operand to a number or register name.
+fn resolve(
+    operand: &str,
+    jump: bool,
+    pc: usize,
+    labels: &HashMap<&str, usize>,
+    constants: &HashMap<&str, Value>,
+) -> Result<String, String> {
+    if operand.starts_with('\'') {
+        let label = operand.trim_matches('\'');
+        if !jump {
+            return Err(format!("label {} can only be used as a jump offset", label));
+        }
+        return match labels.get(label) {
+            Some(&target) => Ok((target as Value - pc as Value).to_string()),
+            None => Err(format!("unknown label {}", label)),
+        };
+    }
+    if operand.parse::<Value>().is_ok() || operand.chars().count() == 1 {
+        return Ok(operand.to_string());
+    }
+    match constants.get(operand) {
+        Some(value) => Ok(value.to_string()),
+        None => Err(format!("unknown constant {}", operand)),
+    }
+}
+
+/// Assemble a program from text.
+pub fn assemble(text: &str) -> Result<Program, Error> {
+    assemble_with_labels(text).map(|(program, _)| program)
+}
+
+/// Assemble a program from text, also giving the pc of every label.
+pub fn assemble_with_labels(text: &str) -> Result<(Program, BTreeMap<String, usize>), Error> {
+    // First pass: find labels and constants, and collect instruction lines.
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut constants: HashMap<&str, Value> = HashMap::new();
+    let mut lines: Vec<Line> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let mut line = strip_comment(line).trim();
+        if line.starts_with(".const") {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() != 3 || words[0] != ".const" {
+                return Err(error(number, format!("expected \".const name value\", found \"{}\"", line)));
+            }
+            let (name, value) = (words[1], words[2]);
+            if !is_identifier(name) || name.chars().count() < 2 {
+                return Err(error(
+                    number,
+                    format!("constant name {} must be an identifier of at least two characters", name),
+                ));
+            }
+            let value = match value.parse::<Value>() {
+                Ok(value) => value,
+                Err(_) => match constants.get(value) {
+                    Some(&value) => value,
+                    None => return Err(error(number, format!("invalid constant value {}", value))),
+                },
+            };
+            if constants.insert(name, value).is_some() {
+                return Err(error(number, format!("constant {} is defined twice", name)));
+            }
+            continue;
+        }
+        while let Some(colon) = line.find(':') {
+            let label = line[..colon].trim();
+            if !is_identifier(label) {
+                return Err(error(number, format!("invalid label \"{}\"", label)));
+            }
+            if labels.insert(label, lines.len()).is_some() {
+                return Err(error(number, format!("label {} is defined twice", label)));
+            }
+            line = line[colon + 1..].trim();
+        }
+        if !line.is_empty() {
+            lines.push(Line {
+                number,
+                words: line.split_whitespace().collect(),
+            });
+        }
+    }
+
+    // Second pass: resolve operands and parse the instructions.
+    let mut program = Vec::with_capacity(lines.len());
+    for (pc, line) in lines.iter().enumerate() {
+        let opcode: Opcode = line.words[0]
+            .parse()
+            .map_err(|cause| error(line.number, format!("{}", cause)))?;
+        let operands = &line.words[1..];
+        if operands.len() != opcode.operands() {
+            return Err(error(
+                line.number,
+                format!(
+                    "{} takes {} operands, found {}",
+                    opcode,
+                    opcode.operands(),
+                    operands.len()
+                ),
+            ));
+        }
+        let mut resolved = vec![opcode.name().to_string()];
+        for (index, operand) in operands.iter().enumerate() {
+            let jump = opcode.is_jump() && index + 1 == operands.len();
+            resolved.push(resolve(operand, jump, pc, &labels, &constants)
+                .map_err(|message| error(line.number, message))?);
+        }
+        let instruction: Instruction = resolved
+            .join(" ")
+            .parse()
+            .map_err(|cause| error(line.number, format!("{}", cause)))?;
+        program.push(instruction);
+    }
+    let labels = labels
+        .into_iter()
+        .map(|(label, pc)| (label.to_string(), pc))
+        .collect();
+    Ok((program, labels))
+}
+
+/// The target of a jump with a constant offset inside or just after the program.
+fn target(program: &[Instruction], pc: usize, instruction: &Instruction) -> Option<usize> {
+    match instruction.offset() {
+        Some(::vm::Source::Value { value }) => {
+            let target = pc as Value + value;
+            if 0 <= target && target <= program.len() as Value {
+                Some(target as usize)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The labels that `disassemble` generates, `L1`, `L2` and so on in program
+/// order, by pc.
+pub fn jump_labels(program: &[Instruction]) -> HashMap<usize, String> {
+    let targets: BTreeSet<usize> = program
+        .iter()
+        .enumerate()
+        .filter_map(|(pc, instruction)| target(program, pc, instruction))
+        .collect();
+    targets
+        .iter()
+        .enumerate()
+        .map(|(index, &target)| (target, format!("L{}", index + 1)))
+        .collect()
+}
+
+/// The program as assembly text, with a generated label for every jump target
+/// that is given as a constant offset inside or just after the program.
+///
+/// Assembling the result gives back the same program.
+pub fn disassemble(program: &[Instruction]) -> String {
+    let names = jump_labels(program);
+
+    let mut result = String::new();
+    for pc in 0..(program.len() + 1) {
+        if let Some(name) = names.get(&pc) {
+            result.push_str(&format!("{}:\n", name));
+        }
+        if pc == program.len() {
+            break;
+        }
+        let instruction = &program[pc];
+        let text = instruction.to_string();
+        match target(program, pc, instruction) {
+            Some(target) => {
+                let operands = text.rsplitn(2, ' ').nth(1).unwrap();
+                result.push_str(&format!("    {} '{}'\n", operands, names[&target]));
+            }
+            None => result.push_str(&format!("    {}\n", text)),
+        }
+    }
+    result
+}
@@ -0,0 +1,429 @@
+//! Control-flow graphs of VM programs.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use vm::{Instruction, Source, Value};
+
+/// How control passes from one block to the next.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum EdgeKind {
+    /// Falling through to the next instruction, also when a jump is not taken.
+    Fallthrough,
+    /// Taking a jump.
+    Taken,
+}
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+/// A maximal sequence of instructions that is only entered at the start and only
+/// left at the end.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Block {
+    /// The first instruction of the block.
+    pub start: usize,
+    /// One past the last instruction of the block.
+    pub end: usize,
+    pub successors: Vec<Edge>,
+    pub predecessors: Vec<usize>,
+}
+
+impl Block {
+    /// The last instruction of the block, if it is a jump.
+    pub fn jump(&self, program: &[Instruction]) -> Option<usize> {
+        if self.end > self.start && program[self.end - 1].offset().is_some() {
+            Some(self.end - 1)
+        } else {
+            None
+        }
+    }
+}
+
+/// A natural loop, the blocks that can reach a back edge to the header without
+/// passing through the header.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Loop {
+    pub header: usize,
+    pub body: BTreeSet<usize>,
+    /// The blocks with back edges to the header.
+    pub latches: Vec<usize>,
+    /// The blocks outside the loop that the loop can exit to.
+    pub exits: BTreeSet<usize>,
+}
+
+/// Whether a jump is always, never or sometimes taken, judging by its operands.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Branch {
+    Always,
+    Never,
+    Conditional,
+}
+
+pub fn branch(instruction: &Instruction) -> Branch {
+    let constant = |condition: Source, test: fn(Value) -> bool| match condition {
+        Source::Value { value } => if test(value) {
+            Branch::Always
+        } else {
+            Branch::Never
+        },
+        Source::Register { .. } => Branch::Conditional,
+    };
+    match *instruction {
+        Instruction::Jgz { condition, .. } => constant(condition, |value| value > 0),
+        Instruction::Jnz { condition, .. } => constant(condition, |value| value != 0),
+        Instruction::Jnq {
+            condition1: Source::Value { value: first },
+            condition2: Source::Value { value: second },
+            ..
+        } => if first != second {
+            Branch::Always
+        } else {
+            Branch::Never
+        },
+        Instruction::Jnq { .. } => Branch::Conditional,
+        _ => Branch::Never,
+    }
+}
+
+/// The control-flow graph of a program.
+///
+/// The last block is a virtual exit block, with no instructions, that all jumps
+/// out of the program lead to. Jumps with offsets in registers can not be
+/// followed, and lead to the exit block; `indirect` tells if there are any.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub exit: usize,
+    /// The block of each instruction.
+    pub block_of: Vec<usize>,
+    pub indirect: bool,
+}
+
+/// The target of the jump at pc, if it has a constant offset. Targets outside
+/// the program are given as the program length.
+fn jump_target(program: &[Instruction], pc: usize) -> Option<usize> {
+    match program[pc].offset() {
+        Some(Source::Value { value }) => {
+            let target = pc as Value + value;
+            if 0 <= target && target < program.len() as Value {
+                Some(target as usize)
+            } else {
+                Some(program.len())
+            }
+        }
+        _ => None,
+    }
+}
+
+impl Cfg {
+    pub fn new(program: &[Instruction]) -> Cfg {
+        let length = program.len();
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        leaders.insert(length);
+        for pc in 0..length {
+            if program[pc].offset().is_some() {
+                leaders.insert(pc + 1);
+                if let Some(target) = jump_target(program, pc) {
+                    leaders.insert(target);
+                }
+            }
+        }
+        let leaders: Vec<usize> = leaders.into_iter().collect();
+
+        let mut blocks: Vec<Block> = leaders
+            .windows(2)
+            .map(|window| Block {
+                start: window[0],
+                end: window[1],
+                successors: Vec::new(),
+                predecessors: Vec::new(),
+            })
+            .collect();
+        blocks.push(Block {
+            start: length,
+            end: length,
+            successors: Vec::new(),
+            predecessors: Vec::new(),
+        });
+        let exit = blocks.len() - 1;
+        let mut block_of = vec![0; length + 1];
+        for (index, block) in blocks.iter().enumerate() {
+            for pc in block.start..block.end {
+                block_of[pc] = index;
+            }
+        }
+        block_of[length] = exit;
+
+        let mut indirect = false;
+        for index in 0..exit {
+            let end = blocks[index].end;
+            let mut successors = Vec::new();
+            match blocks[index].jump(program) {
+                Some(pc) => {
+                    let taken = match jump_target(program, pc) {
+                        Some(target) => block_of[target],
+                        None => {
+                            indirect = true;
+                            exit
+                        }
+                    };
+                    let kind = if taken == exit && jump_target(program, pc).is_none() {
+                        Branch::Conditional
+                    } else {
+                        branch(&program[pc])
+                    };
+                    if kind != Branch::Never {
+                        successors.push(Edge {
+                            target: taken,
+                            kind: EdgeKind::Taken,
+                        });
+                    }
+                    if kind != Branch::Always {
+                        successors.push(Edge {
+                            target: block_of[end],
+                            kind: EdgeKind::Fallthrough,
+                        });
+                    }
+                }
+                None => successors.push(Edge {
+                    target: block_of[end],
+                    kind: EdgeKind::Fallthrough,
+                }),
+            }
+            for edge in &successors {
+                if !blocks[edge.target].predecessors.contains(&index) {
+                    blocks[edge.target].predecessors.push(index);
+                }
+            }
+            blocks[index].successors = successors;
+        }
+
+        Cfg {
+            blocks,
+            exit,
+            block_of,
+            indirect,
+        }
+    }
+
+    pub fn successors(&self, block: usize) -> Vec<usize> {
+        self.blocks[block]
+            .successors
+            .iter()
+            .map(|edge| edge.target)
+            .collect()
+    }
+
+    /// The successor of a block along an edge of the given kind, if any.
+    pub fn successor(&self, block: usize, kind: EdgeKind) -> Option<usize> {
+        self.blocks[block]
+            .successors
+            .iter()
+            .find(|edge| edge.kind == kind)
+            .map(|edge| edge.target)
+    }
+
+    /// The immediate dominator of each block reachable from the entry.
+    pub fn dominators(&self) -> Vec<Option<usize>> {
+        let successors: Vec<Vec<usize>> = (0..self.blocks.len())
+            .map(|block| self.successors(block))
+            .collect();
+        immediate_dominators(0, &successors)
+    }
+
+    /// The immediate post-dominator of each block that can reach the exit.
+    pub fn post_dominators(&self) -> Vec<Option<usize>> {
+        let predecessors: Vec<Vec<usize>> = self.blocks
+            .iter()
+            .map(|block| block.predecessors.clone())
+            .collect();
+        immediate_dominators(self.exit, &predecessors)
+    }
+
+    /// Whether a dominates b, given the immediate dominators.
+    pub fn dominates(dominators: &[Option<usize>], a: usize, mut b: usize) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+            match dominators[b] {
+                Some(dominator) => b = dominator,
+                None => return false,
+            }
+        }
+    }
+
+    /// The natural loops, one per header, ordered by header.
+    pub fn loops(&self) -> Vec<Loop> {
+        let dominators = self.dominators();
+        let mut loops: Vec<Loop> = Vec::new();
+        for (latch, block) in self.blocks.iter().enumerate() {
+            for edge in &block.successors {
+                let header = edge.target;
+                if dominators[latch].is_none() && latch != 0
+                    || !Cfg::dominates(&dominators, header, latch)
+                {
+                    continue;
+                }
+                let mut body = BTreeSet::new();
+                body.insert(header);
+                let mut stack = vec![latch];
+                while let Some(node) = stack.pop() {
+                    if body.insert(node) {
+                        stack.extend(self.blocks[node].predecessors.iter().cloned());
+                    }
+                }
+                match loops.iter().position(|l| l.header == header) {
+                    Some(index) => {
+                        loops[index].body.extend(body);
+                        loops[index].latches.push(latch);
+                    }
+                    None => loops.push(Loop {
+                        header,
+                        body,
+                        latches: vec![latch],
+                        exits: BTreeSet::new(),
+                    }),
+                }
+            }
+        }
+        for l in &mut loops {
+            l.exits = l.body
+                .iter()
+                .flat_map(|&node| self.successors(node))
+                .filter(|target| !l.body.contains(target))
+                .collect();
+        }
+        loops.sort_by_key(|l| l.header);
+        loops
+    }
+
+    /// The graph in Graphviz DOT format, with the instructions of each block,
+    /// taken jumps in green, fallthrough edges of conditional jumps in red, and
+    /// back edges dashed.
+    pub fn to_dot(&self, program: &[Instruction]) -> String {
+        let dominators = self.dominators();
+        let mut result = String::new();
+        writeln!(result, "digraph cfg {{").unwrap();
+        writeln!(result, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            if index == self.exit {
+                label.push_str("exit\\l");
+            }
+            for pc in block.start..block.end {
+                write!(label, "{:>3}: {}\\l", pc, program[pc]).unwrap();
+            }
+            writeln!(result, "    b{} [label=\"{}\"];", index, label).unwrap();
+        }
+        for (index, block) in self.blocks.iter().enumerate() {
+            let conditional = block.successors.len() > 1;
+            for edge in &block.successors {
+                let mut attributes = Vec::new();
+                if conditional {
+                    attributes.push(match edge.kind {
+                        EdgeKind::Taken => "color=green",
+                        EdgeKind::Fallthrough => "color=red",
+                    });
+                }
+                if (index == 0 || dominators[index].is_some())
+                    && Cfg::dominates(&dominators, edge.target, index)
+                {
+                    attributes.push("style=dashed");
+                }
+                if attributes.is_empty() {
+                    writeln!(result, "    b{} -> b{};", index, edge.target).unwrap();
+                } else {
+                    writeln!(
+                        result,
+                        "    b{} -> b{} [{}];",
+                        index,
+                        edge.target,
+                        attributes.join(", ")
+                    ).unwrap();
+                }
+            }
+        }
+        writeln!(result, "}}").unwrap();
+        result
+    }
+}
+
+/// Immediate dominators in the graph given by successors, from entry, using the
+/// iterative algorithm of Cooper, Harvey and Kennedy.
+fn immediate_dominators(entry: usize, successors: &[Vec<usize>]) -> Vec<Option<usize>> {
+    let nodes = successors.len();
+    let mut predecessors = vec![Vec::new(); nodes];
+    for (node, targets) in successors.iter().enumerate() {
+        for &target in targets {
+            predecessors[target].push(node);
+        }
+    }
+
+    // Reverse postorder from entry.
+    let mut postorder = Vec::with_capacity(nodes);
+    let mut visited = vec![false; nodes];
+    let mut stack = vec![(entry, 0)];
+    visited[entry] = true;
+    while let Some(&mut (node, ref mut next)) = stack.last_mut() {
+        if *next < successors[node].len() {
+            let target = successors[node][*next];
+            *next += 1;
+            if !visited[target] {
+                visited[target] = true;
+                stack.push((target, 0));
+            }
+        } else {
+            postorder.push(node);
+            stack.pop();
+        }
+    }
+    let mut order = vec![usize::max_value(); nodes];
+    for (index, &node) in postorder.iter().enumerate() {
+        order[node] = index;
+    }
+
+    let mut dominators: Vec<Option<usize>> = vec![None; nodes];
+    dominators[entry] = Some(entry);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in postorder.iter().rev() {
+            if node == entry {
+                continue;
+            }
+            let mut dominator: Option<usize> = None;
+            for &predecessor in &predecessors[node] {
+                if dominators[predecessor].is_none() {
+                    continue;
+                }
+                dominator = Some(match dominator {
+                    None => predecessor,
+                    Some(mut other) => {
+                        let mut candidate = predecessor;
+                        while candidate != other {
+                            while order[candidate] < order[other] {
+                                candidate = dominators[candidate].unwrap();
+                            }
+                            while order[other] < order[candidate] {
+                                other = dominators[other].unwrap();
+                            }
+                        }
+                        candidate
+                    }
+                });
+            }
+            if dominator.is_some() && dominators[node] != dominator {
+                dominators[node] = dominator;
+                changed = true;
+            }
+        }
+    }
+    dominators[entry] = None;
+    dominators
+}
@@ -0,0 +1,294 @@
+//! A line-oriented debugger for VM programs.
+//!
+//! The debugger runs a single machine with the duet dialect: values sent are
+//! collected in its outbox, and `rcv` takes values from its inbox, which the
+//! `send` command fills. Programs without `snd` and `rcv`, such as those of day
+//! 23, run just as with the coprocessor.
+//!
+//! The commands, with their short forms, are
+//!
+//!  * `step [n]` (`s`): execute n instructions, by default one,
+//!  * `continue` (`c`): run until a breakpoint or watchpoint is hit,
+//!  * `io`: run until the next `snd` or `rcv`, stopping in front of it,
+//!  * `break [pc or label]` (`b`): set a breakpoint, or list them,
+//!  * `delete pc or label`: remove a breakpoint,
+//!  * `watch [register]` (`w`): stop when the register changes, or list the
+//!    watched registers,
+//!  * `unwatch register`: stop watching the register,
+//!  * `registers` (`r`): print the registers that the program uses,
+//!  * `queue` (`q`): print the inbox, the outbox and the number of values sent,
+//!  * `send value`: add a value to the inbox,
+//!  * `list` (`l`): print the program, marking the pc and the breakpoints,
+//!  * `quit`: stop debugging.
+//!
+//! Running commands stop early when the machine halts, waits for a value or
+//! fails. Breakpoints are checked before every instruction but the first, so
+//! that continuing from a breakpoint does not stop at once.
+
+use failure::Error;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::str::FromStr;
+
+use vm::{Duet, Instruction, Machine, Program, RegisterId, Status, Value};
+use vm::assembler::jump_labels;
+
+/// How long a running command runs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Mode {
+    Step(u64),
+    Continue,
+    Io,
+}
+
+/// A number argument.
+fn number<T: FromStr>(argument: &str) -> Result<T, Error> {
+    argument
+        .parse()
+        .map_err(|_| format_err!("Not a number: {}", argument))
+}
+
+pub struct Debugger {
+    pub machine: Machine<Duet>,
+    /// The pc of every label.
+    labels: BTreeMap<String, usize>,
+    /// The first label of every labelled pc.
+    names: HashMap<usize, String>,
+    breakpoints: BTreeSet<usize>,
+    /// The watched registers, with the values they had when last checked.
+    watches: BTreeMap<RegisterId, Value>,
+    quit: bool,
+}
+
+impl Debugger {
+    /// A debugger for program, with the labels of its source. Without labels,
+    /// the labels that the disassembler would generate are used.
+    pub fn new(program: Program, labels: BTreeMap<String, usize>) -> Debugger {
+        let labels = if labels.is_empty() {
+            jump_labels(&program)
+                .into_iter()
+                .map(|(pc, label)| (label, pc))
+                .collect()
+        } else {
+            labels
+        };
+        let mut names = HashMap::new();
+        for (label, &pc) in &labels {
+            names.entry(pc).or_insert_with(|| label.clone());
+        }
+        Debugger {
+            machine: Machine::new(program, Duet::default()),
+            labels,
+            names,
+            breakpoints: BTreeSet::new(),
+            watches: BTreeMap::new(),
+            quit: false,
+        }
+    }
+
+    /// Whether the `quit` command has been given.
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Execute a command line, giving the text to show.
+    pub fn command(&mut self, line: &str) -> Result<String, Error> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, arguments) = match words.split_first() {
+            Some((command, arguments)) => (*command, arguments),
+            None => return Ok(String::new()),
+        };
+        match (command, arguments.len()) {
+            ("step", 0) | ("s", 0) => self.execute(Mode::Step(1)),
+            ("step", 1) | ("s", 1) => {
+                let count = number(arguments[0])?;
+                ensure!(count > 0, "Can not step {} instructions", count);
+                self.execute(Mode::Step(count))
+            }
+            ("continue", 0) | ("c", 0) => self.execute(Mode::Continue),
+            ("io", 0) => self.execute(Mode::Io),
+            ("break", 0) | ("b", 0) => Ok(self.list_breakpoints()),
+            ("break", 1) | ("b", 1) => {
+                let pc = self.pc_of(arguments[0])?;
+                self.breakpoints.insert(pc);
+                Ok(format!("breakpoint at {}", self.describe(pc)))
+            }
+            ("delete", 1) => {
+                let pc = self.pc_of(arguments[0])?;
+                ensure!(self.breakpoints.remove(&pc), "No breakpoint at {}", pc);
+                Ok(format!("deleted breakpoint at {}", self.describe(pc)))
+            }
+            ("watch", 0) | ("w", 0) => Ok(self.list_watches()),
+            ("watch", 1) | ("w", 1) => {
+                let id: RegisterId = arguments[0].parse()?;
+                let value = self.machine.registers[id];
+                self.watches.insert(id, value);
+                Ok(format!("watching {}={}", id, value))
+            }
+            ("unwatch", 1) => {
+                let id: RegisterId = arguments[0].parse()?;
+                ensure!(self.watches.remove(&id).is_some(), "Not watching {}", id);
+                Ok(format!("stopped watching {}", id))
+            }
+            ("registers", 0) | ("r", 0) => Ok(self.registers()),
+            ("queue", 0) | ("q", 0) => Ok(self.queue()),
+            ("send", 1) => {
+                let value = number(arguments[0])?;
+                self.machine.dialect.inbox.push_back(value);
+                Ok(self.queue())
+            }
+            ("list", 0) | ("l", 0) => Ok(self.list()),
+            ("quit", 0) => {
+                self.quit = true;
+                Ok(String::new())
+            }
+            _ => bail!("Unknown command \"{}\"", line.trim()),
+        }
+    }
+
+    /// The pc of a breakpoint, given as a pc or a label.
+    fn pc_of(&self, location: &str) -> Result<usize, Error> {
+        let pc = match location.parse::<usize>() {
+            Ok(pc) => pc,
+            Err(_) => match self.labels.get(location) {
+                Some(&pc) => pc,
+                None => bail!("Unknown label {}", location),
+            },
+        };
+        ensure!(pc < self.machine.program.len(), "No instruction at {}", pc);
+        Ok(pc)
+    }
+
+    /// The pc, its label and the instruction there.
+    fn describe(&self, pc: usize) -> String {
+        let label = match self.names.get(&pc) {
+            Some(label) => format!(" ({})", label),
+            None => String::new(),
+        };
+        match self.machine.program.get(pc) {
+            Some(instruction) => format!("{}{}: {}", pc, label, instruction),
+            None => format!("{}{}", pc, label),
+        }
+    }
+
+    /// Where the machine is.
+    fn location(&self) -> String {
+        if self.machine.halted() {
+            format!("halted at {}", self.machine.pc)
+        } else {
+            format!("at {}", self.describe(self.machine.pc as usize))
+        }
+    }
+
+    /// The watched registers that changed, updating their values.
+    fn changes(&mut self) -> Vec<String> {
+        let mut changes = Vec::new();
+        for (&id, last) in &mut self.watches {
+            let value = self.machine.registers[id];
+            if value != *last {
+                changes.push(format!("{} changed from {} to {}", id, last, value));
+                *last = value;
+            }
+        }
+        changes
+    }
+
+    fn execute(&mut self, mode: Mode) -> Result<String, Error> {
+        let mut executed = 0;
+        loop {
+            if self.machine.halted() {
+                return Ok(self.location());
+            }
+            let pc = self.machine.pc as usize;
+            if executed > 0 {
+                let stop = match mode {
+                    Mode::Step(count) => executed == count,
+                    Mode::Continue => false,
+                    Mode::Io => match self.machine.program[pc] {
+                        Instruction::Snd { .. } | Instruction::Rcv { .. } => true,
+                        _ => false,
+                    },
+                };
+                if stop {
+                    return Ok(self.location());
+                }
+                if self.breakpoints.contains(&pc) {
+                    return Ok(format!("breakpoint {}", self.location()));
+                }
+            }
+            if self.machine.step()? == Status::Waiting {
+                return Ok(format!("waiting for a value {}", self.location()));
+            }
+            executed += 1;
+            let changes = self.changes();
+            if !changes.is_empty() {
+                return Ok(format!("{}\n{}", changes.join("\n"), self.location()));
+            }
+        }
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "no breakpoints".to_string();
+        }
+        self.breakpoints
+            .iter()
+            .map(|&pc| format!("breakpoint at {}", self.describe(pc)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn list_watches(&self) -> String {
+        if self.watches.is_empty() {
+            return "no watchpoints".to_string();
+        }
+        self.watches
+            .iter()
+            .map(|(id, value)| format!("watching {}={}", id, value))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn registers(&self) -> String {
+        let used: BTreeSet<RegisterId> = self.machine
+            .program
+            .iter()
+            .flat_map(Instruction::registers)
+            .collect();
+        used.iter()
+            .map(|&id| format!("{}={}", id, self.machine.registers[id]))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn queue(&self) -> String {
+        let values = |queue: &VecDeque<Value>| {
+            queue
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        format!(
+            "inbox: [{}]\noutbox: [{}]\nsent: {}",
+            values(&self.machine.dialect.inbox),
+            values(&self.machine.dialect.outbox),
+            self.machine.dialect.sent
+        )
+    }
+
+    fn list(&self) -> String {
+        let pc = self.machine.pc;
+        self.machine
+            .program
+            .iter()
+            .enumerate()
+            .map(|(index, _)| {
+                let marker = if index as Value == pc { '>' } else { ' ' };
+                let breakpoint = if self.breakpoints.contains(&index) { '*' } else { ' ' };
+                format!("{}{} {}", marker, breakpoint, self.describe(index))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
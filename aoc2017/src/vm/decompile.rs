@@ -0,0 +1,186 @@
+//! Decompiling VM programs to Rust.
+//!
+//! The generated function takes the registers and two closures for `snd` and
+//! `rcv`:
+//!
+//! ```text
+//! pub fn name<S: FnMut(i64), R: FnMut(i64) -> Option<i64>>(
+//!     registers: &mut [i64; 26], mut snd: S, mut rcv: R)
+//! ```
+//!
+//! `snd` is given the value to send, and `rcv` the current value of its
+//! register, returning the new value or `None` to stop the program. The
+//! registers that the program uses become locals, and are written back when the
+//! program stops. Programs that can be structured become loops and `if`s;
+//! others become a `loop` over a `match` on the program counter.
+//!
+//! The arithmetic is plain Rust arithmetic, so overflow panics only in debug
+//! builds, while the interpreter always reports it.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use vm::{Instruction, RegisterId, Source, Value};
+use vm::cfg::{branch, Branch};
+use vm::structure::{statement, structure, Condition, Stmt};
+
+/// A non-jump instruction as a Rust statement.
+fn rust_statement(instruction: &Instruction) -> String {
+    match *instruction {
+        Instruction::Snd { source } => format!("snd({});", source),
+        Instruction::Rcv { target } => format!(
+            "{} = match rcv({}) {{ Some(value) => value, None => break 'program }};",
+            target, target
+        ),
+        Instruction::MulAdd {
+            target,
+            source,
+            count,
+        } => format!(
+            "assert!({} > 0); {} += {} * {}; {} = 0;",
+            count, target, source, count, count
+        ),
+        Instruction::Divides {
+            target,
+            factor,
+            product,
+            counter,
+        } => format!(
+            "assert!({} < {}); if ({}..{}).any(|k| {} * k == {}) {{ {} = 0; }} {} = {};",
+            counter, product, counter, product, factor, product, target, counter, product
+        ),
+        ref instruction => format!("{};", statement(instruction)),
+    }
+}
+
+fn line(result: &mut String, depth: usize, text: &str) {
+    writeln!(result, "{}{}", "    ".repeat(depth), text).unwrap();
+}
+
+fn structured(program: &[Instruction], stmts: &[Stmt], depth: usize, result: &mut String) {
+    for stmt in stmts {
+        match *stmt {
+            Stmt::Instruction { pc } => line(result, depth, &rust_statement(&program[pc])),
+            Stmt::If {
+                condition,
+                ref then,
+                ref otherwise,
+            } => {
+                line(result, depth, &format!("if {} {{", condition.render(program)));
+                structured(program, then, depth + 1, result);
+                if !otherwise.is_empty() {
+                    line(result, depth, "} else {");
+                    structured(program, otherwise, depth + 1, result);
+                }
+                line(result, depth, "}");
+            }
+            Stmt::While {
+                condition,
+                ref body,
+            } => {
+                line(result, depth, &format!("while {} {{", condition.render(program)));
+                structured(program, body, depth + 1, result);
+                line(result, depth, "}");
+            }
+            Stmt::DoWhile {
+                ref body,
+                condition,
+            } => {
+                line(result, depth, "loop {");
+                structured(program, body, depth + 1, result);
+                line(
+                    result,
+                    depth + 1,
+                    &format!("if {} {{", condition.negate().render(program)),
+                );
+                line(result, depth + 2, "break;");
+                line(result, depth + 1, "}");
+                line(result, depth, "}");
+            }
+            Stmt::Loop { ref body } => {
+                line(result, depth, "loop {");
+                structured(program, body, depth + 1, result);
+                line(result, depth, "}");
+            }
+            Stmt::Break => line(result, depth, "break;"),
+            Stmt::Continue => line(result, depth, "continue;"),
+            Stmt::Halt => line(result, depth, "break 'program;"),
+        }
+    }
+}
+
+/// The program as a loop over a match on the program counter, with one arm per
+/// instruction.
+fn unstructured(program: &[Instruction], depth: usize, result: &mut String) {
+    line(result, depth, "match pc {");
+    for (pc, instruction) in program.iter().enumerate() {
+        let arm = match instruction.offset() {
+            None => rust_statement(instruction),
+            Some(offset) => {
+                let jump = match offset {
+                    Source::Value { value } => format!("pc = {};", pc as Value + value),
+                    Source::Register { id } => format!("pc += {};", id),
+                };
+                match branch(instruction) {
+                    Branch::Never => String::new(),
+                    Branch::Always => format!("{} continue;", jump),
+                    Branch::Conditional => format!(
+                        "if {} {{ {} continue; }}",
+                        Condition { pc, negated: false }.render(program),
+                        jump
+                    ),
+                }
+            }
+        };
+        if arm.is_empty() {
+            line(result, depth + 1, &format!("{} => {{}}", pc));
+        } else {
+            line(result, depth + 1, &format!("{} => {{ {} }}", pc, arm));
+        }
+    }
+    line(result, depth + 1, "_ => break,");
+    line(result, depth, "}");
+    line(result, depth, "pc += 1;");
+}
+
+/// The program as the source of a Rust function with the given name.
+pub fn decompile(program: &[Instruction], name: &str) -> String {
+    let used: BTreeSet<RegisterId> = program.iter().flat_map(Instruction::registers).collect();
+    let mut result = String::new();
+    line(&mut result, 0, "/// Decompiled from duet assembly.");
+    line(
+        &mut result,
+        0,
+        "#[allow(unused_mut, unused_variables, unused_assignments, unused_labels, unreachable_code)]",
+    );
+    line(
+        &mut result,
+        0,
+        &format!(
+            "pub fn {}<S: FnMut(i64), R: FnMut(i64) -> Option<i64>>(registers: &mut [i64; 26], mut snd: S, mut rcv: R) {{",
+            name
+        ),
+    );
+    for &id in &used {
+        line(&mut result, 1, &format!("let mut {} = registers[{}];", id, id.index()));
+    }
+    match structure(program) {
+        Ok(stmts) => {
+            line(&mut result, 1, "'program: loop {");
+            structured(program, &stmts, 2, &mut result);
+            line(&mut result, 2, "break;");
+            line(&mut result, 1, "}");
+        }
+        Err(_) => {
+            line(&mut result, 1, "let mut pc: i64 = 0;");
+            line(&mut result, 1, "'program: loop {");
+            unstructured(program, 2, &mut result);
+            line(&mut result, 1, "}");
+        }
+    }
+    for &id in &used {
+        line(&mut result, 1, &format!("registers[{}] = {};", id.index(), id));
+    }
+    line(&mut result, 0, "}");
+    result
+}
@@ -0,0 +1,1294 @@
+//! A fast interpreter for VM programs.
+//!
+//! Programs are decoded once into operations on a flat slot array, holding the
+//! registers followed by the constants of the program, so that every operand is
+//! a plain index. Jumps with constant offsets go straight to the operation of
+//! their target, and common sequences such as `set g d`, `sub g b`, `jnz g 2`
+//! are fused into single operations. Operations ending in a jump also take the
+//! `snd` or `rcv` and the instruction in front of them, and an unconditional
+//! jump forward runs a copy of the operation it jumps to, so that a pass through
+//! a loop takes few operations.
+//!
+//! The observable behaviour is that of `Machine`: the same registers, opcode
+//! counts, errors and final program counter. The rare instructions that are
+//! not decoded, and operations that fail, are handed to a reference machine.
+//! It has no dialect, so `snd` and `rcv` are always decoded.
+
+use failure::Error;
+
+use std::collections::VecDeque;
+
+use vm::{Coprocessor, Dialect, Duet, Instruction, Machine, Opcode, Program, Receive, RegisterId, Registers,
+         Source, Status, Value, REGISTERS};
+
+/// An index into the slots.
+type Slot = u8;
+
+/// The number of slots. Instructions with constants beyond them are run by
+/// the reference machine.
+const SLOTS: usize = 256;
+
+/// Arithmetic in fused operations.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Arith {
+    Add,
+    Sub,
+    Mul,
+}
+
+impl Arith {
+    /// The arithmetic of an instruction that can be fused. `mod` is left to
+    /// its own operation, as computing it is slow.
+    fn of(instruction: &Instruction) -> Option<(Arith, RegisterId, Source)> {
+        match *instruction {
+            Instruction::Add { target, source } => Some((Arith::Add, target, source)),
+            Instruction::Sub { target, source } => Some((Arith::Sub, target, source)),
+            Instruction::Mul { target, source } => Some((Arith::Mul, target, source)),
+            _ => None,
+        }
+    }
+
+    /// The wrapped result, and whether it overflowed.
+    #[inline]
+    fn apply(self, first: Value, second: Value) -> (Value, bool) {
+        match self {
+            Arith::Add => first.overflowing_add(second),
+            Arith::Sub => first.overflowing_sub(second),
+            Arith::Mul => first.overflowing_mul(second),
+        }
+    }
+}
+
+/// The condition of a fused jump, as the set of signs for which it holds:
+/// bit 0 for negative values, bit 1 for zero and bit 2 for positive ones.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Test {
+    /// `jnz`
+    NonZero = 0b101,
+    /// `jgz`
+    Positive = 0b100,
+    /// `jnz` jumping over the next instruction, which is run when this holds.
+    Zero = 0b010,
+    /// `jgz` jumping over the next instruction, which is run when this holds.
+    NotPositive = 0b011,
+}
+
+impl Test {
+    #[inline]
+    fn holds(self, value: Value) -> bool {
+        let sign = (value >= 0) as u8 + (value > 0) as u8;
+        (self as u8 >> sign) & 1 != 0
+    }
+
+    fn negate(self) -> Test {
+        match self {
+            Test::NonZero => Test::Zero,
+            Test::Positive => Test::NotPositive,
+            Test::Zero => Test::NonZero,
+            Test::NotPositive => Test::Positive,
+        }
+    }
+}
+
+/// A `set` or arithmetic instruction fused in front of a jump.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Pre {
+    /// A `set`, rather than arith.
+    set: bool,
+    arith: Arith,
+    target: Slot,
+    source: Slot,
+}
+
+impl Pre {
+    /// No instruction, by setting a register to itself.
+    const NONE: Pre = Pre {
+        set: true,
+        arith: Arith::Add,
+        target: 0,
+        source: 0,
+    };
+
+    /// Run the instruction, giving the previous value of its target and
+    /// whether it overflowed.
+    #[inline]
+    fn run(self, slots: &mut [Value; SLOTS]) -> (Value, bool) {
+        let (target, source) = (self.target as usize, self.source as usize);
+        let previous = slots[target];
+        let (value, overflow) = if self.set {
+            (slots[source], false)
+        } else {
+            self.arith.apply(previous, slots[source])
+        };
+        slots[target] = value;
+        (previous, overflow)
+    }
+}
+
+/// A `snd` or `rcv` fused in front of an operation ending in a jump. It cannot
+/// be undone, so when the rest of the operation fails, the machine goes on
+/// with the instruction after it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Head {
+    None,
+    Snd(Slot),
+    Rcv(Slot),
+}
+
+/// A decoded operation. Targets of jumps are operation indices. Operations
+/// ending in a jump may run a `snd` or `rcv`, and then an instruction, first.
+#[derive(Debug, Copy, Clone)]
+enum Op {
+    Snd { source: Slot },
+    /// A `snd` of a constant without a slot, in halves so that operations
+    /// stay aligned to four bytes.
+    SndValue { high: u32, low: u32 },
+    Rcv { target: Slot },
+    Set { target: Slot, source: Slot },
+    Add { target: Slot, source: Slot },
+    Sub { target: Slot, source: Slot },
+    Mul { target: Slot, source: Slot },
+    Mod { target: Slot, source: Slot },
+    /// `set target first` followed by arithmetic on target with second.
+    Calc {
+        arith: Arith,
+        target: Slot,
+        first: Slot,
+        second: Slot,
+    },
+    /// Arithmetic on target followed by a jump testing target.
+    ArithJump {
+        head: Head,
+        pre: Pre,
+        arith: Arith,
+        test: Test,
+        target: Slot,
+        source: Slot,
+        to: u32,
+    },
+    /// `set target first`, arithmetic on target with second, and a jump
+    /// testing target.
+    CalcJump {
+        head: Head,
+        pre: Pre,
+        arith: Arith,
+        test: Test,
+        target: Slot,
+        first: Slot,
+        second: Slot,
+        to: u32,
+    },
+    /// `set target first`, arithmetic on target with second and then with
+    /// third, and a jump testing target.
+    Calc2Jump {
+        head: Head,
+        pre: Pre,
+        arith: Arith,
+        then: Arith,
+        test: Test,
+        target: Slot,
+        first: Slot,
+        second: Slot,
+        third: Slot,
+        to: u32,
+    },
+    Jump { head: Head, pre: Pre, to: u32 },
+    Jgz { head: Head, pre: Pre, condition: Slot, to: u32 },
+    Jnz { head: Head, pre: Pre, condition: Slot, to: u32 },
+    Jnq { first: Slot, second: Slot, to: u32 },
+    /// A jump with its offset in a register.
+    Indirect,
+    /// An instruction run by the reference machine.
+    Slow,
+    /// The program counter has left the program.
+    Halt,
+}
+
+/// Decoding state: the values of the registers, followed by the constants.
+struct Slots {
+    values: [Value; SLOTS],
+    used: usize,
+}
+
+impl Slots {
+    fn slot(&mut self, source: Source) -> Option<Slot> {
+        match source {
+            Source::Register { id } => Some(id.index() as Slot),
+            Source::Value { value } => {
+                // Programs have few constants, so a search is faster than a map.
+                if let Some(index) = self.values[REGISTERS..self.used]
+                    .iter()
+                    .position(|&constant| constant == value)
+                {
+                    return Some((REGISTERS + index) as Slot);
+                }
+                if self.used == SLOTS {
+                    return None;
+                }
+                self.values[self.used] = value;
+                self.used += 1;
+                Some((self.used - 1) as Slot)
+            }
+        }
+    }
+}
+
+/// The slot of a register.
+fn register(id: RegisterId) -> Slot {
+    id.index() as Slot
+}
+
+/// The absolute target of a jump with a constant offset.
+fn target(pc: usize, instruction: &Instruction) -> Option<Value> {
+    match instruction.offset() {
+        Some(Source::Value { value }) => Some((pc as Value).saturating_add(value)),
+        _ => None,
+    }
+}
+
+/// The test and absolute target of the instruction at pc, if it is a jump on
+/// register id with a constant offset.
+fn test(program: &[Instruction], pc: usize, id: RegisterId) -> Option<(Test, Value)> {
+    let (test, condition) = match program.get(pc) {
+        Some(&Instruction::Jnz { condition, .. }) => (Test::NonZero, condition),
+        Some(&Instruction::Jgz { condition, .. }) => (Test::Positive, condition),
+        _ => return None,
+    };
+    if condition != (Source::Register { id }) {
+        return None;
+    }
+    target(pc, &program[pc]).map(|to| (test, to))
+}
+
+/// A single instruction as an operation, and the absolute target of its jump
+/// where it is constant.
+fn single(program: &[Instruction], pc: usize, slots: &mut Slots) -> (Op, Option<Value>) {
+    let to = target(pc, &program[pc]);
+    let op = decode(&program[pc], to.is_some(), slots).unwrap_or(Op::Slow);
+    (op, to)
+}
+
+/// An instruction as an operation, if its constants fit in the slots.
+fn decode(instruction: &Instruction, constant: bool, slots: &mut Slots) -> Option<Op> {
+    Some(match *instruction {
+        Instruction::Snd { source } => match (slots.slot(source), source) {
+            (Some(source), _) => Op::Snd { source },
+            (None, Source::Value { value }) => Op::SndValue {
+                high: (value >> 32) as u32,
+                low: value as u32,
+            },
+            (None, Source::Register { .. }) => unreachable!(),
+        },
+        Instruction::Rcv { target } => Op::Rcv {
+            target: register(target),
+        },
+        Instruction::Set { target, source } => Op::Set {
+            target: register(target),
+            source: slots.slot(source)?,
+        },
+        Instruction::Add { target, source } => Op::Add {
+            target: register(target),
+            source: slots.slot(source)?,
+        },
+        Instruction::Sub { target, source } => Op::Sub {
+            target: register(target),
+            source: slots.slot(source)?,
+        },
+        Instruction::Mul { target, source } => Op::Mul {
+            target: register(target),
+            source: slots.slot(source)?,
+        },
+        Instruction::Mod { target, source } => Op::Mod {
+            target: register(target),
+            source: slots.slot(source)?,
+        },
+        Instruction::Jnz {
+            condition: Source::Value { value },
+            ..
+        } if constant && value != 0 =>
+        {
+            Op::Jump {
+                head: Head::None,
+                pre: Pre::NONE,
+                to: 0,
+            }
+        }
+        Instruction::Jgz {
+            condition: Source::Value { value },
+            ..
+        } if constant && value > 0 =>
+        {
+            Op::Jump {
+                head: Head::None,
+                pre: Pre::NONE,
+                to: 0,
+            }
+        }
+        Instruction::Jgz { condition, .. } if constant => Op::Jgz {
+            head: Head::None,
+            pre: Pre::NONE,
+            condition: slots.slot(condition)?,
+            to: 0,
+        },
+        Instruction::Jnz { condition, .. } if constant => Op::Jnz {
+            head: Head::None,
+            pre: Pre::NONE,
+            condition: slots.slot(condition)?,
+            to: 0,
+        },
+        Instruction::Jnq {
+            condition1,
+            condition2,
+            ..
+        } if constant =>
+        {
+            Op::Jnq {
+                first: slots.slot(condition1)?,
+                second: slots.slot(condition2)?,
+                to: 0,
+            }
+        }
+        Instruction::Jgz { .. } | Instruction::Jnz { .. } | Instruction::Jnq { .. } => Op::Indirect,
+        Instruction::MulAdd { .. } | Instruction::Divides { .. } => Op::Slow,
+    })
+}
+
+/// Arithmetic on register id at pc, not reading it, followed by a jump
+/// testing it.
+fn arith_then(program: &[Instruction], pc: usize, id: RegisterId) -> Option<(Arith, Source, (Test, Value))> {
+    match program.get(pc).and_then(Arith::of) {
+        Some((arith, target, source)) if target == id && source != (Source::Register { id }) => {
+            test(program, pc + 1, id).map(|jump| (arith, source, jump))
+        }
+        _ => None,
+    }
+}
+
+/// The fused operation starting at pc, if there is one, with its number of
+/// instructions and the absolute target of its jump. Only the first
+/// instruction may start a loop.
+fn fused(program: &[Instruction], loops: &[bool], pc: usize, slots: &mut Slots) -> Option<(Op, usize, Option<Value>)> {
+    let length = program.len();
+    let fusable = |count: usize| pc + count <= length && (pc + 1..pc + count).all(|inner| !loops[inner]);
+    let arith = |at: usize| program.get(at).and_then(Arith::of);
+    if let Instruction::Set {
+        target: assigned,
+        source: first,
+    } = program[pc]
+    {
+        match arith(pc + 1) {
+            Some((arith, target, second)) if target == assigned && fusable(2) => {
+                // Reading the target after the set reads its first value.
+                let second = if second == (Source::Register { id: target }) {
+                    first
+                } else {
+                    second
+                };
+                let then = arith_then(program, pc + 2, assigned);
+                let (target, first, second) = (register(target), slots.slot(first)?, slots.slot(second)?);
+                return Some(match (then, test(program, pc + 2, assigned)) {
+                    (Some((then, third, (test, to))), _) if fusable(4) => (
+                        Op::Calc2Jump {
+                            head: Head::None,
+                            pre: Pre::NONE,
+                            arith,
+                            then,
+                            test,
+                            target,
+                            first,
+                            second,
+                            third: slots.slot(third)?,
+                            to: 0,
+                        },
+                        4,
+                        Some(to),
+                    ),
+                    (_, Some((test, to))) if fusable(3) => (
+                        Op::CalcJump {
+                            head: Head::None,
+                            pre: Pre::NONE,
+                            arith,
+                            test,
+                            target,
+                            first,
+                            second,
+                            to: 0,
+                        },
+                        3,
+                        Some(to),
+                    ),
+                    _ => (
+                        Op::Calc {
+                            arith,
+                            target,
+                            first,
+                            second,
+                        },
+                        2,
+                        None,
+                    ),
+                });
+            }
+            _ => {}
+        }
+    }
+    match arith(pc) {
+        Some((arith, target, source)) if fusable(2) => {
+            let (test, to) = test(program, pc + 1, target)?;
+            Some((
+                Op::ArithJump {
+                    head: Head::None,
+                    pre: Pre::NONE,
+                    arith,
+                    test,
+                    target: register(target),
+                    source: slots.slot(source)?,
+                    to: 0,
+                },
+                2,
+                Some(to),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// The operation at pc + 1, if it ends in a jump, with the instruction at pc
+/// fused in front of it.
+fn prefixed(program: &[Instruction], loops: &[bool], pc: usize, slots: &mut Slots) -> Option<(Op, usize, Option<Value>)> {
+    if pc + 1 >= program.len() || loops[pc + 1] {
+        return None;
+    }
+    let (set, (arith, target, source)) = match program[pc] {
+        Instruction::Set { target, source } => (true, (Arith::Add, target, source)),
+        ref instruction => (false, Arith::of(instruction)?),
+    };
+    let first = Pre {
+        set,
+        arith,
+        target: register(target),
+        source: slots.slot(source)?,
+    };
+    let (mut op, count, to) = plain(program, loops, pc + 1, slots);
+    match op {
+        Op::ArithJump { ref mut pre, .. }
+        | Op::CalcJump { ref mut pre, .. }
+        | Op::Calc2Jump { ref mut pre, .. }
+        | Op::Jump { ref mut pre, .. }
+        | Op::Jgz { ref mut pre, .. }
+        | Op::Jnz { ref mut pre, .. } => *pre = first,
+        _ => return None,
+    }
+    Some((op, count + 1, to))
+}
+
+/// The operation at pc + 1, if it ends in a jump, with the `snd` or `rcv` at
+/// pc fused in front of it.
+fn headed(program: &[Instruction], loops: &[bool], pc: usize, slots: &mut Slots) -> Option<(Op, usize, Option<Value>)> {
+    if pc + 1 >= program.len() || loops[pc + 1] {
+        return None;
+    }
+    let first = match program[pc] {
+        Instruction::Snd { source } => Head::Snd(slots.slot(source)?),
+        Instruction::Rcv { target } => Head::Rcv(register(target)),
+        _ => return None,
+    };
+    let (mut op, count, to) =
+        prefixed(program, loops, pc + 1, slots).unwrap_or_else(|| plain(program, loops, pc + 1, slots));
+    match op {
+        Op::ArithJump { ref mut head, .. }
+        | Op::CalcJump { ref mut head, .. }
+        | Op::Calc2Jump { ref mut head, .. }
+        | Op::Jump { ref mut head, .. }
+        | Op::Jgz { ref mut head, .. }
+        | Op::Jnz { ref mut head, .. } => *head = first,
+        _ => return None,
+    }
+    Some((op, count + 1, to))
+}
+
+/// The operation starting at pc with nothing fused in front, with its number
+/// of instructions and the absolute target of its jump.
+fn plain(program: &[Instruction], loops: &[bool], pc: usize, slots: &mut Slots) -> (Op, usize, Option<Value>) {
+    fused(program, loops, pc, slots).unwrap_or_else(|| {
+        let (op, to) = single(program, pc, slots);
+        (op, 1, to)
+    })
+}
+
+/// The operation starting at pc, with its number of instructions and the
+/// absolute target of its jump.
+fn operation(program: &[Instruction], loops: &[bool], pc: usize, slots: &mut Slots) -> (Op, usize, Option<Value>) {
+    headed(program, loops, pc, slots)
+        .or_else(|| prefixed(program, loops, pc, slots))
+        .unwrap_or_else(|| plain(program, loops, pc, slots))
+}
+
+/// The operation decoded at pc, with its number of instructions, the number of
+/// instructions it stands for and the absolute target of its jump.
+///
+/// A fused jump over the next instruction usually jumps, so it is turned around
+/// to fall through to the operation after it, and to jump to the operation of
+/// the skipped instruction, which it then stands for as well.
+fn turned(program: &[Instruction], pc: usize, decoded: (Op, usize, Option<Value>)) -> (Op, usize, usize, Option<Value>) {
+    let (mut op, count, mut to) = decoded;
+    let mut span = count;
+    if to == Some((pc + count + 1) as Value) && pc + count < program.len() {
+        if let Op::ArithJump { ref mut test, .. }
+        | Op::CalcJump { ref mut test, .. }
+        | Op::Calc2Jump { ref mut test, .. } = op
+        {
+            *test = test.negate();
+            to = Some((pc + count) as Value);
+            span += 1;
+        }
+    }
+    (op, count, span, to)
+}
+
+/// The operation, if it ends in a jump and has neither head nor instruction in
+/// front, with the given ones put there.
+fn behind(mut op: Op, first: Head, before: Pre) -> Option<Op> {
+    match op {
+        Op::ArithJump {
+            ref mut head,
+            ref mut pre,
+            ..
+        }
+        | Op::CalcJump {
+            ref mut head,
+            ref mut pre,
+            ..
+        }
+        | Op::Calc2Jump {
+            ref mut head,
+            ref mut pre,
+            ..
+        }
+        | Op::Jgz {
+            ref mut head,
+            ref mut pre,
+            ..
+        }
+        | Op::Jnz {
+            ref mut head,
+            ref mut pre,
+            ..
+        } if *head == Head::None && *pre == Pre::NONE =>
+        {
+            *head = first;
+            *pre = before;
+        }
+        _ => return None,
+    }
+    Some(op)
+}
+
+/// Why `execute` stopped.
+enum Exit {
+    /// The operation is run by the reference machine, or failed and is
+    /// replayed by it to report the error.
+    Reference,
+    Waiting,
+    /// The `rcv` at the start of the operation halted the machine.
+    Received,
+    /// The `snd` or `rcv` at the start of the operation ran, but the rest of it
+    /// is left to the operations after that instruction.
+    Headed,
+    /// The operation is a halt.
+    Halted,
+    /// The dialect failed.
+    Error(Error),
+}
+
+/// Execute operations from op until one that the machine has to handle, which
+/// is returned with the reason. Operations are counted when they complete.
+fn execute<D: Dialect>(
+    code: &[Op],
+    slots: &mut [Value; SLOTS],
+    hits: &mut [u64],
+    dialect: &mut D,
+    mut op: usize,
+) -> (usize, Exit) {
+    // Lets the compiler drop the bounds checks on hits.
+    let hits = &mut hits[..code.len()];
+    macro_rules! exit {
+        ($exit:expr) => {{
+            return (op, $exit);
+        }};
+    }
+    macro_rules! checked {
+        ($value:expr) => {
+            match $value {
+                Some(value) => value,
+                None => exit!(Exit::Reference),
+            }
+        };
+    }
+    macro_rules! receive {
+        ($target:expr) => {{
+            let target = $target as usize;
+            match dialect.receive(slots[target]) {
+                Ok(Receive::Store(value)) => slots[target] = value,
+                Ok(Receive::Continue) => {}
+                Ok(Receive::Wait) => exit!(Exit::Waiting),
+                Ok(Receive::Halt) => exit!(Exit::Received),
+                Err(error) => exit!(Exit::Error(error)),
+            }
+        }};
+    }
+    macro_rules! send {
+        ($value:expr) => {{
+            if let Err(error) = dialect.send($value) {
+                exit!(Exit::Error(error));
+            }
+        }};
+    }
+    macro_rules! head {
+        ($head:expr) => {{
+            match $head {
+                Head::None => {}
+                Head::Snd(source) => send!(slots[source as usize]),
+                Head::Rcv(target) => receive!(target),
+            }
+        }};
+    }
+    // Fused operations check for overflow once, at the end. If any part
+    // overflowed, the instruction in front is undone, and the operation is left
+    // to the reference machine, or to the operations after its head.
+    macro_rules! undo {
+        ($head:expr, $pre:expr, $previous:expr) => {{
+            slots[$pre.target as usize] = $previous;
+            match $head {
+                Head::None => exit!(Exit::Reference),
+                _ => exit!(Exit::Headed),
+            }
+        }};
+    }
+    loop {
+        let next = match code[op] {
+            Op::Snd { source } => {
+                send!(slots[source as usize]);
+                op + 1
+            }
+            Op::SndValue { high, low } => {
+                send!((u64::from(high) << 32 | u64::from(low)) as Value);
+                op + 1
+            }
+            Op::Rcv { target } => {
+                receive!(target);
+                op + 1
+            }
+            Op::Set { target, source } => {
+                slots[target as usize] = slots[source as usize];
+                op + 1
+            }
+            Op::Add { target, source } => {
+                let target = target as usize;
+                let (value, overflow) = Arith::Add.apply(slots[target], slots[source as usize]);
+                if overflow {
+                    exit!(Exit::Reference);
+                }
+                slots[target] = value;
+                op + 1
+            }
+            Op::Sub { target, source } => {
+                let target = target as usize;
+                let (value, overflow) = Arith::Sub.apply(slots[target], slots[source as usize]);
+                if overflow {
+                    exit!(Exit::Reference);
+                }
+                slots[target] = value;
+                op + 1
+            }
+            Op::Mul { target, source } => {
+                let target = target as usize;
+                let (value, overflow) = Arith::Mul.apply(slots[target], slots[source as usize]);
+                if overflow {
+                    exit!(Exit::Reference);
+                }
+                slots[target] = value;
+                op + 1
+            }
+            Op::Mod { target, source } => {
+                let target = target as usize;
+                slots[target] = checked!(slots[target].checked_rem(slots[source as usize]));
+                op + 1
+            }
+            Op::Calc {
+                arith,
+                target,
+                first,
+                second,
+            } => {
+                let (value, overflow) = arith.apply(slots[first as usize], slots[second as usize]);
+                if overflow {
+                    exit!(Exit::Reference);
+                }
+                slots[target as usize] = value;
+                op + 1
+            }
+            Op::ArithJump {
+                head: Head::None,
+                pre,
+                arith,
+                test,
+                target,
+                source,
+                to,
+            } => {
+                let (previous, early) = pre.run(slots);
+                let target = target as usize;
+                let (value, overflow) = arith.apply(slots[target], slots[source as usize]);
+                if early | overflow {
+                    undo!(Head::None, pre, previous);
+                }
+                slots[target] = value;
+                if test.holds(value) {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::ArithJump {
+                head,
+                pre,
+                arith,
+                test,
+                target,
+                source,
+                to,
+            } => {
+                head!(head);
+                let (previous, early) = pre.run(slots);
+                let target = target as usize;
+                let (value, overflow) = arith.apply(slots[target], slots[source as usize]);
+                if early | overflow {
+                    undo!(head, pre, previous);
+                }
+                slots[target] = value;
+                if test.holds(value) {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::CalcJump {
+                head: Head::None,
+                pre,
+                arith,
+                test,
+                target,
+                first,
+                second,
+                to,
+            } => {
+                let (previous, early) = pre.run(slots);
+                let (value, overflow) = arith.apply(slots[first as usize], slots[second as usize]);
+                if early | overflow {
+                    undo!(Head::None, pre, previous);
+                }
+                slots[target as usize] = value;
+                if test.holds(value) {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::CalcJump {
+                head,
+                pre,
+                arith,
+                test,
+                target,
+                first,
+                second,
+                to,
+            } => {
+                head!(head);
+                let (previous, early) = pre.run(slots);
+                let (value, overflow) = arith.apply(slots[first as usize], slots[second as usize]);
+                if early | overflow {
+                    undo!(head, pre, previous);
+                }
+                slots[target as usize] = value;
+                if test.holds(value) {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::Calc2Jump {
+                head: Head::None,
+                pre,
+                arith,
+                then,
+                test,
+                target,
+                first,
+                second,
+                third,
+                to,
+            } => {
+                let (previous, early) = pre.run(slots);
+                let (value, overflow) = arith.apply(slots[first as usize], slots[second as usize]);
+                let (value, later) = then.apply(value, slots[third as usize]);
+                if early | overflow | later {
+                    undo!(Head::None, pre, previous);
+                }
+                slots[target as usize] = value;
+                if test.holds(value) {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::Calc2Jump {
+                head,
+                pre,
+                arith,
+                then,
+                test,
+                target,
+                first,
+                second,
+                third,
+                to,
+            } => {
+                head!(head);
+                let (previous, early) = pre.run(slots);
+                let (value, overflow) = arith.apply(slots[first as usize], slots[second as usize]);
+                let (value, later) = then.apply(value, slots[third as usize]);
+                if early | overflow | later {
+                    undo!(head, pre, previous);
+                }
+                slots[target as usize] = value;
+                if test.holds(value) {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::Jump { head: Head::None, pre, to } => {
+                let (previous, overflow) = pre.run(slots);
+                if overflow {
+                    undo!(Head::None, pre, previous);
+                }
+                to as usize
+            }
+            Op::Jump { head, pre, to } => {
+                head!(head);
+                let (previous, overflow) = pre.run(slots);
+                if overflow {
+                    undo!(head, pre, previous);
+                }
+                to as usize
+            }
+            Op::Jgz {
+                head: Head::None,
+                pre,
+                condition,
+                to,
+            } => {
+                let (previous, overflow) = pre.run(slots);
+                if overflow {
+                    undo!(Head::None, pre, previous);
+                }
+                if slots[condition as usize] > 0 {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::Jgz {
+                head,
+                pre,
+                condition,
+                to,
+            } => {
+                head!(head);
+                let (previous, overflow) = pre.run(slots);
+                if overflow {
+                    undo!(head, pre, previous);
+                }
+                if slots[condition as usize] > 0 {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::Jnz {
+                head: Head::None,
+                pre,
+                condition,
+                to,
+            } => {
+                let (previous, overflow) = pre.run(slots);
+                if overflow {
+                    undo!(Head::None, pre, previous);
+                }
+                if slots[condition as usize] != 0 {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::Jnz {
+                head,
+                pre,
+                condition,
+                to,
+            } => {
+                head!(head);
+                let (previous, overflow) = pre.run(slots);
+                if overflow {
+                    undo!(head, pre, previous);
+                }
+                if slots[condition as usize] != 0 {
+                    to as usize
+                } else {
+                    op + 1
+                }
+            }
+            Op::Jnq { first, second, to } => if slots[first as usize] != slots[second as usize] {
+                to as usize
+            } else {
+                op + 1
+            },
+            Op::Indirect | Op::Slow => exit!(Exit::Reference),
+            Op::Halt => exit!(Exit::Halted),
+        };
+        hits[op] += 1;
+        op = next;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FastMachine<D: Dialect> {
+    code: Vec<Op>,
+    /// The pc of the first instruction of each operation.
+    pcs: Vec<Value>,
+    /// The operation to continue with at each pc.
+    entry: Vec<u32>,
+    /// The number of instructions of each operation.
+    lengths: Vec<usize>,
+    /// The pc and number of the instructions that operations run after an
+    /// unconditional jump, if any.
+    tails: Vec<(usize, usize)>,
+    /// How many times each operation has been executed.
+    hits: Vec<u64>,
+    /// Instructions executed by operations that failed part way.
+    partial: [u64; Opcode::COUNT],
+    slots: [Value; SLOTS],
+    op: usize,
+    pc: Value,
+    halted: bool,
+    pub dialect: D,
+    /// Runs slow instructions, and replays failing ones to report the error.
+    /// It never runs `snd` or `rcv`, which need the dialect.
+    reference: Machine<Coprocessor>,
+}
+
+impl<D: Dialect> FastMachine<D> {
+    pub fn new(program: Program, dialect: D) -> FastMachine<D> {
+        let length = program.len();
+        // Operations fuse instructions across targets of jumps, which then
+        // get operations of their own, but not across the start of a loop, as
+        // it is entered once and jumped back to many times.
+        let mut loops = vec![false; length];
+        for (pc, instruction) in program.iter().enumerate() {
+            if let Some(to) = target(pc, instruction) {
+                if 0 <= to && to <= pc as Value {
+                    loops[to as usize] = true;
+                }
+            }
+        }
+        let mut slots = Slots {
+            values: [0; SLOTS],
+            used: REGISTERS,
+        };
+
+        // Decode runs of operations that fall through to each other, leaving
+        // jump targets as pcs for now. Jumps with offsets in registers can land
+        // anywhere, also inside fused operations, so a run is started at every
+        // pc that does not have an operation yet.
+        let mut code = Vec::with_capacity(2 * length);
+        let mut pcs = Vec::with_capacity(2 * length);
+        let mut lengths = Vec::with_capacity(2 * length);
+        let mut tails = Vec::with_capacity(2 * length);
+        let mut entry = vec![None; length];
+        let mut jumps: Vec<(usize, Value)> = Vec::with_capacity(2 * length);
+        // Jumps leave the program at few places, so a search is faster than a
+        // map.
+        let mut halts: Vec<(Value, u32)> = Vec::new();
+        let mut starts: Vec<usize> = (0..length).rev().collect();
+        while let Some(mut pc) = starts.pop() {
+            if entry[pc].is_some() {
+                continue;
+            }
+            while pc < length && entry[pc].is_none() {
+                let (mut op, count, span, mut to) = turned(&program, pc, operation(&program, &loops, pc, &mut slots));
+                starts.extend((pc + 1..pc + span).rev());
+                let mut next = pc + span;
+                // An unconditional jump forward to an operation ending in a
+                // jump is run as part of a copy of that operation, which the
+                // run goes on after.
+                let mut tail = (0, 0);
+                if let (Op::Jump { head, pre, .. }, Some(at)) = (op, to) {
+                    if pc < at as usize && (at as usize) < length && !loops[at as usize] {
+                        let at = at as usize;
+                        let (body, count, span, body_to) = turned(&program, at, plain(&program, &loops, at, &mut slots));
+                        if let Some(body) = behind(body, head, pre) {
+                            op = body;
+                            to = body_to;
+                            tail = (at, count);
+                            starts.extend((at..at + span).rev());
+                            next = at + span;
+                        }
+                    }
+                }
+                entry[pc] = Some(code.len() as u32);
+                if let Some(to) = to {
+                    jumps.push((code.len(), to));
+                }
+                code.push(op);
+                pcs.push(pc as Value);
+                lengths.push(count);
+                tails.push(tail);
+                pc = next;
+            }
+            // Falling off the end halts. Otherwise the run goes on with the
+            // operation at pc, through a jump that counts as no instruction.
+            if pc == length && halts.is_empty() {
+                halts.push((length as Value, code.len() as u32));
+                code.push(Op::Halt);
+            } else {
+                jumps.push((code.len(), pc as Value));
+                code.push(Op::Jump {
+                    head: Head::None,
+                    pre: Pre::NONE,
+                    to: 0,
+                });
+            }
+            pcs.push(pc as Value);
+            lengths.push(0);
+            tails.push((0, 0));
+        }
+        let entry: Vec<u32> = entry.into_iter().map(|op| op.unwrap()).collect();
+
+        // Halting operations for each jump out of the program.
+        let mut halt = |at: Value, code: &mut Vec<Op>, pcs: &mut Vec<Value>, lengths: &mut Vec<usize>| {
+            if let Some(&(_, op)) = halts.iter().find(|&&(pc, _)| pc == at) {
+                return op;
+            }
+            code.push(Op::Halt);
+            pcs.push(at);
+            lengths.push(0);
+            halts.push((at, (code.len() - 1) as u32));
+            (code.len() - 1) as u32
+        };
+        for (index, to) in jumps {
+            let resolved = if 0 <= to && to < length as Value {
+                entry[to as usize]
+            } else {
+                halt(to, &mut code, &mut pcs, &mut lengths)
+            };
+            match code[index] {
+                Op::ArithJump { ref mut to, .. }
+                | Op::CalcJump { ref mut to, .. }
+                | Op::Calc2Jump { ref mut to, .. }
+                | Op::Jump { ref mut to, .. }
+                | Op::Jgz { ref mut to, .. }
+                | Op::Jnz { ref mut to, .. }
+                | Op::Jnq { ref mut to, .. } => *to = resolved,
+                _ => unreachable!(),
+            }
+        }
+
+        let operations = code.len();
+        tails.resize(operations, (0, 0));
+        FastMachine {
+            code,
+            pcs,
+            entry,
+            lengths,
+            tails,
+            hits: vec![0; operations],
+            partial: [0; Opcode::COUNT],
+            slots: slots.values,
+            op: 0,
+            pc: 0,
+            halted: length == 0,
+            dialect,
+            reference: Machine::new(program, Coprocessor),
+        }
+    }
+
+    pub fn register(&self, id: RegisterId) -> Value {
+        self.slots[id.index()]
+    }
+
+    pub fn set_register(&mut self, id: RegisterId, value: Value) {
+        self.slots[id.index()] = value;
+    }
+
+    pub fn registers(&self) -> Registers {
+        let mut registers = Registers::new();
+        for index in 0..REGISTERS {
+            registers[RegisterId::from_index(index).unwrap()] = self.slots[index];
+        }
+        registers
+    }
+
+    /// The program counter, in instructions of the original program, as of
+    /// the last time the machine stopped.
+    pub fn pc(&self) -> Value {
+        self.pc
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// The instructions of an operation, before and after its unconditional
+    /// jump.
+    fn instructions(&self, op: usize) -> [&[Instruction]; 2] {
+        let start = self.pcs[op] as usize;
+        let (tail, length) = self.tails[op];
+        let program = &self.reference.program;
+        match self.lengths[op] {
+            0 => [&[], &[]],
+            count => [&program[start..start + count], &program[tail..tail + length]],
+        }
+    }
+
+    pub fn count(&self, opcode: Opcode) -> u64 {
+        let full: u64 = (0..self.code.len())
+            .map(|op| {
+                let matching = self.instructions(op)
+                    .iter()
+                    .flat_map(|instructions| instructions.iter())
+                    .filter(|instruction| instruction.opcode() == opcode)
+                    .count();
+                self.hits[op] * matching as u64
+            })
+            .sum();
+        full + self.partial[opcode.index()]
+    }
+
+    /// The total number of executed instructions.
+    pub fn steps(&self) -> u64 {
+        let full: u64 = self.hits
+            .iter()
+            .zip(self.lengths.iter().zip(&self.tails))
+            .map(|(hits, (&length, &(_, tail)))| hits * (length + tail) as u64)
+            .sum();
+        full + self.partial.iter().sum::<u64>()
+    }
+
+    /// Set up the reference machine at the start of an operation.
+    fn load_reference(&mut self, op: usize) {
+        for index in 0..REGISTERS {
+            self.reference.registers[RegisterId::from_index(index).unwrap()] = self.slots[index];
+        }
+        self.reference.pc = self.pcs[op];
+    }
+
+    /// Take back the registers and program counter of the reference machine.
+    fn store_reference(&mut self) {
+        for index in 0..REGISTERS {
+            self.slots[index] = self.reference.registers[RegisterId::from_index(index).unwrap()];
+        }
+        self.pc = self.reference.pc;
+    }
+
+    /// Run an operation that needs the reference machine. For one that failed,
+    /// this gives the error of its failing instruction, leaving the machine as
+    /// the reference machine would be.
+    fn run_reference(&mut self, op: usize) -> Result<(), Error> {
+        self.load_reference(op);
+        if let Op::Indirect | Op::Slow = self.code[op] {
+            self.reference.step()?;
+            self.store_reference();
+            self.hits[op] += 1;
+            let pc = self.pc;
+            if 0 <= pc && pc < self.entry.len() as Value {
+                self.op = self.entry[pc as usize] as usize;
+            } else {
+                self.halted = true;
+            }
+            return Ok(());
+        }
+        // The reference machine follows the unconditional jump to the tail.
+        for _ in 0..self.lengths[op] + self.tails[op].1 {
+            let opcode = self.reference.program[self.reference.pc as usize].opcode();
+            if let Err(error) = self.reference.step() {
+                self.store_reference();
+                return Err(error);
+            }
+            self.partial[opcode.index()] += 1;
+        }
+        self.store_reference();
+        bail!("Operation at {} failed, but its instructions did not", self.pcs[op])
+    }
+
+    /// Count the `snd` or `rcv` at pc as run on its own, and move past it.
+    fn pass_head(&mut self) {
+        let opcode = self.reference.program[self.pc as usize].opcode();
+        self.partial[opcode.index()] += 1;
+        self.pc += 1;
+    }
+
+    /// Run until the machine waits or halts.
+    pub fn run(&mut self) -> Result<Status, Error> {
+        while !self.halted {
+            let (op, exit) = execute(&self.code, &mut self.slots, &mut self.hits, &mut self.dialect, self.op);
+            self.op = op;
+            self.pc = self.pcs[op];
+            match exit {
+                Exit::Reference => self.run_reference(op)?,
+                Exit::Waiting => return Ok(Status::Waiting),
+                Exit::Received => {
+                    self.pass_head();
+                    self.halted = true;
+                }
+                Exit::Headed => {
+                    self.pass_head();
+                    self.op = self.entry[self.pc as usize] as usize;
+                }
+                Exit::Halted => self.halted = true,
+                Exit::Error(error) => return Err(error),
+            }
+        }
+        Ok(Status::Halted)
+    }
+}
+
+/// Run two copies of program against each other, as `duet` does, but letting
+/// each run until it waits before delivering what it sent.
+///
+/// As each program only depends on the values it receives, and not on when they
+/// arrive, this ends the same way as running them in lockstep.
+pub fn duet(program: &[Instruction]) -> Result<[FastMachine<Duet>; 2], Error> {
+    let id_p = RegisterId::from_name('p')?;
+    let mut machines = [
+        FastMachine::new(program.to_vec(), Duet::default()),
+        FastMachine::new(program.to_vec(), Duet::default()),
+    ];
+    for (id, machine) in machines.iter_mut().enumerate() {
+        machine.set_register(id_p, id as Value);
+    }
+    loop {
+        let mut progress = false;
+        for from in 0..2 {
+            machines[from].run()?;
+            let mut sent = ::std::mem::replace(&mut machines[from].dialect.outbox, VecDeque::new());
+            progress |= !sent.is_empty();
+            machines[1 - from].dialect.inbox.append(&mut sent);
+            machines[from].dialect.outbox = sent;
+        }
+        if !progress {
+            return Ok(machines);
+        }
+    }
+}
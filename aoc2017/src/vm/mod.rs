@@ -0,0 +1,816 @@
+//! A virtual machine for the duet (day 18) and coprocessor (day 23) assembly.
+//!
+//! The instruction set is the union of the instructions used in both puzzles,
+//! including the `jnq` instruction used in rewritten day 23 programs. What `snd`
+//! and `rcv` do differs between the puzzles, and is decided by a `Dialect`.
+
+use failure::Error;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::{Index, IndexMut};
+use std::str::FromStr;
+
+use vm::profile::Profile;
+
+pub mod assembler;
+pub mod cfg;
+pub mod debugger;
+pub mod decompile;
+pub mod fast;
+pub mod optimize;
+pub mod profile;
+pub mod scheduler;
+pub mod structure;
+pub mod threads;
+
+#[derive(Fail, Debug)]
+#[fail(display = "Not a valid name: {}.", _0)]
+pub struct NotValidName(String);
+
+#[derive(Fail, Debug)]
+#[fail(display = "Not a valid index for register: {}.", _0)]
+pub struct NotValidIndex(usize);
+
+#[derive(Fail, Debug)]
+#[fail(display = "Not enough tokens to parse")]
+pub struct MissingTokens;
+
+pub type Value = i64;
+
+pub const REGISTERS: usize = 26;
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct RegisterId {
+    identifier: u8,
+}
+
+impl RegisterId {
+    pub fn from_name(name: char) -> Result<RegisterId, Error> {
+        if 'a' <= name && name < (b'a' + REGISTERS as u8) as char {
+            Ok(RegisterId {
+                identifier: name as u8 - b'a',
+            })
+        } else {
+            Err(Error::from(NotValidName(name.to_string())))
+        }
+    }
+
+    pub fn from_index(index: usize) -> Result<RegisterId, Error> {
+        if index < REGISTERS {
+            Ok(RegisterId {
+                identifier: index as u8,
+            })
+        } else {
+            Err(Error::from(NotValidIndex(index)))
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.identifier as usize
+    }
+
+    pub fn name(&self) -> char {
+        (self.identifier + b'a') as char
+    }
+}
+
+impl fmt::Display for RegisterId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for RegisterId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(name), None) => RegisterId::from_name(name),
+            _ => Err(Error::from(NotValidName(name.to_string()))),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Source {
+    Register { id: RegisterId },
+    Value { value: Value },
+}
+
+impl Source {
+    pub fn value(&self, registers: &Registers) -> Value {
+        match *self {
+            Source::Register { id } => registers[id],
+            Source::Value { value } => value,
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Source::Register { id } => write!(f, "{}", id),
+            Source::Value { value } => write!(f, "{}", value),
+        }
+    }
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        Ok(if let Ok(value) = name.parse() {
+            Source::Value { value }
+        } else {
+            Source::Register { id: name.parse()? }
+        })
+    }
+}
+
+/// The kind of an instruction, without its operands.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Opcode {
+    Snd,
+    Set,
+    Add,
+    Sub,
+    Mul,
+    Mod,
+    Rcv,
+    Jgz,
+    Jnz,
+    Jnq,
+    MulAdd,
+    Divides,
+}
+
+impl Opcode {
+    pub const COUNT: usize = 12;
+
+    pub fn all() -> [Opcode; Opcode::COUNT] {
+        use self::Opcode::*;
+        [Snd, Set, Add, Sub, Mul, Mod, Rcv, Jgz, Jnz, Jnq, MulAdd, Divides]
+    }
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// The number of operands taken.
+    pub fn operands(&self) -> usize {
+        match *self {
+            Opcode::Snd | Opcode::Rcv => 1,
+            Opcode::Jnq | Opcode::MulAdd => 3,
+            Opcode::Divides => 4,
+            _ => 2,
+        }
+    }
+
+    /// Whether the last operand is a jump offset.
+    pub fn is_jump(&self) -> bool {
+        match *self {
+            Opcode::Jgz | Opcode::Jnz | Opcode::Jnq => true,
+            _ => false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Opcode::Snd => "snd",
+            Opcode::Set => "set",
+            Opcode::Add => "add",
+            Opcode::Sub => "sub",
+            Opcode::Mul => "mul",
+            Opcode::Mod => "mod",
+            Opcode::Rcv => "rcv",
+            Opcode::Jgz => "jgz",
+            Opcode::Jnz => "jnz",
+            Opcode::Jnq => "jnq",
+            Opcode::MulAdd => "mul-add",
+            Opcode::Divides => "divides",
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Opcode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Opcode::all().iter().find(|opcode| opcode.name() == s) {
+            Some(&opcode) => Ok(opcode),
+            None => bail!("Unknown instruction \"{}\"", s),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Instruction {
+    Snd { source: Source },
+    Set { target: RegisterId, source: Source },
+    Add { target: RegisterId, source: Source },
+    Sub { target: RegisterId, source: Source },
+    Mul { target: RegisterId, source: Source },
+    Mod { target: RegisterId, source: Source },
+    Rcv { target: RegisterId },
+    /// Jump if condition is greater than zero.
+    Jgz { condition: Source, offset: Source },
+    /// Jump if condition is not zero.
+    Jnz { condition: Source, offset: Source },
+    /// Jump if the two conditions are not equal.
+    Jnq {
+        condition1: Source,
+        condition2: Source,
+        offset: Source,
+    },
+    /// Add source times count to target and clear count, as a loop adding source
+    /// to target count times would. Count must be positive.
+    MulAdd {
+        target: RegisterId,
+        source: Source,
+        count: RegisterId,
+    },
+    /// Clear target if factor times some value from counter up to, but not
+    /// including, product is product, and set counter to product, as a loop
+    /// trying each value would. Counter must be less than product.
+    Divides {
+        target: RegisterId,
+        factor: Source,
+        product: Source,
+        counter: RegisterId,
+    },
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> Opcode {
+        match *self {
+            Instruction::Snd { .. } => Opcode::Snd,
+            Instruction::Set { .. } => Opcode::Set,
+            Instruction::Add { .. } => Opcode::Add,
+            Instruction::Sub { .. } => Opcode::Sub,
+            Instruction::Mul { .. } => Opcode::Mul,
+            Instruction::Mod { .. } => Opcode::Mod,
+            Instruction::Rcv { .. } => Opcode::Rcv,
+            Instruction::Jgz { .. } => Opcode::Jgz,
+            Instruction::Jnz { .. } => Opcode::Jnz,
+            Instruction::Jnq { .. } => Opcode::Jnq,
+            Instruction::MulAdd { .. } => Opcode::MulAdd,
+            Instruction::Divides { .. } => Opcode::Divides,
+        }
+    }
+
+    /// The offset of a jump instruction.
+    pub fn offset(&self) -> Option<Source> {
+        match *self {
+            Instruction::Jgz { offset, .. }
+            | Instruction::Jnz { offset, .. }
+            | Instruction::Jnq { offset, .. } => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// The instruction with the offset of a jump replaced.
+    pub fn with_offset(&self, offset: Source) -> Instruction {
+        match *self {
+            Instruction::Jgz { condition, .. } => Instruction::Jgz { condition, offset },
+            Instruction::Jnz { condition, .. } => Instruction::Jnz { condition, offset },
+            Instruction::Jnq {
+                condition1,
+                condition2,
+                ..
+            } => Instruction::Jnq {
+                condition1,
+                condition2,
+                offset,
+            },
+            instruction => instruction,
+        }
+    }
+
+    /// The registers that an instruction reads or writes.
+    pub fn registers(&self) -> Vec<RegisterId> {
+        let sources = match *self {
+            Instruction::Snd { source } => vec![source],
+            Instruction::Set { target, source }
+            | Instruction::Add { target, source }
+            | Instruction::Sub { target, source }
+            | Instruction::Mul { target, source }
+            | Instruction::Mod { target, source } => vec![Source::Register { id: target }, source],
+            Instruction::Rcv { target } => vec![Source::Register { id: target }],
+            Instruction::Jgz { condition, offset } | Instruction::Jnz { condition, offset } => {
+                vec![condition, offset]
+            }
+            Instruction::Jnq {
+                condition1,
+                condition2,
+                offset,
+            } => vec![condition1, condition2, offset],
+            Instruction::MulAdd {
+                target,
+                source,
+                count,
+            } => vec![
+                Source::Register { id: target },
+                source,
+                Source::Register { id: count },
+            ],
+            Instruction::Divides {
+                target,
+                factor,
+                product,
+                counter,
+            } => vec![
+                Source::Register { id: target },
+                factor,
+                product,
+                Source::Register { id: counter },
+            ],
+        };
+        sources
+            .into_iter()
+            .filter_map(|source| match source {
+                Source::Register { id } => Some(id),
+                Source::Value { .. } => None,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Snd { source } => write!(f, "snd {}", source),
+            Instruction::Set { target, source } => write!(f, "set {} {}", target, source),
+            Instruction::Add { target, source } => write!(f, "add {} {}", target, source),
+            Instruction::Sub { target, source } => write!(f, "sub {} {}", target, source),
+            Instruction::Mul { target, source } => write!(f, "mul {} {}", target, source),
+            Instruction::Mod { target, source } => write!(f, "mod {} {}", target, source),
+            Instruction::Rcv { target } => write!(f, "rcv {}", target),
+            Instruction::Jgz { condition, offset } => write!(f, "jgz {} {}", condition, offset),
+            Instruction::Jnz { condition, offset } => write!(f, "jnz {} {}", condition, offset),
+            Instruction::Jnq {
+                condition1,
+                condition2,
+                offset,
+            } => write!(f, "jnq {} {} {}", condition1, condition2, offset),
+            Instruction::MulAdd {
+                target,
+                source,
+                count,
+            } => write!(f, "mul-add {} {} {}", target, source, count),
+            Instruction::Divides {
+                target,
+                factor,
+                product,
+                counter,
+            } => write!(f, "divides {} {} {} {}", target, factor, product, counter),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let opcode: Opcode = words.next().ok_or(MissingTokens)?.parse()?;
+        let mut next = || words.next().ok_or(MissingTokens);
+        Ok(match opcode {
+            Opcode::Snd => Instruction::Snd {
+                source: next()?.parse()?,
+            },
+            Opcode::Set => Instruction::Set {
+                target: next()?.parse()?,
+                source: next()?.parse()?,
+            },
+            Opcode::Add => Instruction::Add {
+                target: next()?.parse()?,
+                source: next()?.parse()?,
+            },
+            Opcode::Sub => Instruction::Sub {
+                target: next()?.parse()?,
+                source: next()?.parse()?,
+            },
+            Opcode::Mul => Instruction::Mul {
+                target: next()?.parse()?,
+                source: next()?.parse()?,
+            },
+            Opcode::Mod => Instruction::Mod {
+                target: next()?.parse()?,
+                source: next()?.parse()?,
+            },
+            Opcode::Rcv => Instruction::Rcv {
+                target: next()?.parse()?,
+            },
+            Opcode::Jgz => Instruction::Jgz {
+                condition: next()?.parse()?,
+                offset: next()?.parse()?,
+            },
+            Opcode::Jnz => Instruction::Jnz {
+                condition: next()?.parse()?,
+                offset: next()?.parse()?,
+            },
+            Opcode::Jnq => Instruction::Jnq {
+                condition1: next()?.parse()?,
+                condition2: next()?.parse()?,
+                offset: next()?.parse()?,
+            },
+            Opcode::MulAdd => Instruction::MulAdd {
+                target: next()?.parse()?,
+                source: next()?.parse()?,
+                count: next()?.parse()?,
+            },
+            Opcode::Divides => Instruction::Divides {
+                target: next()?.parse()?,
+                factor: next()?.parse()?,
+                product: next()?.parse()?,
+                counter: next()?.parse()?,
+            },
+        })
+    }
+}
+
+pub type Program = Vec<Instruction>;
+
+/// Assemble a program, see `assembler::assemble`.
+pub fn parse_program(text: &str) -> Result<Program, Error> {
+    assembler::assemble(text)
+}
+
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Registers {
+    values: [Value; REGISTERS],
+}
+
+impl Registers {
+    pub fn new() -> Registers {
+        Registers {
+            values: [0; REGISTERS],
+        }
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers::new()
+    }
+}
+
+impl Index<RegisterId> for Registers {
+    type Output = Value;
+
+    fn index(&self, id: RegisterId) -> &Value {
+        &self.values[id.index()]
+    }
+}
+
+impl IndexMut<RegisterId> for Registers {
+    fn index_mut(&mut self, id: RegisterId) -> &mut Value {
+        &mut self.values[id.index()]
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for index in 0..REGISTERS {
+            write!(f, "{}:{},", RegisterId::from_index(index).unwrap(), self.values[index])?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// What a `rcv` instruction does.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Receive {
+    /// Store the value in the register and continue.
+    Store(Value),
+    /// Leave the register unchanged and continue.
+    Continue,
+    /// Stay on the instruction until something changes.
+    Wait,
+    /// Stop the machine after the instruction.
+    Halt,
+}
+
+/// The semantics of `snd` and `rcv`.
+pub trait Dialect {
+    /// Handle `snd` with the value of its operand.
+    fn send(&mut self, value: Value) -> Result<(), Error>;
+
+    /// Handle `rcv`, given the current value of its register.
+    fn receive(&mut self, current: Value) -> Result<Receive, Error>;
+}
+
+/// Day 18, part 1: `snd` plays a sound, and `rcv` recovers the last sound played
+/// if its register is not zero, stopping the machine.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Sound {
+    pub last_sound: Option<Value>,
+    pub recovered: Option<Value>,
+}
+
+impl Dialect for Sound {
+    fn send(&mut self, value: Value) -> Result<(), Error> {
+        self.last_sound = Some(value);
+        Ok(())
+    }
+
+    fn receive(&mut self, current: Value) -> Result<Receive, Error> {
+        if current == 0 {
+            return Ok(Receive::Continue);
+        }
+        match self.last_sound {
+            Some(sound) => {
+                self.recovered = Some(sound);
+                Ok(Receive::Halt)
+            }
+            None => bail!("Trying to recover last played sound, but no sound played"),
+        }
+    }
+}
+
+/// Day 18, part 2: `snd` sends a value to the other program, and `rcv` waits for
+/// a value from it.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Duet {
+    pub inbox: VecDeque<Value>,
+    pub outbox: VecDeque<Value>,
+    pub sent: usize,
+}
+
+impl Dialect for Duet {
+    fn send(&mut self, value: Value) -> Result<(), Error> {
+        self.outbox.push_back(value);
+        self.sent += 1;
+        Ok(())
+    }
+
+    fn receive(&mut self, _current: Value) -> Result<Receive, Error> {
+        Ok(match self.inbox.pop_front() {
+            Some(value) => Receive::Store(value),
+            None => Receive::Wait,
+        })
+    }
+}
+
+/// Day 23: the coprocessor has no `snd` or `rcv`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Coprocessor;
+
+impl Dialect for Coprocessor {
+    fn send(&mut self, _value: Value) -> Result<(), Error> {
+        bail!("The coprocessor does not support snd")
+    }
+
+    fn receive(&mut self, _current: Value) -> Result<Receive, Error> {
+        bail!("The coprocessor does not support rcv")
+    }
+}
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Status {
+    /// The last instruction was executed.
+    Running,
+    /// The machine is waiting in a `rcv`.
+    Waiting,
+    /// The machine has jumped outside the program or been stopped by `rcv`.
+    Halted,
+}
+
+#[derive(Debug, Clone)]
+pub struct Machine<D: Dialect> {
+    pub program: Program,
+    pub registers: Registers,
+    pub pc: Value,
+    pub dialect: D,
+    /// The number of executed instructions of each opcode.
+    pub counts: [u64; Opcode::COUNT],
+    /// Where executed instructions are recorded, if anywhere.
+    pub profile: Option<Profile>,
+    halted: bool,
+}
+
+impl<D: Dialect> Machine<D> {
+    pub fn new(program: Program, dialect: D) -> Machine<D> {
+        Machine {
+            program,
+            registers: Registers::new(),
+            pc: 0,
+            dialect,
+            counts: [0; Opcode::COUNT],
+            profile: None,
+            halted: false,
+        }
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted || self.pc < 0 || self.pc >= self.program.len() as Value
+    }
+
+    /// The total number of executed instructions.
+    pub fn steps(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn count(&self, opcode: Opcode) -> u64 {
+        self.counts[opcode.index()]
+    }
+
+    /// Set target to operation applied to target and source, failing on overflow.
+    fn apply(
+        &mut self,
+        target: RegisterId,
+        source: Source,
+        operation: fn(Value, Value) -> Option<Value>,
+    ) -> Result<(), Error> {
+        let value = source.value(&self.registers);
+        match operation(self.registers[target], value) {
+            Some(result) => self.registers[target] = result,
+            None => bail!(
+                "Arithmetic overflow in \"{}\" at {}",
+                self.program[self.pc as usize],
+                self.pc
+            ),
+        }
+        Ok(())
+    }
+
+    /// The value, if it fits in a register.
+    fn fits(&self, value: i128) -> Result<Value, Error> {
+        if i128::from(Value::min_value()) <= value && value <= i128::from(Value::max_value()) {
+            Ok(value as Value)
+        } else {
+            bail!(
+                "Arithmetic overflow in \"{}\" at {}",
+                self.program[self.pc as usize],
+                self.pc
+            )
+        }
+    }
+
+    /// Execute one instruction, if possible.
+    pub fn step(&mut self) -> Result<Status, Error> {
+        if self.halted() {
+            return Ok(Status::Halted);
+        }
+        let pc = self.pc;
+        let instruction = self.program[pc as usize];
+        let mut next = pc + 1;
+        let mut status = Status::Running;
+        // Whether the conditional write of rcv or divides happened.
+        let mut wrote = true;
+        match instruction {
+            Instruction::Snd { source } => self.dialect.send(source.value(&self.registers))?,
+            Instruction::Set { target, source } => self.registers[target] = source.value(&self.registers),
+            Instruction::Add { target, source } => self.apply(target, source, Value::checked_add)?,
+            Instruction::Sub { target, source } => self.apply(target, source, Value::checked_sub)?,
+            Instruction::Mul { target, source } => self.apply(target, source, Value::checked_mul)?,
+            Instruction::Mod { target, source } => {
+                ensure!(
+                    source.value(&self.registers) != 0,
+                    "Modulo by zero in \"{}\" at {}",
+                    instruction,
+                    pc
+                );
+                self.apply(target, source, Value::checked_rem)?
+            }
+            Instruction::Rcv { target } => match self.dialect.receive(self.registers[target])? {
+                Receive::Store(value) => self.registers[target] = value,
+                Receive::Continue => wrote = false,
+                Receive::Wait => {
+                    next = pc;
+                    status = Status::Waiting;
+                }
+                Receive::Halt => {
+                    wrote = false;
+                    self.halted = true;
+                    status = Status::Halted;
+                }
+            },
+            Instruction::Jgz { condition, offset } => if condition.value(&self.registers) > 0 {
+                next = jump(pc, offset.value(&self.registers));
+            },
+            Instruction::Jnz { condition, offset } => if condition.value(&self.registers) != 0 {
+                next = jump(pc, offset.value(&self.registers));
+            },
+            Instruction::Jnq {
+                condition1,
+                condition2,
+                offset,
+            } => if condition1.value(&self.registers) != condition2.value(&self.registers) {
+                next = jump(pc, offset.value(&self.registers));
+            },
+            Instruction::MulAdd {
+                target,
+                source,
+                count,
+            } => {
+                let times = self.registers[count];
+                ensure!(
+                    times > 0,
+                    "The loop of \"{}\" at {} does not terminate",
+                    instruction,
+                    pc
+                );
+                let result = i128::from(self.registers[target])
+                    + i128::from(source.value(&self.registers)) * i128::from(times);
+                self.registers[target] = self.fits(result)?;
+                self.registers[count] = 0;
+            }
+            Instruction::Divides {
+                target,
+                factor,
+                product,
+                counter,
+            } => {
+                let factor = i128::from(factor.value(&self.registers));
+                let product = i128::from(product.value(&self.registers));
+                let first = i128::from(self.registers[counter]);
+                ensure!(
+                    first < product,
+                    "The loop of \"{}\" at {} does not terminate",
+                    instruction,
+                    pc
+                );
+                // The products tried lie between those of the first and last values.
+                self.fits(factor * first)?;
+                self.fits(factor * (product - 1))?;
+                let found = if factor == 0 {
+                    product == 0
+                } else {
+                    product % factor == 0 && first <= product / factor && product / factor < product
+                };
+                if found {
+                    self.registers[target] = 0;
+                } else {
+                    wrote = false;
+                }
+                self.registers[counter] = product as Value;
+            }
+        }
+        if status != Status::Waiting {
+            self.counts[instruction.opcode().index()] += 1;
+            if let Some(ref mut profile) = self.profile {
+                profile.record(pc as usize, &instruction, wrote, &self.registers);
+            }
+        }
+        self.pc = next;
+        Ok(status)
+    }
+
+    /// Run until the machine waits or halts.
+    pub fn run(&mut self) -> Result<Status, Error> {
+        loop {
+            let status = self.step()?;
+            if status != Status::Running {
+                return Ok(status);
+            }
+        }
+    }
+}
+
+/// The target of a jump by offset from pc. Jumps past the largest pc leave the
+/// program, so that the machine halts instead of overflowing.
+fn jump(pc: Value, offset: Value) -> Value {
+    pc.checked_add(offset).unwrap_or(Value::max_value())
+}
+
+/// Run two copies of program against each other, with register p set to the
+/// program id, until both are waiting or halted.
+///
+/// The programs take turns executing one instruction each, and values sent are
+/// delivered after each round.
+pub fn duet(program: &[Instruction]) -> Result<[Machine<Duet>; 2], Error> {
+    let id_p = RegisterId::from_name('p')?;
+    let mut machines = [
+        Machine::new(program.to_vec(), Duet::default()),
+        Machine::new(program.to_vec(), Duet::default()),
+    ];
+    for (id, machine) in machines.iter_mut().enumerate() {
+        machine.registers[id_p] = id as Value;
+    }
+    loop {
+        let first = machines[0].step()?;
+        let second = machines[1].step()?;
+        let mut delivered = false;
+        for from in 0..2 {
+            while let Some(value) = machines[from].dialect.outbox.pop_front() {
+                machines[1 - from].dialect.inbox.push_back(value);
+                delivered = true;
+            }
+        }
+        if first != Status::Running && second != Status::Running && !delivered {
+            return Ok(machines);
+        }
+    }
+}
@@ -0,0 +1,869 @@
+//! A peephole and idiom optimiser for VM programs.
+//!
+//! The optimiser applies its passes until the program no longer changes:
+//!
+//!  * constant folding, of arithmetic on registers with values known within a
+//!    basic block, and of jumps with constant conditions,
+//!  * strength reduction, removing instructions without effect and replacing
+//!    others with cheaper ones,
+//!  * idiom recognition, replacing loops that add a value a number of times by
+//!    `mul-add`, also where such a loop is itself repeated a number of times,
+//!    and loops that look for a factor by `divides`, and
+//!  * dead-store elimination, removing `set`s of registers that are written
+//!    before they are read.
+//!
+//! Every change a pass makes is checked by differential execution, running the
+//! program before and after the change on a set of inputs and comparing how
+//! they end.
+//!
+//! Programs with jumps to offsets in registers are only changed in place, as
+//! moving instructions would change where those jumps go, and constants are
+//! not folded across instructions, as any of them can be jumped to.
+
+use failure::Error;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use vm::{Dialect, Instruction, Machine, Program, Receive, RegisterId, Source, Status, Value, REGISTERS};
+use vm::cfg::{branch, Branch, Cfg};
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Pass {
+    ConstantFolding,
+    StrengthReduction,
+    Idioms,
+    DeadStores,
+}
+
+impl Pass {
+    pub fn all() -> [Pass; 4] {
+        [
+            Pass::ConstantFolding,
+            Pass::StrengthReduction,
+            Pass::Idioms,
+            Pass::DeadStores,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Pass::ConstantFolding => "constant folding",
+            Pass::StrengthReduction => "strength reduction",
+            Pass::Idioms => "idiom",
+            Pass::DeadStores => "dead-store elimination",
+        }
+    }
+
+    pub fn apply(&self, program: &[Instruction]) -> Program {
+        match *self {
+            Pass::ConstantFolding => fold_constants(program),
+            Pass::StrengthReduction => reduce_strength(program),
+            Pass::Idioms => replace_idioms(program),
+            Pass::DeadStores => remove_dead_stores(program),
+        }
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The program with each instruction replaced by a sequence of instructions.
+///
+/// Constant jump offsets in a replacement are relative to the instruction it
+/// replaces, and are adjusted to the new positions. Jumps to removed
+/// instructions go to the next instruction kept.
+fn rewrite(program: &[Instruction], replacements: Vec<Vec<Instruction>>) -> Program {
+    let length = program.len();
+    if replacements.iter().any(|replacement| replacement.len() != 1) && Cfg::new(program).indirect {
+        return program.to_vec();
+    }
+    let mut position = Vec::with_capacity(length + 1);
+    let mut next = 0;
+    for replacement in &replacements {
+        position.push(next as Value);
+        next += replacement.len();
+    }
+    position.push(next as Value);
+    let relocate = |target: Value| -> Value {
+        if target < 0 {
+            target
+        } else if target >= length as Value {
+            position[length].saturating_add(target - length as Value)
+        } else {
+            position[target as usize]
+        }
+    };
+
+    let mut result = Vec::with_capacity(next);
+    for (pc, replacement) in replacements.into_iter().enumerate() {
+        for instruction in replacement {
+            let new_pc = result.len() as Value;
+            result.push(match instruction.offset() {
+                Some(Source::Value { value }) => instruction.with_offset(Source::Value {
+                    value: relocate((pc as Value).saturating_add(value)).saturating_sub(new_pc),
+                }),
+                _ => instruction,
+            });
+        }
+    }
+    result
+}
+
+fn bit(id: RegisterId) -> u32 {
+    1 << id.index()
+}
+
+fn source_bits(sources: &[Source]) -> u32 {
+    sources.iter().fold(0, |bits, source| match *source {
+        Source::Register { id } => bits | bit(id),
+        Source::Value { .. } => bits,
+    })
+}
+
+/// The registers an instruction reads, and those it may write, as bit sets.
+fn uses_and_defs(instruction: &Instruction) -> (u32, u32) {
+    match *instruction {
+        Instruction::Snd { source } => (source_bits(&[source]), 0),
+        Instruction::Set { target, source } => (source_bits(&[source]), bit(target)),
+        Instruction::Add { target, source }
+        | Instruction::Sub { target, source }
+        | Instruction::Mul { target, source }
+        | Instruction::Mod { target, source } => (bit(target) | source_bits(&[source]), bit(target)),
+        // The program may stop at a rcv, when all registers are observed.
+        Instruction::Rcv { target } => ((1 << REGISTERS) - 1, bit(target)),
+        Instruction::Jgz { condition, offset } | Instruction::Jnz { condition, offset } => {
+            (source_bits(&[condition, offset]), 0)
+        }
+        Instruction::Jnq {
+            condition1,
+            condition2,
+            offset,
+        } => (source_bits(&[condition1, condition2, offset]), 0),
+        Instruction::MulAdd {
+            target,
+            source,
+            count,
+        } => (
+            bit(target) | bit(count) | source_bits(&[source]),
+            bit(target) | bit(count),
+        ),
+        Instruction::Divides {
+            target,
+            factor,
+            product,
+            counter,
+        } => (
+            bit(target) | bit(counter) | source_bits(&[factor, product]),
+            bit(target) | bit(counter),
+        ),
+    }
+}
+
+/// The instruction with registers of known values replaced by the values.
+fn substitute(instruction: &Instruction, known: &[Option<Value>; REGISTERS]) -> Instruction {
+    let value = |source: Source| match source {
+        Source::Register { id } => match known[id.index()] {
+            Some(value) => Source::Value { value },
+            None => source,
+        },
+        source => source,
+    };
+    match *instruction {
+        Instruction::Snd { source } => Instruction::Snd {
+            source: value(source),
+        },
+        Instruction::Set { target, source } => Instruction::Set {
+            target,
+            source: value(source),
+        },
+        Instruction::Add { target, source } => Instruction::Add {
+            target,
+            source: value(source),
+        },
+        Instruction::Sub { target, source } => Instruction::Sub {
+            target,
+            source: value(source),
+        },
+        Instruction::Mul { target, source } => Instruction::Mul {
+            target,
+            source: value(source),
+        },
+        Instruction::Mod { target, source } => Instruction::Mod {
+            target,
+            source: value(source),
+        },
+        Instruction::Jgz { condition, offset } => Instruction::Jgz {
+            condition: value(condition),
+            offset: value(offset),
+        },
+        Instruction::Jnz { condition, offset } => Instruction::Jnz {
+            condition: value(condition),
+            offset: value(offset),
+        },
+        Instruction::Jnq {
+            condition1,
+            condition2,
+            offset,
+        } => Instruction::Jnq {
+            condition1: value(condition1),
+            condition2: value(condition2),
+            offset: value(offset),
+        },
+        Instruction::MulAdd {
+            target,
+            source,
+            count,
+        } => Instruction::MulAdd {
+            target,
+            source: value(source),
+            count,
+        },
+        Instruction::Divides {
+            target,
+            factor,
+            product,
+            counter,
+        } => Instruction::Divides {
+            target,
+            factor: value(factor),
+            product: value(product),
+            counter,
+        },
+        instruction => instruction,
+    }
+}
+
+fn fold_constants(program: &[Instruction]) -> Program {
+    let cfg = Cfg::new(program);
+    let mut known = [None; REGISTERS];
+    let mut replacements = Vec::with_capacity(program.len());
+    for pc in 0..program.len() {
+        // A jump with its offset in a register can land on any instruction.
+        if cfg.indirect || cfg.blocks[cfg.block_of[pc]].start == pc {
+            known = [None; REGISTERS];
+        }
+        let instruction = substitute(&program[pc], &known);
+        let arithmetic: Option<(RegisterId, Value, fn(Value, Value) -> Option<Value>)> = match instruction {
+            Instruction::Add {
+                target,
+                source: Source::Value { value },
+            } => Some((target, value, Value::checked_add)),
+            Instruction::Sub {
+                target,
+                source: Source::Value { value },
+            } => Some((target, value, Value::checked_sub)),
+            Instruction::Mul {
+                target,
+                source: Source::Value { value },
+            } => Some((target, value, Value::checked_mul)),
+            Instruction::Mod {
+                target,
+                source: Source::Value { value },
+            } => Some((target, value, Value::checked_rem)),
+            _ => None,
+        };
+        let folded = match arithmetic {
+            Some((target, value, operation)) => known[target.index()]
+                .and_then(|current| operation(current, value))
+                .map(|result| Instruction::Set {
+                    target,
+                    source: Source::Value { value: result },
+                }),
+            None => None,
+        };
+        let instruction = folded.unwrap_or(instruction);
+        let (_, defs) = uses_and_defs(&instruction);
+        for index in 0..REGISTERS {
+            if defs & (1 << index) != 0 {
+                known[index] = None;
+            }
+        }
+        if let Instruction::Set {
+            target,
+            source: Source::Value { value },
+        } = instruction
+        {
+            known[target.index()] = Some(value);
+        }
+        replacements.push(match (branch(&instruction), instruction.offset()) {
+            (Branch::Never, Some(_)) => vec![],
+            (Branch::Always, Some(offset)) => vec![
+                Instruction::Jnz {
+                    condition: Source::Value { value: 1 },
+                    offset,
+                },
+            ],
+            _ => vec![instruction],
+        });
+    }
+    rewrite(program, replacements)
+}
+
+fn reduce_strength(program: &[Instruction]) -> Program {
+    let replacements = program
+        .iter()
+        .map(|&instruction| match instruction {
+            Instruction::Sub {
+                target,
+                source: Source::Value { value },
+            } if value.checked_neg().is_some() => vec![
+                Instruction::Add {
+                    target,
+                    source: Source::Value { value: -value },
+                },
+            ],
+            Instruction::Add {
+                source: Source::Value { value: 0 },
+                ..
+            }
+            | Instruction::Mul {
+                source: Source::Value { value: 1 },
+                ..
+            } => vec![],
+            Instruction::Set {
+                target,
+                source: Source::Register { id },
+            } if target == id =>
+            {
+                vec![]
+            }
+            Instruction::Mul {
+                target,
+                source: Source::Value { value: 0 },
+            }
+            | Instruction::Mod {
+                target,
+                source: Source::Value { value: 1 },
+            } => vec![
+                Instruction::Set {
+                    target,
+                    source: Source::Value { value: 0 },
+                },
+            ],
+            Instruction::Mul {
+                target,
+                source: Source::Value { value: 2 },
+            } => vec![
+                Instruction::Add {
+                    target,
+                    source: Source::Register { id: target },
+                },
+            ],
+            // Both ways lead to the next instruction.
+            Instruction::Jgz {
+                offset: Source::Value { value: 1 },
+                ..
+            }
+            | Instruction::Jnz {
+                offset: Source::Value { value: 1 },
+                ..
+            }
+            | Instruction::Jnq {
+                offset: Source::Value { value: 1 },
+                ..
+            } => vec![],
+            instruction => if instruction.offset().is_some() && branch(&instruction) == Branch::Never {
+                vec![]
+            } else {
+                vec![instruction]
+            },
+        })
+        .collect();
+    rewrite(program, replacements)
+}
+
+/// The register an instruction adds to and what it adds.
+fn increment(instruction: &Instruction) -> Option<(RegisterId, Source)> {
+    match *instruction {
+        Instruction::Add { target, source } => Some((target, source)),
+        Instruction::Sub {
+            target,
+            source: Source::Value { value },
+        } => value
+            .checked_neg()
+            .map(|value| (target, Source::Value { value })),
+        _ => None,
+    }
+}
+
+fn is_step(instruction: &Instruction, register: RegisterId, amount: Value) -> bool {
+    increment(instruction) == Some((register, Source::Value { value: amount }))
+}
+
+/// Whether an instruction subtracts source from register.
+fn is_subtraction(instruction: &Instruction, register: RegisterId, source: Source) -> bool {
+    match (*instruction, source) {
+        (Instruction::Sub { target, source: subtracted }, _) => target == register && subtracted == source,
+        (_, Source::Value { value }) => value
+            .checked_neg()
+            .map_or(false, |negated| is_step(instruction, register, negated)),
+        _ => false,
+    }
+}
+
+fn is_register(source: Source, register: RegisterId) -> bool {
+    source == Source::Register { id: register }
+}
+
+/// A loop adding to a register while counting another down to zero:
+///
+/// ```text
+/// loop: add t x
+///       add y -1
+///       jnz y 'loop'
+/// ```
+fn mul_add(code: &[Instruction]) -> Option<Vec<Instruction>> {
+    if code.len() < 3 {
+        return None;
+    }
+    let count = match code[2] {
+        Instruction::Jnz {
+            condition: Source::Register { id },
+            offset: Source::Value { value: -2 },
+        } => id,
+        _ => return None,
+    };
+    let add = if is_step(&code[1], count, -1) {
+        code[0]
+    } else if is_step(&code[0], count, -1) {
+        code[1]
+    } else {
+        return None;
+    };
+    match increment(&add) {
+        Some((target, source))
+            if target != count && !is_register(source, target) && !is_register(source, count) =>
+        {
+            Some(vec![
+                Instruction::MulAdd {
+                    target,
+                    source,
+                    count,
+                },
+            ])
+        }
+        _ => None,
+    }
+}
+
+/// A loop repeating a `mul-add`, once its own loop has been replaced:
+///
+/// ```text
+/// outer: set c x           set c 0
+///        mul-add t s c     mul-add c x d
+///        add d -1          mul-add t s c
+///        jnz d 'outer'
+/// ```
+///
+/// The second `mul-add` fails where x is not positive, as the first one of the
+/// loop does.
+fn nested_mul_add(code: &[Instruction]) -> Option<Vec<Instruction>> {
+    if code.len() < 4 {
+        return None;
+    }
+    let (inner, times) = match code[0] {
+        Instruction::Set { target, source } => (target, source),
+        _ => return None,
+    };
+    let (target, source) = match code[1] {
+        Instruction::MulAdd { target, source, count } if count == inner => (target, source),
+        _ => return None,
+    };
+    let count = match code[3] {
+        Instruction::Jnz {
+            condition: Source::Register { id },
+            offset: Source::Value { value: -3 },
+        } => id,
+        _ => return None,
+    };
+    let read = [times, source];
+    let distinct = !read.iter().any(|&value| {
+        is_register(value, target) || is_register(value, inner) || is_register(value, count)
+    }) && target != inner && target != count && inner != count;
+    if !is_step(&code[2], count, -1) || !distinct {
+        return None;
+    }
+    Some(vec![
+        Instruction::Set {
+            target: inner,
+            source: Source::Value { value: 0 },
+        },
+        Instruction::MulAdd {
+            target: inner,
+            source: times,
+            count,
+        },
+        Instruction::MulAdd {
+            target,
+            source,
+            count: inner,
+        },
+    ])
+}
+
+/// A loop looking for a factor, as in day 23:
+///
+/// ```text
+/// loop: set g d            loop: set g d
+///       mul g e                  mul g e
+///       sub g b                  jnq g b 2
+///       jnz g 2                  set f 0
+///       set f 0                  add e 1
+///       add e 1                  jnq e b 'loop'
+///       set g e
+///       sub g b
+///       jnz g 'loop'
+/// ```
+fn divides(code: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    if code.len() < 6 {
+        return None;
+    }
+    let (g, factor) = match code[0] {
+        Instruction::Set { target, source } => (target, source),
+        _ => return None,
+    };
+    let counter = match code[1] {
+        Instruction::Mul {
+            target,
+            source: Source::Register { id },
+        } if target == g =>
+        {
+            id
+        }
+        _ => return None,
+    };
+    let (length, product) = match code[2] {
+        Instruction::Jnq {
+            condition1,
+            condition2,
+            offset: Source::Value { value: 2 },
+        } if is_register(condition1, g) =>
+        {
+            (6, condition2)
+        }
+        Instruction::Sub { target, source } if target == g && code.len() >= 9 => (9, source),
+        Instruction::Add {
+            target,
+            source: Source::Value { value },
+        } if target == g && code.len() >= 9 && value.checked_neg().is_some() =>
+        {
+            (9, Source::Value { value: -value })
+        }
+        _ => return None,
+    };
+    // The long form tests g again before setting f.
+    let long = length == 9;
+    if long && code[3] != (Instruction::Jnz {
+        condition: Source::Register { id: g },
+        offset: Source::Value { value: 2 },
+    }) {
+        return None;
+    }
+    let skip = if long { 1 } else { 0 };
+    let found = match code[3 + skip] {
+        Instruction::Set {
+            target,
+            source: Source::Value { value: 0 },
+        } => target,
+        _ => return None,
+    };
+    if !is_step(&code[4 + skip], counter, 1) {
+        return None;
+    }
+    let back = -(length as Value - 1);
+    let looped = if long {
+        code[6] == Instruction::Set {
+            target: g,
+            source: Source::Register { id: counter },
+        } && is_subtraction(&code[7], g, product) && code[8] == Instruction::Jnz {
+            condition: Source::Register { id: g },
+            offset: Source::Value { value: back },
+        }
+    } else {
+        code[5] == Instruction::Jnq {
+            condition1: Source::Register { id: counter },
+            condition2: product,
+            offset: Source::Value { value: back },
+        }
+    };
+    let read = [factor, product];
+    let distinct = !read.iter().any(|&source| {
+        is_register(source, g) || is_register(source, found) || is_register(source, counter)
+    }) && g != found && g != counter && found != counter;
+    if !looped || !distinct {
+        return None;
+    }
+
+    let mut replacement = vec![
+        Instruction::Divides {
+            target: found,
+            factor,
+            product,
+            counter,
+        },
+    ];
+    if long {
+        replacement.push(Instruction::Set {
+            target: g,
+            source: Source::Value { value: 0 },
+        });
+    } else {
+        // The last product tried is left in g.
+        replacement.push(Instruction::Set {
+            target: g,
+            source: Source::Register { id: counter },
+        });
+        replacement.push(Instruction::Add {
+            target: g,
+            source: Source::Value { value: -1 },
+        });
+        replacement.push(Instruction::Mul {
+            target: g,
+            source: factor,
+        });
+    }
+    Some((length, replacement))
+}
+
+/// The jumps with constant offsets, as pairs of where they are and where they
+/// go.
+fn jumps(program: &[Instruction]) -> Vec<(usize, Value)> {
+    program
+        .iter()
+        .enumerate()
+        .filter_map(|(pc, instruction)| match instruction.offset() {
+            Some(Source::Value { value }) => Some((pc, (pc as Value).saturating_add(value))),
+            _ => None,
+        })
+        .collect()
+}
+
+fn replace_idioms(program: &[Instruction]) -> Program {
+    let jumps = jumps(program);
+    let mut replacements: Vec<Vec<Instruction>> = program.iter().map(|&instruction| vec![instruction]).collect();
+    let mut pc = 0;
+    while pc < program.len() {
+        let code = &program[pc..];
+        let found = mul_add(code)
+            .map(|replacement| (3, replacement))
+            .or_else(|| nested_mul_add(code).map(|replacement| (4, replacement)))
+            .or_else(|| divides(code));
+        if let Some((length, replacement)) = found {
+            // Only the head of the loop may be jumped to from outside it.
+            let inside = |at: Value| pc as Value <= at && at < (pc + length) as Value;
+            if !jumps
+                .iter()
+                .any(|&(from, to)| !inside(from as Value) && inside(to) && to != pc as Value)
+            {
+                replacements[pc] = replacement;
+                for inner in pc + 1..pc + length {
+                    replacements[inner] = vec![];
+                }
+                pc += length;
+                continue;
+            }
+        }
+        pc += 1;
+    }
+    rewrite(program, replacements)
+}
+
+fn remove_dead_stores(program: &[Instruction]) -> Program {
+    let cfg = Cfg::new(program);
+    if cfg.indirect {
+        return program.to_vec();
+    }
+    let all = (1 << REGISTERS) - 1;
+    let live_out = |live_in: &[u32], block: usize| {
+        cfg.successors(block)
+            .iter()
+            .fold(0, |live, &successor| live | live_in[successor])
+    };
+    let mut live_in = vec![0u32; cfg.blocks.len()];
+    live_in[cfg.exit] = all;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in (0..cfg.exit).rev() {
+            let mut live = live_out(&live_in, block);
+            for pc in (cfg.blocks[block].start..cfg.blocks[block].end).rev() {
+                let (uses, defs) = uses_and_defs(&program[pc]);
+                live = (live & !defs) | uses;
+            }
+            if live != live_in[block] {
+                live_in[block] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut replacements: Vec<Vec<Instruction>> = program.iter().map(|&instruction| vec![instruction]).collect();
+    for block in 0..cfg.exit {
+        let mut live = live_out(&live_in, block);
+        for pc in (cfg.blocks[block].start..cfg.blocks[block].end).rev() {
+            if let Instruction::Set { target, .. } = program[pc] {
+                if live & bit(target) == 0 {
+                    replacements[pc] = vec![];
+                    continue;
+                }
+            }
+            let (uses, defs) = uses_and_defs(&program[pc]);
+            live = (live & !defs) | uses;
+        }
+    }
+    rewrite(program, replacements)
+}
+
+/// A dialect for differential execution, which records the values sent and
+/// receives the given inputs, halting when they run out.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Recorder {
+    pub sent: Vec<Value>,
+    pub inputs: VecDeque<Value>,
+}
+
+impl Dialect for Recorder {
+    fn send(&mut self, value: Value) -> Result<(), Error> {
+        self.sent.push(value);
+        Ok(())
+    }
+
+    fn receive(&mut self, _current: Value) -> Result<Receive, Error> {
+        Ok(match self.inputs.pop_front() {
+            Some(value) => Receive::Store(value),
+            None => Receive::Halt,
+        })
+    }
+}
+
+/// The initial registers and the inputs for one run of a program.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Run {
+    pub registers: Vec<(RegisterId, Value)>,
+    pub inputs: Vec<Value>,
+}
+
+/// Parses the initial registers of a run without inputs, written as `a=1,c=-4`.
+impl FromStr for Run {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut registers = Vec::new();
+        for assignment in s.split(',') {
+            let parts: Vec<&str> = assignment.split('=').collect();
+            ensure!(
+                parts.len() == 2,
+                "Expected registers with values like a=1,c=-4, found \"{}\"",
+                s
+            );
+            registers.push((parts[0].trim().parse()?, parts[1].trim().parse()?));
+        }
+        Ok(Run {
+            registers,
+            inputs: vec![],
+        })
+    }
+}
+
+/// How a run of a program ended.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Outcome {
+    Halted { registers: Vec<Value>, sent: Vec<Value> },
+    Failed,
+    OutOfSteps,
+}
+
+/// Run a program for at most limit steps.
+pub fn execute(program: &[Instruction], run: &Run, limit: u64) -> Outcome {
+    let recorder = Recorder {
+        sent: Vec::new(),
+        inputs: run.inputs.iter().cloned().collect(),
+    };
+    let mut machine = Machine::new(program.to_vec(), recorder);
+    for &(id, value) in &run.registers {
+        machine.registers[id] = value;
+    }
+    for _ in 0..limit {
+        match machine.step() {
+            Ok(Status::Running) => {}
+            Ok(_) => {
+                return Outcome::Halted {
+                    registers: (0..REGISTERS)
+                        .map(|index| machine.registers[RegisterId::from_index(index).unwrap()])
+                        .collect(),
+                    sent: machine.dialect.sent,
+                }
+            }
+            Err(_) => return Outcome::Failed,
+        }
+    }
+    Outcome::OutOfSteps
+}
+
+/// Check that two programs end the same way on the runs. The first program
+/// must halt or fail within limit steps on each run.
+pub fn differential(original: &[Instruction], changed: &[Instruction], runs: &[Run], limit: u64) -> Result<(), Error> {
+    for (index, run) in runs.iter().enumerate() {
+        let expected = execute(original, run, limit);
+        ensure!(
+            expected != Outcome::OutOfSteps,
+            "Run {} does not end within {} steps",
+            index + 1,
+            limit
+        );
+        let actual = execute(changed, run, limit);
+        ensure!(
+            actual == expected,
+            "Run {} ends with {:?} instead of {:?}",
+            index + 1,
+            actual,
+            expected
+        );
+    }
+    Ok(())
+}
+
+/// An optimiser, with the passes to apply and the runs to check them on.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Optimizer {
+    pub passes: Vec<Pass>,
+    pub runs: Vec<Run>,
+    pub limit: u64,
+}
+
+impl Optimizer {
+    pub fn new(runs: Vec<Run>) -> Optimizer {
+        Optimizer {
+            passes: Pass::all().to_vec(),
+            runs,
+            limit: 10_000_000,
+        }
+    }
+
+    /// Apply the passes until the program no longer changes, checking each
+    /// change against the program before it, and the result against the
+    /// original program.
+    pub fn optimize(&self, program: &[Instruction]) -> Result<Program, Error> {
+        let mut current = program.to_vec();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for pass in &self.passes {
+                let next = pass.apply(&current);
+                if next != current {
+                    differential(&current, &next, &self.runs, self.limit)
+                        .map_err(|cause| format_err!("Checking the {} pass failed: {}", pass, cause))?;
+                    current = next;
+                    changed = true;
+                }
+            }
+        }
+        differential(program, &current, &self.runs, self.limit)?;
+        Ok(current)
+    }
+}
@@ -0,0 +1,248 @@
+//! Execution profiles of VM programs.
+//!
+//! A `Profile` set on a `Machine` records, for every executed instruction, the
+//! hits of its pc and opcode and the registers it wrote, and keeps the last
+//! instructions in a bounded trace. Reports are text, with the program listed
+//! next to the hits so that hot loops stand out, or JSON.
+
+use failure::Error;
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use vm::{Instruction, Opcode, RegisterId, Registers, Value, REGISTERS};
+
+/// The format of a report.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => bail!("Unknown report format {}, expected text or json", s),
+        }
+    }
+}
+
+/// An executed instruction, with the values of the registers it wrote.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Trace {
+    /// The number of instructions executed before it.
+    pub step: u64,
+    pub pc: usize,
+    pub instruction: Instruction,
+    pub writes: Vec<(RegisterId, Value)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// The number of executions of each pc.
+    pub hits: Vec<u64>,
+    /// The number of executions of each opcode.
+    pub counts: [u64; Opcode::COUNT],
+    /// The number of writes to each register.
+    pub writes: [u64; REGISTERS],
+    /// The last executed instructions, oldest first.
+    pub trace: VecDeque<Trace>,
+    /// The length of the trace.
+    length: usize,
+    steps: u64,
+}
+
+/// The registers that an instruction writes, given whether the conditional
+/// write of `rcv` or `divides` happened.
+fn writes(instruction: &Instruction, wrote: bool) -> Vec<RegisterId> {
+    match *instruction {
+        Instruction::Set { target, .. }
+        | Instruction::Add { target, .. }
+        | Instruction::Sub { target, .. }
+        | Instruction::Mul { target, .. }
+        | Instruction::Mod { target, .. } => vec![target],
+        Instruction::Rcv { target } if wrote => vec![target],
+        Instruction::MulAdd { target, count, .. } => vec![target, count],
+        Instruction::Divides {
+            target, counter, ..
+        } => if wrote {
+            vec![target, counter]
+        } else {
+            vec![counter]
+        },
+        _ => vec![],
+    }
+}
+
+/// A string as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut result = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            ch if (ch as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+impl Profile {
+    /// A profile that traces the last length instructions.
+    pub fn new(length: usize) -> Profile {
+        Profile {
+            length,
+            ..Profile::default()
+        }
+    }
+
+    /// The number of recorded instructions.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Record an executed instruction, given whether its conditional write
+    /// happened and the registers after it.
+    pub fn record(&mut self, pc: usize, instruction: &Instruction, wrote: bool, registers: &Registers) {
+        if pc >= self.hits.len() {
+            self.hits.resize(pc + 1, 0);
+        }
+        self.hits[pc] += 1;
+        self.counts[instruction.opcode().index()] += 1;
+        let written = writes(instruction, wrote);
+        for &id in &written {
+            self.writes[id.index()] += 1;
+        }
+        if self.length > 0 {
+            if self.trace.len() == self.length {
+                self.trace.pop_front();
+            }
+            self.trace.push_back(Trace {
+                step: self.steps,
+                pc,
+                instruction: *instruction,
+                writes: written.iter().map(|&id| (id, registers[id])).collect(),
+            });
+        }
+        self.steps += 1;
+    }
+
+    /// The report of the profile of program.
+    pub fn report(&self, program: &[Instruction], format: Format) -> String {
+        match format {
+            Format::Text => self.text(program),
+            Format::Json => self.json(program),
+        }
+    }
+
+    /// The report as text: the opcode counts, the program with the hits and
+    /// share of every pc, the register writes and the trace.
+    pub fn text(&self, program: &[Instruction]) -> String {
+        let mut result = String::new();
+        let total = self.steps.max(1) as f64;
+        writeln!(result, "steps: {}", self.steps).unwrap();
+        writeln!(result, "opcodes:").unwrap();
+        for opcode in Opcode::all().iter() {
+            let count = self.counts[opcode.index()];
+            if count > 0 {
+                writeln!(result, "  {:<8}{:>12}", opcode.name(), count).unwrap();
+            }
+        }
+        writeln!(result, "program:").unwrap();
+        for (pc, instruction) in program.iter().enumerate() {
+            let hits = self.hits.get(pc).cloned().unwrap_or(0);
+            writeln!(
+                result,
+                "  {:>12} {:>5.1}% {:>4}: {}",
+                hits,
+                100.0 * hits as f64 / total,
+                pc,
+                instruction
+            ).unwrap();
+        }
+        writeln!(result, "writes:").unwrap();
+        for index in 0..REGISTERS {
+            if self.writes[index] > 0 {
+                let id = RegisterId::from_index(index).unwrap();
+                writeln!(result, "  {:<8}{:>12}", id.name(), self.writes[index]).unwrap();
+            }
+        }
+        if self.length > 0 {
+            writeln!(result, "trace:").unwrap();
+            for trace in &self.trace {
+                let writes: Vec<String> = trace
+                    .writes
+                    .iter()
+                    .map(|&(id, value)| format!("{}={}", id, value))
+                    .collect();
+                let line = format!("  {:>12} {:>4}: {}", trace.step, trace.pc, trace.instruction);
+                if writes.is_empty() {
+                    writeln!(result, "{}", line).unwrap();
+                } else {
+                    writeln!(result, "{:<40} {}", line, writes.join(" ")).unwrap();
+                }
+            }
+        }
+        result
+    }
+
+    /// The report as a JSON object, on one line.
+    pub fn json(&self, program: &[Instruction]) -> String {
+        let opcodes: Vec<String> = Opcode::all()
+            .iter()
+            .filter(|opcode| self.counts[opcode.index()] > 0)
+            .map(|opcode| format!("{}:{}", json_string(opcode.name()), self.counts[opcode.index()]))
+            .collect();
+        let pcs: Vec<String> = program
+            .iter()
+            .enumerate()
+            .map(|(pc, instruction)| {
+                format!(
+                    "{{\"pc\":{},\"instruction\":{},\"hits\":{}}}",
+                    pc,
+                    json_string(&instruction.to_string()),
+                    self.hits.get(pc).cloned().unwrap_or(0)
+                )
+            })
+            .collect();
+        let writes: Vec<String> = (0..REGISTERS)
+            .filter(|&index| self.writes[index] > 0)
+            .map(|index| {
+                let id = RegisterId::from_index(index).unwrap();
+                format!("{}:{}", json_string(&id.to_string()), self.writes[index])
+            })
+            .collect();
+        let trace: Vec<String> = self.trace
+            .iter()
+            .map(|trace| {
+                let writes: Vec<String> = trace
+                    .writes
+                    .iter()
+                    .map(|&(id, value)| format!("{}:{}", json_string(&id.to_string()), value))
+                    .collect();
+                format!(
+                    "{{\"step\":{},\"pc\":{},\"instruction\":{},\"writes\":{{{}}}}}",
+                    trace.step,
+                    trace.pc,
+                    json_string(&trace.instruction.to_string()),
+                    writes.join(",")
+                )
+            })
+            .collect();
+        format!(
+            "{{\"steps\":{},\"opcodes\":{{{}}},\"pcs\":[{}],\"writes\":{{{}}},\"trace\":[{}]}}\n",
+            self.steps,
+            opcodes.join(","),
+            pcs.join(","),
+            writes.join(","),
+            trace.join(",")
+        )
+    }
+}
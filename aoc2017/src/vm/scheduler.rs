@@ -0,0 +1,301 @@
+//! Running any number of programs that send values to each other.
+//!
+//! Every program has an inbox, from which its `rcv` takes values, and the
+//! topology decides which inboxes receive the values that a program sends.
+//! A program is blocked when it waits in a `rcv` with an empty inbox, or in a
+//! `snd` while an inbox it sends to is full. The programs run until all are
+//! blocked or halted, and if any is blocked the result is a deadlock.
+//!
+//! With unbounded inboxes the values that every program sends and receives do
+//! not depend on the order the programs are run in, so all policies end in the
+//! same state. With bounded inboxes they may deadlock in different places.
+
+use failure::Error;
+
+use std::fmt;
+use std::str::FromStr;
+
+use vm::{Duet, Instruction, Machine, RegisterId, Value};
+
+/// Where the values sent by each program go.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Topology {
+    /// To the next program, and from the last to the first.
+    Ring,
+    /// To every other program.
+    Broadcast,
+    /// Along every pair of sender and receiver.
+    Pairs(Vec<(usize, usize)>),
+}
+
+impl Topology {
+    /// The receivers of the values sent by program id out of count.
+    pub fn receivers(&self, id: usize, count: usize) -> Vec<usize> {
+        match *self {
+            Topology::Ring => vec![(id + 1) % count],
+            Topology::Broadcast => (0..count).filter(|&other| other != id).collect(),
+            Topology::Pairs(ref pairs) => pairs
+                .iter()
+                .filter(|&&(sender, _)| sender == id)
+                .map(|&(_, receiver)| receiver)
+                .collect(),
+        }
+    }
+}
+
+/// Parses `ring`, `broadcast`, or pairs written as `0-1,1-2,2-0`, where
+/// `0-1` means that program 0 sends to program 1.
+impl FromStr for Topology {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ring" => Ok(Topology::Ring),
+            "broadcast" => Ok(Topology::Broadcast),
+            _ => {
+                let mut pairs = Vec::new();
+                for pair in s.split(',') {
+                    let ids: Vec<&str> = pair.split('-').collect();
+                    ensure!(
+                        ids.len() == 2,
+                        "Expected ring, broadcast or pairs like 0-1,1-0, found \"{}\"",
+                        s
+                    );
+                    pairs.push((ids[0].trim().parse()?, ids[1].trim().parse()?));
+                }
+                Ok(Topology::Pairs(pairs))
+            }
+        }
+    }
+}
+
+/// The order in which programs are run.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Policy {
+    /// Each program in turn runs the given number of instructions, or until it
+    /// blocks.
+    RoundRobin(u64),
+    /// The program that has run the fewest instructions runs next, one
+    /// instruction at a time.
+    Fair,
+}
+
+/// Parses `fair`, `round-robin`, or `round-robin:n` for n instructions per turn.
+impl FromStr for Policy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("fair"), None) => Ok(Policy::Fair),
+            (Some("round-robin"), None) => Ok(Policy::RoundRobin(1)),
+            (Some("round-robin"), Some(turn)) => {
+                let turn = turn.parse()?;
+                ensure!(turn > 0, "A turn must be at least one instruction");
+                Ok(Policy::RoundRobin(turn))
+            }
+            _ => bail!("Expected fair, round-robin or round-robin:n, found \"{}\"", s),
+        }
+    }
+}
+
+/// Where a program stopped.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum State {
+    /// It can run.
+    Runnable,
+    /// It waits for a value in a `rcv`.
+    Receiving,
+    /// It waits in a `snd` for room in an inbox it sends to.
+    Sending,
+    Halted,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            State::Runnable => "runnable",
+            State::Receiving => "receiving",
+            State::Sending => "sending",
+            State::Halted => "halted",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A scheduler, with the topology, the policy and the size of the inboxes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Scheduler {
+    pub topology: Topology,
+    pub policy: Policy,
+    /// The number of values an inbox holds, if bounded.
+    pub capacity: Option<usize>,
+    /// The number of instructions after which the programs are stopped.
+    pub limit: u64,
+}
+
+/// Copies of program, with register p set to the id of each copy.
+pub fn copies(program: &[Instruction], count: usize) -> Result<Vec<Machine<Duet>>, Error> {
+    let id_p = RegisterId::from_name('p')?;
+    Ok((0..count)
+        .map(|id| {
+            let mut machine = Machine::new(program.to_vec(), Duet::default());
+            machine.registers[id_p] = id as Value;
+            machine
+        })
+        .collect())
+}
+
+impl Scheduler {
+    pub fn new(topology: Topology) -> Scheduler {
+        Scheduler {
+            topology,
+            policy: Policy::RoundRobin(1),
+            capacity: None,
+            limit: 1_000_000_000,
+        }
+    }
+
+    /// Run the machines until all are blocked or halted.
+    pub fn run(&self, machines: Vec<Machine<Duet>>) -> Result<Schedule, Error> {
+        ensure!(self.capacity != Some(0), "An inbox must hold at least one value");
+        let count = machines.len();
+        let receivers: Vec<Vec<usize>> = (0..count)
+            .map(|id| self.topology.receivers(id, count))
+            .collect();
+        for (id, receivers) in receivers.iter().enumerate() {
+            for &receiver in receivers {
+                ensure!(
+                    receiver < count,
+                    "Program {} sends to program {}, but there are only {} programs",
+                    id,
+                    receiver,
+                    count
+                );
+            }
+        }
+        let mut schedule = Schedule {
+            machines,
+            receivers,
+            capacity: self.capacity,
+        };
+        let mut steps = 0;
+        loop {
+            let ran = match self.policy {
+                Policy::RoundRobin(turn) => {
+                    let mut ran = 0;
+                    for id in 0..count {
+                        let mut left = turn;
+                        while left > 0 && schedule.state(id) == State::Runnable {
+                            schedule.step(id)?;
+                            left -= 1;
+                        }
+                        ran += turn - left;
+                    }
+                    ran
+                }
+                Policy::Fair => match (0..count)
+                    .filter(|&id| schedule.state(id) == State::Runnable)
+                    .min_by_key(|&id| schedule.machines[id].steps())
+                {
+                    Some(id) => {
+                        schedule.step(id)?;
+                        1
+                    }
+                    None => 0,
+                },
+            };
+            if ran == 0 {
+                return Ok(schedule);
+            }
+            steps += ran;
+            ensure!(steps <= self.limit, "The programs do not end within {} steps", self.limit);
+        }
+    }
+}
+
+/// Machines run by a scheduler.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub machines: Vec<Machine<Duet>>,
+    /// The receivers of the values sent by each machine.
+    receivers: Vec<Vec<usize>>,
+    capacity: Option<usize>,
+}
+
+impl Schedule {
+    /// Where machine id stopped, or that it can run.
+    pub fn state(&self, id: usize) -> State {
+        let machine = &self.machines[id];
+        if machine.halted() {
+            return State::Halted;
+        }
+        match machine.program[machine.pc as usize] {
+            Instruction::Rcv { .. } if machine.dialect.inbox.is_empty() => State::Receiving,
+            Instruction::Snd { .. } => match self.capacity {
+                Some(capacity)
+                    if self.receivers[id]
+                        .iter()
+                        .any(|&receiver| self.machines[receiver].dialect.inbox.len() >= capacity) =>
+                {
+                    State::Sending
+                }
+                _ => State::Runnable,
+            },
+            _ => State::Runnable,
+        }
+    }
+
+    /// Execute an instruction of a runnable machine, and deliver what it sent.
+    fn step(&mut self, id: usize) -> Result<(), Error> {
+        self.machines[id]
+            .step()
+            .map_err(|cause| format_err!("Program {}: {}", id, cause))?;
+        while let Some(value) = self.machines[id].dialect.outbox.pop_front() {
+            for &receiver in &self.receivers[id] {
+                self.machines[receiver].dialect.inbox.push_back(value);
+            }
+        }
+        Ok(())
+    }
+
+    /// The machines that are neither runnable nor halted, when run to the end.
+    pub fn blocked(&self) -> Vec<usize> {
+        (0..self.machines.len())
+            .filter(|&id| self.state(id) != State::Halted)
+            .collect()
+    }
+
+    pub fn deadlocked(&self) -> bool {
+        !self.blocked().is_empty()
+    }
+}
+
+/// Tells whether the programs deadlocked, and for every program its state,
+/// where and in which instruction it stopped, its inbox and the number of values
+/// it sent.
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let blocked = self.blocked();
+        if blocked.is_empty() {
+            writeln!(f, "all programs halted")?;
+        } else {
+            let ids: Vec<String> = blocked.iter().map(|id| id.to_string()).collect();
+            writeln!(f, "deadlock, blocked programs: {}", ids.join(", "))?;
+        }
+        for (id, machine) in self.machines.iter().enumerate() {
+            let inbox: Vec<String> = machine
+                .dialect
+                .inbox
+                .iter()
+                .map(|value| value.to_string())
+                .collect();
+            write!(f, "program {}: {} at {}", id, self.state(id), machine.pc)?;
+            if let Some(instruction) = machine.program.get(machine.pc as usize) {
+                write!(f, ": {}", instruction)?;
+            }
+            writeln!(f, ", inbox [{}], sent {}", inbox.join(" "), machine.dialect.sent)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,489 @@
+//! Structured control flow for VM programs, recovered from the control-flow
+//! graph, and rendered as pseudo-code.
+//!
+//! Natural loops become loops, and conditional jumps become `if`s joined at
+//! their immediate post-dominator. Loops must have a single exit, and breaks
+//! and continues can only refer to the innermost loop; programs that do not fit
+//! are rendered with `goto`s instead.
+
+use failure::Error;
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use vm::{Instruction, Source};
+use vm::cfg::{branch, Branch, Cfg, EdgeKind, Loop};
+
+/// The condition of the jump at pc, which holds when the jump is taken, unless
+/// negated.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Condition {
+    pub pc: usize,
+    pub negated: bool,
+}
+
+impl Condition {
+    pub fn negate(self) -> Condition {
+        Condition {
+            pc: self.pc,
+            negated: !self.negated,
+        }
+    }
+
+    /// The condition as an expression, such as `g != 0`.
+    pub fn render(&self, program: &[Instruction]) -> String {
+        let (left, right, holds, fails) = match program[self.pc] {
+            Instruction::Jgz { condition, .. } => (condition.to_string(), "0".to_string(), ">", "<="),
+            Instruction::Jnz { condition, .. } => (condition.to_string(), "0".to_string(), "!=", "=="),
+            Instruction::Jnq {
+                condition1,
+                condition2,
+                ..
+            } => (condition1.to_string(), condition2.to_string(), "!=", "=="),
+            ref instruction => panic!("{} is not a jump", instruction),
+        };
+        let operator = if self.negated { fails } else { holds };
+        format!("{} {} {}", left, operator, right)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Stmt {
+    /// A non-jump instruction.
+    Instruction { pc: usize },
+    If {
+        condition: Condition,
+        then: Vec<Stmt>,
+        otherwise: Vec<Stmt>,
+    },
+    While { condition: Condition, body: Vec<Stmt> },
+    DoWhile { body: Vec<Stmt>, condition: Condition },
+    Loop { body: Vec<Stmt> },
+    Break,
+    Continue,
+    Halt,
+}
+
+/// A loop that is being structured.
+struct Frame {
+    header: usize,
+    follow: Option<usize>,
+}
+
+struct Structurer<'a> {
+    program: &'a [Instruction],
+    cfg: &'a Cfg,
+    loops: HashMap<usize, Loop>,
+    post_dominators: Vec<Option<usize>>,
+    emitted: Vec<bool>,
+}
+
+impl<'a> Structurer<'a> {
+    /// The statement for going to block, if it leaves the current sequence.
+    /// Reaching stop gives an empty statement list.
+    fn control(&self, block: usize, stop: Option<usize>, frames: &[Frame]) -> Result<Option<Vec<Stmt>>, Error> {
+        if Some(block) == stop {
+            return Ok(Some(vec![]));
+        }
+        if let Some(frame) = frames.last() {
+            if block == frame.header {
+                return Ok(Some(vec![Stmt::Continue]));
+            }
+            if Some(block) == frame.follow {
+                return Ok(Some(vec![Stmt::Break]));
+            }
+        }
+        if block == self.cfg.exit {
+            return Ok(Some(vec![Stmt::Halt]));
+        }
+        for frame in frames {
+            if block == frame.header || Some(block) == frame.follow {
+                bail!(
+                    "Jump to pc {} leaves more than one loop",
+                    self.cfg.blocks[block].start
+                );
+            }
+        }
+        Ok(None)
+    }
+
+    fn branch(&mut self, block: usize, stop: Option<usize>, frames: &mut Vec<Frame>) -> Result<Vec<Stmt>, Error> {
+        match self.control(block, stop, frames)? {
+            Some(stmts) => Ok(stmts),
+            None => self.sequence(block, stop, frames, false),
+        }
+    }
+
+    /// The statements from block until stop, or until control leaves the
+    /// sequence. When entering, block is the header of the innermost loop.
+    fn sequence(
+        &mut self,
+        mut block: usize,
+        stop: Option<usize>,
+        frames: &mut Vec<Frame>,
+        mut entering: bool,
+    ) -> Result<Vec<Stmt>, Error> {
+        let mut stmts = Vec::new();
+        loop {
+            if !entering {
+                if let Some(control) = self.control(block, stop, frames)? {
+                    stmts.extend(control);
+                    return Ok(stmts);
+                }
+                if self.loops.contains_key(&block) {
+                    let follow = {
+                        let l = &self.loops[&block];
+                        if l.exits.len() > 1 {
+                            bail!(
+                                "Loop at pc {} has more than one exit",
+                                self.cfg.blocks[block].start
+                            );
+                        }
+                        l.exits.iter().next().cloned()
+                    };
+                    frames.push(Frame {
+                        header: block,
+                        follow,
+                    });
+                    let body = self.sequence(block, None, frames, true);
+                    frames.pop();
+                    stmts.push(Stmt::Loop { body: body? });
+                    match follow {
+                        Some(follow) => {
+                            block = follow;
+                            continue;
+                        }
+                        None => return Ok(stmts),
+                    }
+                }
+            }
+            entering = false;
+
+            if self.emitted[block] {
+                bail!(
+                    "Control reaches pc {} in more than one way",
+                    self.cfg.blocks[block].start
+                );
+            }
+            self.emitted[block] = true;
+            let jump = self.cfg.blocks[block].jump(self.program);
+            let (start, end) = (self.cfg.blocks[block].start, self.cfg.blocks[block].end);
+            let last = jump.unwrap_or(end);
+            stmts.extend((start..last).map(|pc| Stmt::Instruction { pc }));
+
+            let taken = self.cfg.successor(block, EdgeKind::Taken);
+            let fallthrough = self.cfg.successor(block, EdgeKind::Fallthrough);
+            match (jump, taken, fallthrough) {
+                (Some(pc), Some(taken), Some(fallthrough)) => {
+                    let join = match (self.post_dominators[block], frames.last()) {
+                        (Some(join), Some(frame)) => if join != frame.header
+                            && self.loops[&frame.header].body.contains(&join)
+                        {
+                            Some(join)
+                        } else {
+                            None
+                        },
+                        (join, None) => join,
+                        (None, _) => None,
+                    };
+                    let stop = join.or(stop);
+                    let then = self.branch(taken, stop, frames)?;
+                    let otherwise = self.branch(fallthrough, stop, frames)?;
+                    stmts.push(Stmt::If {
+                        condition: Condition { pc, negated: false },
+                        then,
+                        otherwise,
+                    });
+                    match join {
+                        Some(join) => block = join,
+                        None => return Ok(stmts),
+                    }
+                }
+                (_, Some(next), None) | (_, None, Some(next)) => block = next,
+                _ => bail!("Block at pc {} has no successors", start),
+            }
+        }
+    }
+}
+
+fn normalize_if(condition: Condition, then: Vec<Stmt>, otherwise: Vec<Stmt>) -> Option<Stmt> {
+    if then.is_empty() && otherwise.is_empty() {
+        None
+    } else if then.is_empty() {
+        Some(Stmt::If {
+            condition: condition.negate(),
+            then: otherwise,
+            otherwise: then,
+        })
+    } else {
+        Some(Stmt::If {
+            condition,
+            then,
+            otherwise,
+        })
+    }
+}
+
+/// Remove continues at the end of a loop body.
+fn trim_continue(stmts: &mut Vec<Stmt>) {
+    match stmts.pop() {
+        Some(Stmt::Continue) | None => {}
+        Some(Stmt::If {
+            condition,
+            mut then,
+            mut otherwise,
+        }) => {
+            trim_continue(&mut then);
+            trim_continue(&mut otherwise);
+            stmts.extend(normalize_if(condition, then, otherwise));
+        }
+        Some(stmt) => stmts.push(stmt),
+    }
+}
+
+/// Whether the statements continue the loop they are in.
+fn continues(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match *stmt {
+        Stmt::Continue => true,
+        Stmt::If {
+            ref then,
+            ref otherwise,
+            ..
+        } => continues(then) || continues(otherwise),
+        _ => false,
+    })
+}
+
+fn is_break(stmts: &[Stmt]) -> bool {
+    stmts.len() == 1 && stmts[0] == Stmt::Break
+}
+
+/// Tidy up the statements, turning loops into while and do-while loops where
+/// possible.
+fn simplify(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    let mut result = Vec::new();
+    for stmt in stmts {
+        match stmt {
+            Stmt::If {
+                condition,
+                then,
+                otherwise,
+            } => result.extend(normalize_if(condition, simplify(then), simplify(otherwise))),
+            Stmt::Loop { body } => {
+                let mut body = simplify(body);
+                trim_continue(&mut body);
+                let first = match body.first() {
+                    Some(&Stmt::If {
+                        condition,
+                        ref then,
+                        ..
+                    }) if is_break(then) => Some(condition),
+                    _ => None,
+                };
+                let last = match body.last() {
+                    Some(&Stmt::If {
+                        condition,
+                        ref then,
+                        ref otherwise,
+                    }) if is_break(then) && otherwise.is_empty() => Some(condition),
+                    _ => None,
+                };
+                if let Some(condition) = first {
+                    let mut rest = body.split_off(1);
+                    let mut body = match body.pop() {
+                        Some(Stmt::If { otherwise, .. }) => otherwise,
+                        _ => unreachable!(),
+                    };
+                    body.append(&mut rest);
+                    result.push(Stmt::While {
+                        condition: condition.negate(),
+                        body,
+                    });
+                } else if let (Some(condition), false) = (last, continues(&body)) {
+                    body.pop();
+                    result.push(Stmt::DoWhile {
+                        body,
+                        condition: condition.negate(),
+                    });
+                } else {
+                    result.push(Stmt::Loop { body });
+                }
+            }
+            Stmt::While { condition, body } => result.push(Stmt::While {
+                condition,
+                body: simplify(body),
+            }),
+            Stmt::DoWhile { body, condition } => result.push(Stmt::DoWhile {
+                body: simplify(body),
+                condition,
+            }),
+            stmt => result.push(stmt),
+        }
+    }
+    result
+}
+
+/// The structured statements of a program, or an error telling why the
+/// program could not be structured.
+pub fn structure(program: &[Instruction]) -> Result<Vec<Stmt>, Error> {
+    let cfg = Cfg::new(program);
+    if cfg.indirect {
+        bail!("The program has jumps with offsets in registers");
+    }
+    let mut structurer = Structurer {
+        program,
+        cfg: &cfg,
+        loops: cfg.loops().into_iter().map(|l| (l.header, l)).collect(),
+        post_dominators: cfg.post_dominators(),
+        emitted: vec![false; cfg.blocks.len()],
+    };
+    let stmts = structurer.sequence(0, None, &mut Vec::new(), false)?;
+    let mut stmts = simplify(stmts);
+    if stmts.last() == Some(&Stmt::Halt) {
+        stmts.pop();
+    }
+    Ok(stmts)
+}
+
+/// An instruction as a pseudo-code statement.
+pub fn statement(instruction: &Instruction) -> String {
+    match *instruction {
+        Instruction::Snd { source } => format!("snd {}", source),
+        Instruction::Set { target, source } => format!("{} = {}", target, source),
+        Instruction::Add {
+            target,
+            source: Source::Value { value },
+        } if value < 0 => format!("{} -= {}", target, -(value as i128)),
+        Instruction::Add { target, source } => format!("{} += {}", target, source),
+        Instruction::Sub {
+            target,
+            source: Source::Value { value },
+        } if value < 0 => format!("{} += {}", target, -(value as i128)),
+        Instruction::Sub { target, source } => format!("{} -= {}", target, source),
+        Instruction::Mul { target, source } => format!("{} *= {}", target, source),
+        Instruction::Mod { target, source } => format!("{} %= {}", target, source),
+        Instruction::Rcv { target } => format!("rcv {}", target),
+        Instruction::MulAdd {
+            target,
+            source,
+            count,
+        } => format!("{} += {} * {}, {} = 0", target, source, count, count),
+        Instruction::Divides {
+            target,
+            factor,
+            product,
+            counter,
+        } => format!(
+            "if {} * k == {} for some k in {}..{} {{ {} = 0 }}, {} = {}",
+            factor, product, counter, product, target, counter, product
+        ),
+        ref jump => format!("{}", jump),
+    }
+}
+
+fn render(program: &[Instruction], stmts: &[Stmt], depth: usize, result: &mut String) {
+    let indent = "    ".repeat(depth);
+    for stmt in stmts {
+        match *stmt {
+            Stmt::Instruction { pc } => {
+                writeln!(result, "{}{}", indent, statement(&program[pc])).unwrap();
+            }
+            Stmt::If {
+                condition,
+                ref then,
+                ref otherwise,
+            } => {
+                writeln!(result, "{}if {} {{", indent, condition.render(program)).unwrap();
+                render(program, then, depth + 1, result);
+                if !otherwise.is_empty() {
+                    writeln!(result, "{}}} else {{", indent).unwrap();
+                    render(program, otherwise, depth + 1, result);
+                }
+                writeln!(result, "{}}}", indent).unwrap();
+            }
+            Stmt::While {
+                condition,
+                ref body,
+            } => {
+                writeln!(result, "{}while {} {{", indent, condition.render(program)).unwrap();
+                render(program, body, depth + 1, result);
+                writeln!(result, "{}}}", indent).unwrap();
+            }
+            Stmt::DoWhile {
+                ref body,
+                condition,
+            } => {
+                writeln!(result, "{}do {{", indent).unwrap();
+                render(program, body, depth + 1, result);
+                writeln!(result, "{}}} while {}", indent, condition.render(program)).unwrap();
+            }
+            Stmt::Loop { ref body } => {
+                writeln!(result, "{}loop {{", indent).unwrap();
+                render(program, body, depth + 1, result);
+                writeln!(result, "{}}}", indent).unwrap();
+            }
+            Stmt::Break => writeln!(result, "{}break", indent).unwrap(),
+            Stmt::Continue => writeln!(result, "{}continue", indent).unwrap(),
+            Stmt::Halt => writeln!(result, "{}halt", indent).unwrap(),
+        }
+    }
+}
+
+/// The program as pseudo-code with gotos, one label per jump target.
+fn unstructured(program: &[Instruction]) -> String {
+    let cfg = Cfg::new(program);
+    let targets: BTreeSet<usize> = cfg.blocks
+        .iter()
+        .enumerate()
+        .filter(|&(_, block)| match block.jump(program).and_then(|pc| program[pc].offset()) {
+            Some(Source::Value { .. }) => true,
+            _ => false,
+        })
+        .filter_map(|(index, _)| cfg.successor(index, EdgeKind::Taken))
+        .collect();
+    let names: HashMap<usize, String> = targets
+        .iter()
+        .enumerate()
+        .map(|(index, &target)| (target, format!("L{}", index + 1)))
+        .collect();
+    let mut result = String::new();
+    for (index, block) in cfg.blocks.iter().enumerate() {
+        if let Some(name) = names.get(&index) {
+            writeln!(result, "{}:", name).unwrap();
+        }
+        for pc in block.start..block.end {
+            let instruction = &program[pc];
+            if instruction.offset().is_none() {
+                writeln!(result, "    {}", statement(instruction)).unwrap();
+                continue;
+            }
+            let target = match instruction.offset() {
+                Some(Source::Register { id }) => format!("pc + {}", id),
+                _ => match cfg.successor(index, EdgeKind::Taken) {
+                    Some(target) => names[&target].clone(),
+                    None => continue,
+                },
+            };
+            if branch(instruction) == Branch::Always {
+                writeln!(result, "    goto {}", target).unwrap();
+            } else {
+                let condition = Condition { pc, negated: false };
+                writeln!(result, "    if {} goto {}", condition.render(program), target).unwrap();
+            }
+        }
+    }
+    result
+}
+
+/// The program as structured pseudo-code, falling back to gotos for programs
+/// that can not be structured.
+pub fn pseudo_code(program: &[Instruction]) -> String {
+    match structure(program) {
+        Ok(stmts) => {
+            let mut result = String::new();
+            render(program, &stmts, 0, &mut result);
+            result
+        }
+        Err(_) => unstructured(program),
+    }
+}
@@ -0,0 +1,193 @@
+//! Running programs that send values to each other on threads of their own.
+//!
+//! Every program runs on an OS thread, and the values it sends go through
+//! `std::sync::mpsc` channels to the programs the topology says. The threads
+//! share a count of the programs waiting in `rcv` and of the values on their
+//! way to each program: when every program that has not stopped is waiting and
+//! no values are on their way, the programs are deadlocked, and the waiting
+//! ones stop in their `rcv`.
+//!
+//! When no program receives from more than one other, as in a ring or in the
+//! duet of day 18, every program sends and receives the same values as with
+//! the scheduler. Otherwise, the order in which values from different senders
+//! arrive depends on the timing of the threads.
+
+use failure::Error;
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use vm::{Dialect, Duet, Machine, Receive, Status, Value};
+use vm::scheduler::Topology;
+
+/// What the threads know about each other.
+struct Counts {
+    /// The number of threads waiting in `rcv`.
+    waiting: usize,
+    /// Whether each thread has stopped.
+    stopped: Vec<bool>,
+    /// The number of values sent to each thread but not yet received.
+    pending: Vec<usize>,
+    deadlock: bool,
+}
+
+impl Counts {
+    /// Whether no thread can continue. Values sent to stopped threads are never
+    /// received, and do not count.
+    fn deadlocked(&self) -> bool {
+        let stopped = self.stopped.iter().filter(|&&stopped| stopped).count();
+        let pending: usize = self.pending
+            .iter()
+            .zip(&self.stopped)
+            .filter(|&(_, &stopped)| !stopped)
+            .map(|(&pending, _)| pending)
+            .sum();
+        self.waiting + stopped == self.stopped.len() && pending == 0
+    }
+}
+
+struct Shared {
+    counts: Mutex<Counts>,
+    changed: Condvar,
+}
+
+/// The duet dialect over channels: `snd` sends to the receivers of the
+/// program, and `rcv` waits for a value, or for a deadlock.
+pub struct Channels {
+    id: usize,
+    receivers: Vec<(usize, Sender<Value>)>,
+    inbox: Receiver<Value>,
+    shared: Arc<Shared>,
+    pub sent: usize,
+}
+
+impl Channels {
+    /// Note that the thread has stopped, which may leave the others
+    /// deadlocked.
+    fn stop(&self) {
+        let mut counts = self.shared.counts.lock().unwrap();
+        counts.stopped[self.id] = true;
+        if counts.deadlocked() {
+            counts.deadlock = true;
+        }
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Dialect for Channels {
+    fn send(&mut self, value: Value) -> Result<(), Error> {
+        for &(receiver, ref sender) in &self.receivers {
+            // Values sent to a program that has stopped are lost.
+            if sender.send(value).is_ok() {
+                self.shared.counts.lock().unwrap().pending[receiver] += 1;
+                self.shared.changed.notify_all();
+            }
+        }
+        self.sent += 1;
+        Ok(())
+    }
+
+    fn receive(&mut self, _current: Value) -> Result<Receive, Error> {
+        let mut counts = self.shared.counts.lock().unwrap();
+        loop {
+            if counts.pending[self.id] > 0 {
+                counts.pending[self.id] -= 1;
+                drop(counts);
+                // The value is in the channel before it is counted as pending.
+                return Ok(Receive::Store(self.inbox.recv()?));
+            }
+            if counts.deadlock {
+                return Ok(Receive::Wait);
+            }
+            counts.waiting += 1;
+            if counts.deadlocked() {
+                counts.deadlock = true;
+                counts.waiting -= 1;
+                self.shared.changed.notify_all();
+                return Ok(Receive::Wait);
+            }
+            counts = self.shared.changed.wait(counts).unwrap();
+            counts.waiting -= 1;
+        }
+    }
+}
+
+/// Run a machine for at most limit steps, or until it halts or waits.
+fn run(machine: &mut Machine<Channels>, limit: u64) -> Result<(), Error> {
+    for _ in 0..limit {
+        if machine.step()? != Status::Running {
+            return Ok(());
+        }
+    }
+    bail!("Program {} does not end within {} steps", machine.dialect.id, limit)
+}
+
+/// Run the machines on threads of their own until all are waiting or halted,
+/// failing if any machine fails or runs more than limit steps.
+pub fn run_threads(topology: &Topology, machines: Vec<Machine<Duet>>, limit: u64) -> Result<Vec<Machine<Channels>>, Error> {
+    let count = machines.len();
+    let shared = Arc::new(Shared {
+        counts: Mutex::new(Counts {
+            waiting: 0,
+            stopped: vec![false; count],
+            pending: vec![0; count],
+            deadlock: false,
+        }),
+        changed: Condvar::new(),
+    });
+    let receivers: Vec<Vec<usize>> = (0..count).map(|id| topology.receivers(id, count)).collect();
+    for (id, receivers) in receivers.iter().enumerate() {
+        for &receiver in receivers {
+            ensure!(
+                receiver < count,
+                "Program {} sends to program {}, but there are only {} programs",
+                id,
+                receiver,
+                count
+            );
+        }
+    }
+    let (senders, inboxes): (Vec<Sender<Value>>, Vec<Receiver<Value>>) = (0..count).map(|_| channel()).unzip();
+
+    let mut handles = Vec::with_capacity(count);
+    for ((id, machine), inbox) in machines.into_iter().enumerate().zip(inboxes) {
+        // Values already in the inbox of the machine are sent to it first.
+        for &value in &machine.dialect.inbox {
+            senders[id].send(value).unwrap();
+            shared.counts.lock().unwrap().pending[id] += 1;
+        }
+        let channels = Channels {
+            id,
+            receivers: receivers[id]
+                .iter()
+                .map(|&receiver| (receiver, senders[receiver].clone()))
+                .collect(),
+            inbox,
+            shared: Arc::clone(&shared),
+            sent: machine.dialect.sent,
+        };
+        let mut threaded = Machine::new(machine.program, channels);
+        threaded.registers = machine.registers;
+        threaded.pc = machine.pc;
+        threaded.counts = machine.counts;
+        threaded.profile = machine.profile;
+        handles.push(thread::spawn(move || {
+            let result = run(&mut threaded, limit);
+            threaded.dialect.stop();
+            result.map(|()| threaded)
+        }));
+    }
+    drop(senders);
+
+    // Every thread is joined before the first failure is reported.
+    let results: Vec<Result<Machine<Channels>, Error>> = handles
+        .into_iter()
+        .enumerate()
+        .map(|(id, handle)| match handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(format_err!("Program {} panicked", id)),
+        })
+        .collect();
+    results.into_iter().collect()
+}
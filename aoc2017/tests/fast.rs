@@ -0,0 +1,236 @@
+extern crate aoc2017;
+
+use aoc2017::vm::*;
+use aoc2017::vm::fast::FastMachine;
+
+use std::fmt::Debug;
+
+const PROGRAMS: usize = 3000;
+const LIMIT: u64 = 10_000;
+/// The number of times a waiting program is given a value and run again.
+const ROUNDS: usize = 3;
+
+/// A xorshift generator, so that the programs are the same on every run.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+
+    fn register(&mut self) -> char {
+        ['a', 'b', 'c', 'g'][self.below(4) as usize]
+    }
+
+    /// Mostly small constants, and now and then one that overflows when used.
+    fn constant(&mut self) -> Value {
+        match self.below(12) {
+            0 => Value::max_value() - self.below(3) as Value,
+            1 => Value::min_value() + self.below(3) as Value,
+            2 => 1 << 40,
+            _ => self.below(9) as Value - 3,
+        }
+    }
+
+    fn source(&mut self) -> String {
+        if self.below(2) == 0 {
+            self.register().to_string()
+        } else {
+            self.constant().to_string()
+        }
+    }
+
+    /// A jump offset, in a register now and then so that jumps land inside
+    /// fused operations.
+    fn offset(&mut self) -> String {
+        if self.below(5) == 0 {
+            self.register().to_string()
+        } else {
+            (self.below(9) as Value - 4).to_string()
+        }
+    }
+}
+
+/// A short random program, often with the sequences that the fast machine
+/// fuses into single operations.
+fn random_program(random: &mut Random) -> String {
+    let length = 3 + random.below(14);
+    let mut lines = Vec::new();
+    while (lines.len() as u64) < length {
+        let target = random.register();
+        match random.below(16) {
+            0 | 1 => {
+                lines.push(format!("set {} {}", target, random.source()));
+                lines.push(format!("sub {} {}", target, random.source()));
+                lines.push(format!("jnz {} {}", target, random.offset()));
+            }
+            2 => {
+                lines.push(format!("set {} {}", target, random.source()));
+                lines.push(format!("mul {} {}", target, random.source()));
+                lines.push(format!("sub {} {}", target, random.source()));
+                lines.push(format!("jgz {} {}", target, random.offset()));
+            }
+            3 => lines.push(format!("set {} {}", target, random.source())),
+            4 => lines.push(format!("add {} {}", target, random.source())),
+            5 => lines.push(format!("sub {} {}", target, random.source())),
+            6 => lines.push(format!("mul {} {}", target, random.source())),
+            7 => lines.push(format!("mod {} {}", target, random.source())),
+            8 | 9 => lines.push(format!("jnz {} {}", random.source(), random.offset())),
+            10 => lines.push(format!("jgz {} {}", random.source(), random.offset())),
+            11 => lines.push(format!(
+                "jnq {} {} {}",
+                random.source(),
+                random.source(),
+                random.offset()
+            )),
+            12 => lines.push(format!("snd {}", random.source())),
+            13 => lines.push(format!("rcv {}", target)),
+            14 => lines.push(format!("mul-add {} {} {}", target, random.source(), random.register())),
+            _ => lines.push(format!(
+                "divides {} {} {} {}",
+                target,
+                random.source(),
+                random.source(),
+                random.register()
+            )),
+        }
+    }
+    lines.join("\n")
+}
+
+/// Run the program on the reference machine, for at most LIMIT steps, and on
+/// the fast machine, and check that they end in the same state with the same
+/// result. Each time the program waits, feed gives both a value, and they run
+/// again. Gives whether the reference machine ended within the limit, as only
+/// then are they compared.
+fn compare<D>(text: &str, dialect: D, feed: fn(&mut D, Value)) -> bool
+where
+    D: Dialect + Clone + Debug + PartialEq,
+{
+    let program = parse_program(text).unwrap();
+    let mut reference = Machine::new(program.clone(), dialect.clone());
+    let mut fast = FastMachine::new(program, dialect);
+    for round in 0..ROUNDS {
+        let mut expected = Ok(Status::Running);
+        while expected == Ok(Status::Running) {
+            if reference.steps() >= LIMIT {
+                return false;
+            }
+            expected = reference.step().map_err(|error| error.to_string());
+        }
+        let actual = fast.run().map_err(|error| error.to_string());
+        assert_eq!(expected, actual, "Results differ for\n{}", text);
+        for opcode in Opcode::all().iter() {
+            assert_eq!(
+                reference.count(*opcode),
+                fast.count(*opcode),
+                "Counts of {} differ for\n{}",
+                opcode,
+                text
+            );
+        }
+        assert_eq!(
+            (&reference.registers, reference.pc, reference.halted(), &reference.dialect),
+            (&fast.registers(), fast.pc(), fast.halted(), &fast.dialect),
+            "States differ for\n{}",
+            text
+        );
+        if expected != Ok(Status::Waiting) {
+            break;
+        }
+        feed(&mut reference.dialect, round as Value);
+        feed(&mut fast.dialect, round as Value);
+    }
+    true
+}
+
+fn coprocessor(text: &str) -> bool {
+    compare(text, Coprocessor, |_, _| {})
+}
+
+#[test]
+fn falls_off_end_after_fused_operation() {
+    assert!(coprocessor("set a 1\njgz -1 -1\nset b 2"));
+    assert!(coprocessor("add a 1\njgz -1 -1"));
+    assert!(coprocessor("set a 3\nsub a 1\njnz a -1"));
+}
+
+#[test]
+fn sends_constants_without_slots() {
+    // Enough constants to fill every slot, so that the sent one has none.
+    let mut lines: Vec<String> = (0..300).map(|value| format!("set a {}", value)).collect();
+    lines.push("snd 1000".to_string());
+    lines.push("snd -5000000000".to_string());
+    lines.push("snd a".to_string());
+    let program = parse_program(&lines.join("\n")).unwrap();
+    let mut reference = Machine::new(program.clone(), Duet::default());
+    let mut fast = FastMachine::new(program, Duet::default());
+    assert_eq!(reference.run().unwrap(), fast.run().unwrap());
+    assert_eq!(reference.dialect, fast.dialect);
+    assert_eq!(fast.dialect.outbox, vec![1000, -5_000_000_000, 299]);
+}
+
+#[test]
+fn undoes_overflowing_fused_operations() {
+    assert!(coprocessor(
+        "set a 9223372036854775807\nset g 1\nadd g a\njnz g 2\nset b 1\nadd a 1\njnz a -3"
+    ));
+    assert!(coprocessor("set a 4611686018427387904\nset g a\nmul g 2\nsub g 1\njnz g -2"));
+}
+
+fn duet(text: &str) -> bool {
+    compare(text, Duet::default(), |duet, value| duet.inbox.push_back(value))
+}
+
+#[test]
+fn overflows_after_snd_and_rcv() {
+    assert!(duet("rcv a\nadd a 9223372036854775807\njgz a -2"));
+    assert!(duet("snd 7\nset a 9223372036854775807\nadd a 1\njnz a -3"));
+    assert!(duet("rcv a\nsub b 4611686018427387904\nset g a\nmul g 3\nsub g b\njgz g -5"));
+}
+
+#[test]
+fn runs_unconditional_jumps_into_operations() {
+    assert!(coprocessor("set a 3\njnz 1 2\nset b 1\nsub a 1\njnz a -3"));
+    assert!(duet("rcv a\nsnd a\nset b a\njgz 1 2\nset c 1\nadd b 9223372036854775806\njgz b -6"));
+    assert!(coprocessor("set a 1\njnz 1 2\nset b 1\nadd a 9223372036854775807\njgz a -3"));
+}
+
+#[test]
+fn halts_on_overflowing_jumps() {
+    let text = "set a 9223372036854775807\njgz 1 a\nset b 1";
+    let mut reference = Machine::new(parse_program(text).unwrap(), Coprocessor);
+    assert_eq!(reference.run().unwrap(), Status::Halted);
+    assert_eq!(reference.registers[RegisterId::from_name('b').unwrap()], 0);
+    assert!(coprocessor(text));
+    assert!(coprocessor("set a 9223372036854775807\nset b 1\njnz b a"));
+    assert!(coprocessor("set a 9223372036854775807\njnz 1 9223372036854775807"));
+}
+
+#[test]
+fn jumps_into_fused_operations() {
+    assert!(coprocessor("set a 2\njnz 1 3\nset g a\nsub g 1\njnz g a\nadd b 1\nsub a 1\njgz a -5"));
+}
+
+#[test]
+fn random_programs() {
+    let mut random = Random(0x2017_1223);
+    let mut compared = 0;
+    for _ in 0..PROGRAMS {
+        let text = random_program(&mut random);
+        let ended = [
+            coprocessor(&text),
+            duet(&text),
+            compare(&text, Sound::default(), |_, _| {}),
+        ];
+        compared += ended.iter().filter(|&&ended| ended).count();
+    }
+    assert!(
+        compared > PROGRAMS,
+        "Only {} runs ended within the limit",
+        compared
+    );
+}
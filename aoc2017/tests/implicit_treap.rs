@@ -0,0 +1,104 @@
+extern crate aoc2017;
+
+use aoc2017::{ImplicitTreap, Spinlock};
+
+/// A xorshift generator, so that the insertions are the same on every run.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// A treap and a `Vec` of node ids after the same random insertions.
+fn random_inserts(seed: u64, count: usize) -> (ImplicitTreap<usize>, Vec<usize>) {
+    let mut random = Random(seed);
+    let mut treap = ImplicitTreap::new();
+    let mut model = Vec::new();
+    for value in 0..count {
+        let position = random.below(model.len() + 1);
+        let node = treap.insert(position, value * 10);
+        assert_eq!(node, value);
+        model.insert(position, node);
+    }
+    (treap, model)
+}
+
+#[test]
+fn insert() {
+    for seed in 1..20 {
+        let (treap, model) = random_inserts(seed, 500);
+        assert_eq!(treap.len(), model.len());
+        let values: Vec<usize> = treap.iter().cloned().collect();
+        let expected: Vec<usize> = model.iter().map(|node| node * 10).collect();
+        assert_eq!(values, expected, "seed {}", seed);
+    }
+}
+
+#[test]
+fn insert_at_ends() {
+    let mut treap = ImplicitTreap::new();
+    let mut model = Vec::new();
+    for value in 0..100 {
+        let position = if value % 2 == 0 { 0 } else { model.len() };
+        treap.insert(position, value);
+        model.insert(position, value);
+    }
+    let values: Vec<i32> = treap.iter().cloned().collect();
+    assert_eq!(values, model);
+}
+
+#[test]
+fn node_at() {
+    for seed in 1..20 {
+        let (treap, model) = random_inserts(seed, 500);
+        for (position, &node) in model.iter().enumerate() {
+            assert_eq!(treap.node_at(position), Some(node), "seed {}", seed);
+            assert_eq!(treap.get(position), Some(&(node * 10)));
+        }
+        assert_eq!(treap.node_at(model.len()), None);
+        assert_eq!(treap.get(model.len() + 1), None);
+    }
+}
+
+#[test]
+fn position() {
+    for seed in 1..20 {
+        let (treap, model) = random_inserts(seed, 500);
+        for (position, &node) in model.iter().enumerate() {
+            assert_eq!(treap.position(node), position, "seed {}", seed);
+            assert_eq!(*treap.value(node), node * 10);
+        }
+    }
+}
+
+#[test]
+fn empty() {
+    let treap: ImplicitTreap<()> = ImplicitTreap::new();
+    assert!(treap.is_empty());
+    assert_eq!(treap.node_at(0), None);
+    assert_eq!(treap.iter().count(), 0);
+}
+
+#[test]
+fn spinlock() {
+    let steps = 3;
+    let mut spinlock = Spinlock::new(steps, 2018);
+    spinlock.run_until(2017);
+    let mut model = vec![0];
+    let mut position = 0;
+    for value in 1..2018 {
+        position = (position + steps) % model.len() + 1;
+        model.insert(position, value);
+    }
+    for (position, &value) in model.iter().enumerate() {
+        assert_eq!(spinlock.value_at(position), value);
+        assert_eq!(spinlock.value_after(value), Some(model[(position + 1) % model.len()]));
+    }
+    assert_eq!(spinlock.value_after(2017), Some(638));
+    assert_eq!(spinlock.value_after(2018), None);
+}
@@ -1,11 +1,9 @@
 extern crate assert_cli;
 
-/*
-Argument does not seem to work
 #[test]
 fn sample1() {
     assert_cli::Assert::main_binary()
-        .with_args(&["-p 5"])
+        .with_args(&["-p", "5"])
         .stdin(
             "\
              s1,x3/4,pe/b
@@ -15,7 +13,36 @@ fn sample1() {
         .is("baedc")
         .unwrap();
 }
-*/
+
+
+#[test]
+fn numbered_names() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-p", "5", "--prefix", "p"])
+        .stdin(
+            "\
+             s1,x3/4,pp4/p1
+             ",
+        )
+        .stdout()
+        .is("p1 p0 p4 p3 p2")
+        .unwrap();
+}
+
+
+#[test]
+fn many_programs() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-p", "3000"])
+        .stdin(
+            "\
+             s1,x3/40,pp2999/p1000
+             ",
+        )
+        .stdout()
+        .contains("p1000 p0 p1 p39 p3 p4 p5")
+        .unwrap();
+}
 
 
 #[test]
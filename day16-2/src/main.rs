@@ -6,7 +6,6 @@ use failure::Error;
 
 use std::{io, process};
 use std::io::BufRead;
-use std::str::FromStr;
 use std::fmt;
 use std::collections::HashMap;
 
@@ -18,27 +17,70 @@ use structopt::StructOpt;
 
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 struct Program {
-    identifier: u8,
+    index: usize,
 }
 
 impl Program {
-    fn from_name(name: char) -> Program {
-        Program { identifier: name as u8 }
-    }
     fn from_ordinal(index: usize) -> Program {
-        Program { identifier: (index + (b'a' as usize)) as u8 }
-    }
-    fn name(&self) -> char {
-        self.identifier as char
+        Program { index }
     }
     fn index(&self) -> usize {
-        (self.identifier - (b'a' as u8)) as usize
+        self.index
     }
 }
 
-impl fmt::Display for Program {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.name())
+
+/// The names of the programs in a line.
+///
+/// Lines of at most 26 programs use the single letters `a`, `b`, ... as names
+/// by default. Longer lines (or any line when a prefix is given) use numbered
+/// names such as `p0`, `p1`, ..., `p99`.
+#[derive(Debug, Clone)]
+struct Alphabet {
+    names: Vec<String>,
+    indices: HashMap<String, usize>,
+    single_chars: bool,
+}
+
+impl Alphabet {
+    fn new(programs: usize, prefix: Option<&str>) -> Alphabet {
+        let names: Vec<String> = match prefix {
+            None if programs <= 26 => (0..programs)
+                .map(|index| ((index as u8 + b'a') as char).to_string())
+                .collect(),
+            _ => {
+                let prefix = prefix.unwrap_or("p");
+                (0..programs)
+                    .map(|index| format!("{}{}", prefix, index))
+                    .collect()
+            }
+        };
+        let indices = names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.clone(), index))
+            .collect();
+        let single_chars = names.iter().all(|name| name.chars().count() == 1);
+        Alphabet {
+            names,
+            indices,
+            single_chars,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn program(&self, name: &str) -> Result<Program, Error> {
+        match self.indices.get(name) {
+            Some(&index) => Ok(Program::from_ordinal(index)),
+            None => bail!("Unknown program name \"{}\"", name),
+        }
+    }
+
+    fn name(&self, program: &Program) -> &str {
+        &self.names[program.index()]
     }
 }
 
@@ -53,41 +95,54 @@ enum Instruction {
 
 use Instruction::*;
 
-impl FromStr for Instruction {
-    type Err = Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Instruction {
+    fn parse(s: &str, alphabet: &Alphabet) -> Result<Instruction, Error> {
         let s = s.trim();
-        let instruction = s.chars().next().unwrap();
-        Ok(if instruction == 's' {
-            Spin { amount: s.get(1..).unwrap().parse()? }
+        let instruction = match s.chars().next() {
+            Some(instruction) => instruction,
+            None => bail!("Empty instruction"),
+        };
+        let arguments = &s[instruction.len_utf8()..];
+        let instruction = if instruction == 's' {
+            Spin { amount: arguments.parse()? }
         } else {
-            let mut parts = s.get(1..).unwrap().split('/');
-            let first = parts.next().unwrap();
-            let second = parts.next().unwrap();
+            let mut parts = arguments.split('/');
+            let (first, second) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(first), Some(second), None) => (first, second),
+                _ => bail!("Could not parse \"{}\" as an instruction", s),
+            };
             match instruction {
                 'x' => Exchange {
                     first: first.parse()?,
                     second: second.parse()?,
                 },
                 'p' => Partner {
-                    first: Program::from_name(first.parse()?),
-                    second: Program::from_name(second.parse()?),
+                    first: alphabet.program(first)?,
+                    second: alphabet.program(second)?,
                 },
                 _ => bail!("Could not parse \"{}\" as an instruction", s),
             }
-        })
+        };
+        match instruction {
+            Spin { amount } if amount > alphabet.len() => {
+                bail!("Spin amount in \"{}\" is larger than the line", s)
+            }
+            Exchange { first, second } if first >= alphabet.len() || second >= alphabet.len() => {
+                bail!("Exchange position in \"{}\" is outside the line", s)
+            }
+            _ => Ok(instruction),
+        }
     }
 }
 
 
-fn read_input() -> Result<Vec<Instruction>, Error> {
+fn read_input(alphabet: &Alphabet) -> Result<Vec<Instruction>, Error> {
     let mut input: Vec<Instruction> = Vec::new();
     let stdin = io::stdin();
     for line in stdin.lock().lines() {
         for word in line?.trim().split(',') {
-            if !word.is_empty() {
-                input.push(word.parse()?);
+            if !word.trim().is_empty() {
+                input.push(Instruction::parse(word, alphabet)?);
             }
         }
     }
@@ -95,10 +150,10 @@ fn read_input() -> Result<Vec<Instruction>, Error> {
 }
 
 
-
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 struct Programs {
     line: Vec<Program>,
+    positions: Vec<usize>,
 }
 
 impl Programs {
@@ -107,51 +162,60 @@ impl Programs {
         for index in 0..max_program {
             line.push(Program::from_ordinal(index));
         }
-        Programs { line }
+        let positions = (0..max_program).collect();
+        Programs { line, positions }
     }
 
     fn position(&self, program: &Program) -> usize {
-        self.line
-            .iter()
-            .enumerate()
-            .find(|&(_, p)| p == program)
-            .map(|(i, _)| i)
-            .unwrap()
+        self.positions[program.index()]
     }
 
     fn execute(&self, instruction: &Instruction) -> Programs {
-        let new_line = match *instruction {
+        let mut result = self.clone();
+        match *instruction {
             Spin { amount } => {
                 let length = self.line.len();
-                self.line
-                    .iter()
-                    .cycle()
-                    .skip(length - amount)
-                    .take(length)
-                    .cloned()
-                    .collect()
+                result.line.rotate_right(amount);
+                for position in &mut result.positions {
+                    *position = (*position + amount) % length;
+                }
             }
             Exchange { first, second } => {
-                let mut new_line = self.line.clone();
-                new_line.swap(first, second);
-                new_line
+                result.line.swap(first, second);
+                result.positions[self.line[first].index()] = second;
+                result.positions[self.line[second].index()] = first;
             }
             Partner { first, second } => {
-                let mut new_line = self.line.clone();
                 let first_pos = self.position(&first);
                 let second_pos = self.position(&second);
-                new_line.swap(first_pos, second_pos);
-                new_line
+                result.line.swap(first_pos, second_pos);
+                result.positions[first.index()] = second_pos;
+                result.positions[second.index()] = first_pos;
             }
-        };
-        Programs { line: new_line }
+        }
+        result
     }
+
+    fn named<'a>(&'a self, alphabet: &'a Alphabet) -> NamedPrograms<'a> {
+        NamedPrograms {
+            programs: self,
+            alphabet,
+        }
+    }
+}
+
+struct NamedPrograms<'a> {
+    programs: &'a Programs,
+    alphabet: &'a Alphabet,
 }
 
-impl fmt::Display for Programs {
+impl<'a> fmt::Display for NamedPrograms<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for program in &self.line {
-            write!(f, "{}", program)?;
+        for (position, program) in self.programs.line.iter().enumerate() {
+            if position > 0 && !self.alphabet.single_chars {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", self.alphabet.name(program))?;
         }
         Ok(())
     }
@@ -163,12 +227,16 @@ struct Opt {
     /// An argument of type usize, with a default value.
     #[structopt(short = "p", long = "programs", help = "Number of programs", default_value = "16")]
     programs: usize,
+    #[structopt(long = "prefix",
+                help = "Name programs <prefix>0, <prefix>1, ... (default for more than 26 programs is p)")]
+    prefix: Option<String>,
 }
 
 
 fn run() -> Result<(), Error> {
-    let instructions = read_input()?;
     let opt = Opt::from_args();
+    let alphabet = Alphabet::new(opt.programs, opt.prefix.as_ref().map(|prefix| prefix.as_str()));
+    let instructions = read_input(&alphabet)?;
 
     let initial_programs = Programs::new(opt.programs);
 
@@ -198,7 +266,7 @@ fn run() -> Result<(), Error> {
         programs = next_programs;
     }
 
-    println!("{}", programs);
+    println!("{}", programs.named(&alphabet));
 
     Ok(())
 }
@@ -1,21 +1,33 @@
 extern crate assert_cli;
 
-/*
-Argument does not seem to work
 #[test]
 fn sample1() {
     assert_cli::Assert::main_binary()
-        .with_args(&["-p 5"])
+        .with_args(&["-p", "5"])
         .stdin(
             "\
              s1,x3/4,pe/b
              ",
         )
         .stdout()
-        .is("baedc")
+        .is("abcde")
+        .unwrap();
+}
+
+
+#[test]
+fn numbered_names() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-p", "5", "--prefix", "p"])
+        .stdin(
+            "\
+             s1,x3/4,pp4/p1
+             ",
+        )
+        .stdout()
+        .is("p0 p1 p2 p3 p4")
         .unwrap();
 }
-*/
 
 
 #[test]
@@ -1,8 +1,18 @@
+#[macro_use]
 extern crate failure;
 use failure::Error;
 
 use std::{io, process};
 
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+use structopt::StructOpt;
+
+extern crate aoc2017;
+use aoc2017::Spinlock;
+
 
 fn read_input() -> Result<usize, Error> {
     let mut input = String::new();
@@ -11,22 +21,39 @@ fn read_input() -> Result<usize, Error> {
 }
 
 
+#[derive(StructOpt, Debug)]
+#[structopt(name = "day17-1", about = "Solver for day 17, part 1.")]
+struct Opt {
+    #[structopt(short = "n", long = "values", help = "Last value to insert",
+                default_value = "2017")]
+    values: usize,
+    #[structopt(short = "a", long = "after", help = "Print the value following this value")]
+    after: Option<usize>,
+    #[structopt(short = "i", long = "at", help = "Print the value at this position")]
+    at: Option<usize>,
+}
+
+
 fn run() -> Result<(), Error> {
     let steps = read_input()?;
+    let opt = Opt::from_args();
 
-    let mut buffer: Vec<usize> = Vec::with_capacity(2018);
-    buffer.push(0);
-    let mut position: usize = 0;
+    let mut spinlock = Spinlock::new(steps, opt.values + 1);
+    spinlock.run_until(opt.values);
 
-    for i in 1..2018 {
-        position = (position + steps) % buffer.len();
-        buffer.insert(position + 1, i);
-        position += 1;
+    let after = match (opt.after, opt.at) {
+        (None, None) => Some(opt.values),
+        (after, _) => after,
+    };
+    if let Some(value) = after {
+        match spinlock.value_after(value) {
+            Some(next_value) => println!("{}", next_value),
+            None => bail!("Value {} is never inserted", value),
+        }
+    }
+    if let Some(position) = opt.at {
+        println!("{}", spinlock.value_at(position));
     }
-
-    let final_cell_value = buffer[(position + 1) % buffer.len()];
-
-    println!("{}", final_cell_value);
 
     Ok(())
 }
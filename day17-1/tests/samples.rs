@@ -10,6 +10,17 @@ fn sample1() {
 }
 
 
+#[test]
+fn sample1_queries() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-n", "9", "-a", "5", "-i", "1"])
+        .stdin("3")
+        .stdout()
+        .is("7\n9")
+        .unwrap();
+}
+
+
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
@@ -1,8 +1,18 @@
+#[macro_use]
 extern crate failure;
 use failure::Error;
 
 use std::{io, process};
 
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+use structopt::StructOpt;
+
+extern crate aoc2017;
+use aoc2017::Spinlock;
+
 
 fn read_input() -> Result<usize, Error> {
     let mut input = String::new();
@@ -11,15 +21,45 @@ fn read_input() -> Result<usize, Error> {
 }
 
 
+#[derive(StructOpt, Debug)]
+#[structopt(name = "day17-2", about = "Solver for day 17, part 2.")]
+struct Opt {
+    #[structopt(short = "n", long = "values", help = "Last value to insert",
+                default_value = "50000000")]
+    values: usize,
+    #[structopt(short = "a", long = "after", help = "Print the value following this value")]
+    after: Option<usize>,
+    #[structopt(short = "i", long = "at", help = "Print the value at this position")]
+    at: Option<usize>,
+}
+
+
 fn run() -> Result<(), Error> {
     let steps = read_input()?;
-    let values = 50_000_000;
+    let opt = Opt::from_args();
+
+    if opt.after.is_some() || opt.at.is_some() {
+        let mut spinlock = Spinlock::new(steps, opt.values + 1);
+        spinlock.run_until(opt.values);
+
+        if let Some(value) = opt.after {
+            match spinlock.value_after(value) {
+                Some(next_value) => println!("{}", next_value),
+                None => bail!("Value {} is never inserted", value),
+            }
+        }
+        if let Some(position) = opt.at {
+            println!("{}", spinlock.value_at(position));
+        }
+
+        return Ok(());
+    }
 
     let mut position: usize = 1;
     let mut value_count = 2;
     let mut cell_after_0 = 1;
 
-    for i in 2..values {
+    for i in 2..(opt.values + 1) {
         position = (position + steps) % value_count;
         value_count += 1;
         if position == 0 {
@@ -21,6 +21,58 @@ jgz a -2",
         .unwrap();
 }
 
+#[test]
+fn labels() {
+    assert_cli::Assert::main_binary()
+        .stdin(
+            "\
+set a 3
+loop: snd a
+sub a 1
+jgz a 'loop'
+set a 1
+rcv a",
+        )
+        .stdout()
+        .is("1")
+        .unwrap();
+}
+
+
+#[test]
+fn comments_and_constants() {
+    assert_cli::Assert::main_binary()
+        .stdin(
+            "\
+; count down from start, playing each value
+.const start 3
+set a start  # initial value
+loop: snd a
+sub a 1
+jgz a 'loop'
+set a 1
+rcv a",
+        )
+        .stdout()
+        .is("1")
+        .unwrap();
+}
+
+#[test]
+fn unknown_label() {
+    assert_cli::Assert::main_binary()
+        .stdin(
+            "\
+set a 1
+jgz a 'nowhere'",
+        )
+        .fails()
+        .stderr()
+        .contains("Line 2: unknown label nowhere")
+        .unwrap();
+}
+
+
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
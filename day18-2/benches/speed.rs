@@ -0,0 +1,57 @@
+//! The reference and the fast duet on the puzzle inputs.
+//!
+//! Run with `cargo bench`. Runs the duets in turn a number of times, checks
+//! that the programs send the same number of values and end in the same state,
+//! and reports the time of the fastest run and the speedup.
+
+extern crate aoc2017;
+
+#[path = "../../day23-1/benches/timing/mod.rs"]
+mod timing;
+
+use aoc2017::vm::*;
+use aoc2017::vm::fast;
+
+fn main() {
+    println!("{:>12} {:>10} {:>10} {:>10} {:>8}", "input", "engine", "sent", "ms/run", "speedup");
+    for &(name, text) in &[
+        ("puzzle1", include_str!("../data/puzzle1.in")),
+        ("puzzle_jim", include_str!("../data/puzzle_jim.in")),
+    ] {
+        let program = parse_program(text).unwrap();
+        let ((reference_time, reference), (fast_time, fast)) = timing::race(
+            || {
+                let machines = duet(&program).unwrap();
+                machines
+                    .iter()
+                    .map(|machine| (machine.dialect.sent, machine.steps(), machine.registers.clone(), machine.pc))
+                    .collect::<Vec<_>>()
+            },
+            || {
+                let machines = fast::duet(&program).unwrap();
+                machines
+                    .iter()
+                    .map(|machine| (machine.dialect.sent, machine.steps(), machine.registers(), machine.pc()))
+                    .collect::<Vec<_>>()
+            },
+        );
+        assert_eq!(reference, fast);
+
+        println!(
+            "{:>12} {:>10} {:>10} {:>10.3} {:>8}",
+            name,
+            "reference",
+            reference[1].0,
+            reference_time * 1e3,
+            ""
+        );
+        println!(
+            "{:>12} {:>10} {:>10} {:>10.3} {:>7.1}x",
+            name,
+            "fast",
+            fast[1].0,
+            fast_time * 1e3,
+            reference_time / fast_time
+        );
+    }
+}
@@ -45,3 +45,140 @@ fn puzzle1() {
         .is("5969")
         .unwrap();
 }
+
+#[test]
+fn profile_json() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-p", "json"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("5969")
+        .stderr()
+        .contains("[{\"steps\":58926,")
+        .stderr()
+        .contains("},{\"steps\":57743,")
+        .unwrap();
+}
+
+#[test]
+fn deadlock_report() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-r"])
+        .stdin(
+            "\
+snd 1
+snd 2
+snd p
+rcv a
+rcv b
+rcv c
+rcv d",
+        )
+        .stdout()
+        .is("3")
+        .stderr()
+        .is(
+            "\
+deadlock, blocked programs: 0, 1
+program 0: receiving at 6: rcv d, inbox [], sent 3
+program 1: receiving at 6: rcv d, inbox [], sent 3",
+        )
+        .unwrap();
+}
+
+#[test]
+fn bounded_queues() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-r", "-c", "1"])
+        .stdin(
+            "\
+snd 1
+snd 2
+snd p
+rcv a
+rcv b
+rcv c
+rcv d",
+        )
+        .stdout()
+        .is("1")
+        .stderr()
+        .contains("program 0: sending at 1: snd 2, inbox [1], sent 1")
+        .unwrap();
+}
+
+#[test]
+fn fair_policy() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--policy", "fair"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("5969")
+        .unwrap();
+}
+
+#[test]
+fn ring_of_three() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-n", "3", "--topology", "ring", "--policy", "round-robin:100", "-r"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("5969")
+        .stderr()
+        .contains("program 2: receiving at 26: rcv b, inbox [], sent 5969")
+        .unwrap();
+}
+
+#[test]
+fn pairs() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-n", "4", "--topology", "0-1,1-0,2-3,3-2", "-r"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("5969")
+        .stderr()
+        .contains("program 3: halted at 54, inbox [], sent 0")
+        .unwrap();
+}
+
+#[test]
+fn unknown_receiver() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--topology", "0-1,1-2"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .fails()
+        .stderr()
+        .contains("Program 1 sends to program 2, but there are only 2 programs")
+        .unwrap();
+}
+
+#[test]
+fn threads() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--threads"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("5969")
+        .unwrap();
+}
+
+#[test]
+fn fast() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--fast"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("5969")
+        .unwrap();
+}
+
+#[test]
+fn fast_ring() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-f", "-n", "3", "--topology", "ring"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .fails()
+        .stderr()
+        .contains("The fast interpreter runs two programs with unbounded inboxes")
+        .unwrap();
+}
@@ -0,0 +1,106 @@
+extern crate aoc2017;
+
+use aoc2017::vm::*;
+use aoc2017::vm::scheduler::{copies, Scheduler, Topology};
+use aoc2017::vm::threads::run_threads;
+
+const PROGRAMS: usize = 300;
+const LIMIT: u64 = 100_000;
+
+/// A xorshift generator, so that the programs are the same on every run.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+
+    fn register(&mut self) -> char {
+        ['a', 'b', 'c', 'p'][self.below(4) as usize]
+    }
+
+    fn source(&mut self) -> String {
+        if self.below(2) == 0 {
+            self.register().to_string()
+        } else {
+            (self.below(11) as Value - 3).to_string()
+        }
+    }
+}
+
+/// A short random program. Jumps go mostly forward, so that many programs end.
+fn random_program(random: &mut Random) -> String {
+    let length = 4 + random.below(12);
+    let mut lines = Vec::new();
+    for _ in 0..length {
+        let line = match random.below(9) {
+            0 | 1 => format!("snd {}", random.source()),
+            2 | 3 => format!("rcv {}", random.register()),
+            4 => format!("set {} {}", random.register(), random.source()),
+            5 => format!("add {} {}", random.register(), random.source()),
+            6 => format!("mul {} {}", random.register(), random.source()),
+            7 => format!("mod {} {}", random.register(), 1 + random.below(5)),
+            _ => format!("jgz {} {}", random.source(), random.below(7) as Value - 2),
+        };
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// Run the program on the scheduler and on threads, and check that every
+/// program ends in the same state. Gives whether the scheduler ended within
+/// the limit, as only then are they compared.
+fn compare(text: &str, topology: Topology, count: usize) -> bool {
+    let program = parse_program(text).unwrap();
+    let mut scheduler = Scheduler::new(topology);
+    scheduler.limit = LIMIT;
+    let expected = match scheduler.run(copies(&program, count).unwrap()) {
+        Ok(schedule) => schedule.machines,
+        Err(_) => return false,
+    };
+    let actual = run_threads(&scheduler.topology, copies(&program, count).unwrap(), LIMIT).unwrap();
+    for (id, (expected, actual)) in expected.iter().zip(&actual).enumerate() {
+        assert_eq!(
+            (expected.dialect.sent, expected.pc, &expected.registers, expected.counts),
+            (actual.dialect.sent, actual.pc, &actual.registers, actual.counts),
+            "Program {} of {} in {:?} differs for\n{}",
+            id,
+            count,
+            scheduler.topology,
+            text
+        );
+    }
+    true
+}
+
+#[test]
+fn puzzle1() {
+    let program = parse_program(include_str!("../data/puzzle1.in")).unwrap();
+    let machines = run_threads(&Topology::Broadcast, copies(&program, 2).unwrap(), LIMIT).unwrap();
+    assert_eq!(machines[0].dialect.sent, 6096);
+    assert_eq!(machines[1].dialect.sent, 5969);
+}
+
+#[test]
+fn random_programs() {
+    let mut random = Random(0x2017_1218);
+    let mut compared = 0;
+    for _ in 0..PROGRAMS {
+        let text = random_program(&mut random);
+        let ended = [
+            compare(&text, Topology::Broadcast, 2),
+            compare(&text, Topology::Ring, 3),
+            compare(&text, Topology::Ring, 5),
+            compare(&text, Topology::Pairs(vec![(0, 2), (2, 1), (1, 3)]), 4),
+        ];
+        compared += ended.iter().filter(|&&ended| ended).count();
+    }
+    assert!(
+        compared > PROGRAMS,
+        "Only {} runs ended within the limit",
+        compared
+    );
+}
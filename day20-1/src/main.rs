@@ -10,6 +10,12 @@ extern crate strum_macros;
 extern crate rayon;
 use rayon::prelude::*;
 
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+use structopt::StructOpt;
+
 use std::{io, process};
 use std::io::BufRead;
 use std::collections::HashMap;
@@ -179,21 +185,144 @@ fn read_input() -> Result<Vec<NamedParticle>, Error> {
 
 
 
-fn run() -> Result<(), Error> {
-    let mut particles = read_input()?;
+/// The sign that a coordinate eventually settles on as time grows.
+///
+/// At tick t, the coordinate is `p + v*t + a*t*(t+1)/2`, so the sign is decided by
+/// the acceleration if non-zero, then by `2*v + a`, and finally by the position.
+/// Note that a velocity pointing against the acceleration only delays the sign
+/// flip, it never prevents it.
+fn eventual_sign(p: Scalar, v: Scalar, a: Scalar) -> Scalar {
+    if a != 0 {
+        a.signum()
+    } else if v != 0 {
+        v.signum()
+    } else {
+        p.signum()
+    }
+}
 
-    const ITERATIONS: usize = 100_000;
+/// The Manhattan distance of a particle from the origin for all large enough ticks.
+///
+/// Once every coordinate has its eventual sign s, the distance at tick t is exactly
+/// `acceleration*t*(t+1)/2 + velocity*t + position`, where each term is the sum
+/// of the corresponding components multiplied by s. Comparing these terms
+/// lexicographically thus orders particles by their distance in the long run.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+struct LongTermDistance {
+    acceleration: Scalar,
+    velocity: Scalar,
+    position: Scalar,
+}
 
-    particles.par_iter_mut().for_each(
-        |np| for _ in 0..ITERATIONS {
-            np.particle.step_in_place()
-        },
-    );
+impl LongTermDistance {
+    fn new(particle: &Particle) -> LongTermDistance {
+        let axes = [
+            (particle.position.x, particle.velocity.x, particle.acceleration.x),
+            (particle.position.y, particle.velocity.y, particle.acceleration.y),
+            (particle.position.z, particle.velocity.z, particle.acceleration.z),
+        ];
+        let mut distance = LongTermDistance {
+            acceleration: 0,
+            velocity: 0,
+            position: 0,
+        };
+        for &(p, v, a) in &axes {
+            let sign = eventual_sign(p, v, a);
+            distance.acceleration += sign * a;
+            distance.velocity += sign * v;
+            distance.position += sign * p;
+        }
+        distance
+    }
+
+    /// Explain why a particle with this distance is not farther away in the long run
+    /// than one with the other distance.
+    fn explain(&self, other: &LongTermDistance) -> String {
+        if self.acceleration != other.acceleration {
+            format!(
+                "acceleration {} < {}",
+                self.acceleration,
+                other.acceleration
+            )
+        } else if self.velocity != other.velocity {
+            format!(
+                "equal acceleration {}, velocity along acceleration {} < {}",
+                self.acceleration,
+                self.velocity,
+                other.velocity
+            )
+        } else if self.position != other.position {
+            format!(
+                "equal acceleration {} and velocity {}, position {} < {}",
+                self.acceleration,
+                self.velocity,
+                self.position,
+                other.position
+            )
+        } else {
+            format!("tie, both eventually at distance {}", self)
+        }
+    }
+}
+
+impl fmt::Display for LongTermDistance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}*t*(t+1)/2 + {}*t + {}",
+            self.acceleration,
+            self.velocity,
+            self.position
+        )
+    }
+}
+
+/// All particles ordered by their distance in the long run, closest first.
+///
+/// Particles that tie are ordered by id.
+fn long_term_ranking(particles: &[NamedParticle]) -> Vec<(LongTermDistance, NamedParticle)> {
+    let mut ranking: Vec<(LongTermDistance, NamedParticle)> = particles
+        .iter()
+        .map(|np| (LongTermDistance::new(&np.particle), *np))
+        .collect();
+    ranking.sort_by_key(|&(distance, np)| (distance, np.id));
+    ranking
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "day20-1", about = "Solver for day 20, part 1.")]
+struct Opt {
+    #[structopt(short = "r", long = "ranking",
+                help = "Print all particles ordered by long-term distance, with explanations")]
+    ranking: bool,
+}
+
+
+fn run() -> Result<(), Error> {
+    let opt = Opt::from_args();
+    let particles = read_input()?;
+
+    let ranking = long_term_ranking(&particles);
+
+    if opt.ranking {
+        for (index, &(distance, np)) in ranking.iter().enumerate() {
+            if index > 0 {
+                let (previous_distance, previous) = ranking[index - 1];
+                let relation = if previous_distance == distance { "=" } else { "<" };
+                println!(
+                    "  {} {} {}: {}",
+                    previous.id,
+                    relation,
+                    np.id,
+                    previous_distance.explain(&distance)
+                );
+            }
+            println!("{}: {}", np.id, distance);
+        }
+        return Ok(());
+    }
 
-    let min_particle = particles.iter().min_by_key(
-        |np| np.particle.position.manhattan(),
-    );
-    if let Some(np) = min_particle {
+    if let Some(&(_, np)) = ranking.first() {
         println!("{}", np.id);
         Ok(())
     } else {
@@ -14,6 +14,49 @@ p=< 4,0,0>, v=< 0,0,0>, a=<-2,0,0>",
 }
 
 
+#[test]
+fn sample1_ranking() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-r"])
+        .stdin(
+            "\
+p=< 3,0,0>, v=< 2,0,0>, a=<-1,0,0>
+p=< 4,0,0>, v=< 0,0,0>, a=<-2,0,0>",
+        )
+        .stdout()
+        .is(
+            "\
+0: 1*t*(t+1)/2 + -2*t + -3
+  0 < 1: acceleration 1 < 2
+1: 2*t*(t+1)/2 + 0*t + -4",
+        )
+        .unwrap();
+}
+
+
+#[test]
+fn velocity_sign_flip_and_tie() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-r"])
+        .stdin(
+            "\
+p=<0,0,0>, v=<1,0,0>, a=<0,0,-1>
+p=<0,0,0>, v=< 1,0,0>, a=<1,0,0>
+p=<0,0,0>, v=<-5,0,0>, a=<1,0,0>",
+        )
+        .stdout()
+        .is(
+            "\
+2: 1*t*(t+1)/2 + -5*t + 0
+  2 < 0: equal acceleration 1, velocity along acceleration -5 < 1
+0: 1*t*(t+1)/2 + 1*t + 0
+  0 = 1: tie, both eventually at distance 1*t*(t+1)/2 + 1*t + 0
+1: 1*t*(t+1)/2 + 1*t + 0",
+        )
+        .unwrap();
+}
+
+
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
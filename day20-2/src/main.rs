@@ -10,8 +10,15 @@ extern crate strum_macros;
 extern crate rayon;
 use rayon::prelude::*;
 
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+use structopt::StructOpt;
+
 use std::{io, process};
-use std::io::BufRead;
+use std::io::{BufRead, Write};
+use std::fs::File;
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::str;
@@ -179,33 +186,348 @@ fn read_input() -> Result<Vec<NamedParticle>, Error> {
 
 
 
-fn run() -> Result<(), Error> {
-    let mut particles = read_input()?;
+/// The ticks at which a single coordinate of two particles coincide.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Meetings {
+    Always,
+    At(Vec<u64>),
+}
 
-    const ITERATIONS: usize = 10_000;
+/// Integer square root, if value is a perfect square.
+fn exact_sqrt(value: i128) -> Option<i128> {
+    if value < 0 {
+        return None;
+    }
+    let mut root = (value as f64).sqrt() as i128;
+    while root.checked_mul(root).map_or(true, |square| square > value) {
+        root -= 1;
+    }
+    while (root + 1).checked_mul(root + 1).map_or(false, |square| square <= value) {
+        root += 1;
+    }
+    if root * root == value {
+        Some(root)
+    } else {
+        None
+    }
+}
 
-    for _ in 0..ITERATIONS {
-        let mut seen = HashSet::new();
-        let mut collisions = HashSet::new();
+/// `b*b - 4*a*c`, if it fits in an `i128`.
+fn discriminant(a: i128, b: i128, c: i128) -> Option<i128> {
+    let square = b.checked_mul(b)?;
+    let product = a.checked_mul(c)?.checked_mul(4)?;
+    square.checked_sub(product)
+}
 
-        for np in &mut particles {
-            np.particle.step_in_place();
-            let position = np.particle.position;
-            if !seen.insert(position) {
-                collisions.insert(position);
+/// The positive integer solutions of `a*t^2 + b*t + c = 0` that fit in a `u64`.
+///
+/// Solutions are only found when the discriminant `b*b - 4*a*c` fits in an
+/// `i128`, which it does for the coefficients of particles with positions,
+/// velocities and accelerations below 2^60 in magnitude.
+fn positive_integer_roots(a: i128, b: i128, c: i128) -> Meetings {
+    let mut roots = Vec::new();
+    {
+        let mut add_root = |numerator: i128, denominator: i128| {
+            let root = numerator / denominator;
+            if numerator % denominator == 0 && 0 < root && root <= i128::from(u64::max_value()) {
+                roots.push(root as u64);
+            }
+        };
+        if a == 0 {
+            if b == 0 {
+                return if c == 0 { Meetings::Always } else { Meetings::At(Vec::new()) };
+            }
+            add_root(-c, b);
+        } else if let Some(root) = discriminant(a, b, c).and_then(exact_sqrt) {
+            add_root(-b - root, 2 * a);
+            if root != 0 {
+                add_root(-b + root, 2 * a);
             }
         }
+    }
+    roots.sort();
+    Meetings::At(roots)
+}
+
+/// The ticks when the coordinates of two particles along one axis coincide.
+///
+/// At tick t, a coordinate is at `p + v*t + a*t*(t+1)/2`, so the difference between
+/// the particles is zero when `da*t^2 + (2*dv + da)*t + 2*dp = 0`.
+fn axis_meetings(p: i128, v: i128, a: i128) -> Meetings {
+    positive_integer_roots(a, 2 * v + a, 2 * p)
+}
+
+/// The first tick (after tick 0) at which the two particles are at the same position.
+fn first_collision(first: &Particle, second: &Particle) -> Option<u64> {
+    let difference = |first: Scalar, second: Scalar| i128::from(first) - i128::from(second);
+    let axes = [
+        (
+            difference(first.position.x, second.position.x),
+            difference(first.velocity.x, second.velocity.x),
+            difference(first.acceleration.x, second.acceleration.x),
+        ),
+        (
+            difference(first.position.y, second.position.y),
+            difference(first.velocity.y, second.velocity.y),
+            difference(first.acceleration.y, second.acceleration.y),
+        ),
+        (
+            difference(first.position.z, second.position.z),
+            difference(first.velocity.z, second.velocity.z),
+            difference(first.acceleration.z, second.acceleration.z),
+        ),
+    ];
+
+    let mut candidates: Option<Vec<u64>> = None;
+    for &(p, v, a) in &axes {
+        if let Meetings::At(ticks) = axis_meetings(p, v, a) {
+            candidates = Some(match candidates {
+                None => ticks,
+                Some(previous) => previous
+                    .into_iter()
+                    .filter(|tick| ticks.contains(tick))
+                    .collect(),
+            });
+        }
+    }
+
+    // Identical particles meet on every tick, including the first one
+    candidates
+        .unwrap_or_else(|| vec![1])
+        .into_iter()
+        .next()
+}
 
-        particles = particles
-            .into_iter()
-            .filter(|np| !collisions.contains(&np.particle.position))
+/// The tick at which each particle is destroyed in a collision, or `None` for survivors.
+///
+/// All pairwise first collisions are computed exactly and processed in time order,
+/// where a collision only counts if both particles are still alive at that tick.
+fn collision_ticks(particles: &[NamedParticle]) -> Vec<Option<u64>> {
+    let mut events = Vec::new();
+    for (i, first) in particles.iter().enumerate() {
+        for (j, second) in particles.iter().enumerate().skip(i + 1) {
+            if let Some(tick) = first_collision(&first.particle, &second.particle) {
+                events.push((tick, i, j));
+            }
+        }
+    }
+    events.sort();
+
+    let mut died: Vec<Option<u64>> = vec![None; particles.len()];
+    let mut start = 0;
+    while start < events.len() {
+        let tick = events[start].0;
+        let end = start + events[start..]
+            .iter()
+            .take_while(|&&(event_tick, _, _)| event_tick == tick)
+            .count();
+        let colliding: Vec<usize> = events[start..end]
+            .iter()
+            .filter(|&&(_, i, j)| died[i].is_none() && died[j].is_none())
+            .flat_map(|&(_, i, j)| vec![i, j])
             .collect();
+        for index in colliding {
+            died[index] = Some(tick);
+        }
+        start = end;
     }
 
-    println!("{}", particles.len());
+    died
+}
+
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ExportFormat {
+    Csv,
+    JsonLines,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" | "json-lines" => Ok(ExportFormat::JsonLines),
+            _ => bail!("Unknown export format \"{}\", expected csv or jsonl", s),
+        }
+    }
+}
+
+/// Which ticks and particles to include in a trajectory export.
+#[derive(Debug, Clone)]
+struct ExportOptions {
+    format: ExportFormat,
+    from: u64,
+    to: u64,
+    stride: u64,
+    ids: Option<HashSet<usize>>,
+}
+
+fn parse_ids(s: &str) -> Result<HashSet<usize>, Error> {
+    let mut ids = HashSet::new();
+    for id in s.split(',').map(|id| id.trim()).filter(|id| !id.is_empty()) {
+        ids.insert(id.parse()?);
+    }
+    Ok(ids)
+}
+
+fn write_row<W: Write>(
+    out: &mut W,
+    format: ExportFormat,
+    tick: u64,
+    np: &NamedParticle,
+    alive: bool,
+) -> Result<(), Error> {
+    let Particle {
+        position: p,
+        velocity: v,
+        acceleration: a,
+    } = np.particle;
+    match format {
+        ExportFormat::Csv => writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            tick,
+            np.id,
+            p.x,
+            p.y,
+            p.z,
+            v.x,
+            v.y,
+            v.z,
+            a.x,
+            a.y,
+            a.z,
+            if alive { "alive" } else { "collided" }
+        )?,
+        ExportFormat::JsonLines => writeln!(
+            out,
+            "{{\"tick\":{},\"id\":{},\"position\":[{},{},{}],\"velocity\":[{},{},{}],\
+             \"acceleration\":[{},{},{}],\"alive\":{}}}",
+            tick,
+            np.id,
+            p.x,
+            p.y,
+            p.z,
+            v.x,
+            v.y,
+            v.z,
+            a.x,
+            a.y,
+            a.z,
+            alive
+        )?,
+    }
     Ok(())
 }
 
+/// Write the state of every selected particle for every selected tick.
+///
+/// A particle is written as collided on the tick it is destroyed, and is left
+/// out of all later ticks.
+fn export_trajectories<W: Write>(
+    out: &mut W,
+    particles: &[NamedParticle],
+    died: &[Option<u64>],
+    options: &ExportOptions,
+) -> Result<(), Error> {
+    ensure!(options.stride > 0, "Stride must be positive");
+    if options.format == ExportFormat::Csv {
+        writeln!(out, "tick,id,px,py,pz,vx,vy,vz,ax,ay,az,state")?;
+    }
+
+    let mut selected: Vec<(NamedParticle, Option<u64>)> = particles
+        .iter()
+        .zip(died)
+        .filter(|&(np, _)| options.ids.as_ref().map_or(true, |ids| ids.contains(&np.id)))
+        .map(|(np, &tick)| (*np, tick))
+        .collect();
+
+    let mut tick = 0;
+    while tick <= options.to {
+        if tick >= options.from && (tick - options.from) % options.stride == 0 {
+            for &(ref np, death) in &selected {
+                if death.map_or(true, |death| tick <= death) {
+                    write_row(out, options.format, tick, np, death != Some(tick))?;
+                }
+            }
+        }
+        selected.retain(|&(_, death)| death.map_or(true, |death| tick < death));
+        for &mut (ref mut np, _) in &mut selected {
+            np.particle.step_in_place();
+        }
+        tick += 1;
+    }
+
+    Ok(())
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "day20-2", about = "Solver for day 20, part 2.")]
+struct Opt {
+    #[structopt(short = "d", long = "deaths",
+                help = "Also print the tick at which each destroyed particle collided")]
+    deaths: bool,
+    #[structopt(short = "e", long = "export",
+                help = "Export per-tick particle trajectories as csv or jsonl instead")]
+    export: Option<ExportFormat>,
+    #[structopt(short = "o", long = "output", help = "File to export to (default stdout)")]
+    output: Option<String>,
+    #[structopt(long = "from", help = "First tick to export", default_value = "0")]
+    from: usize,
+    #[structopt(long = "to", help = "Last tick to export (default the last collision)")]
+    to: Option<u64>,
+    #[structopt(long = "stride", help = "Export every n:th tick", default_value = "1")]
+    stride: usize,
+    #[structopt(long = "ids", help = "Comma separated particle ids to export (default all)")]
+    ids: Option<String>,
+}
+
+
+fn run() -> Result<(), Error> {
+    let opt = Opt::from_args();
+    let particles = read_input()?;
+
+    let died = collision_ticks(&particles);
+
+    if let Some(format) = opt.export {
+        let options = ExportOptions {
+            format,
+            from: opt.from as u64,
+            to: opt.to
+                .unwrap_or_else(|| died.iter().filter_map(|&tick| tick).max().unwrap_or(0)),
+            stride: opt.stride as u64,
+            ids: match opt.ids {
+                Some(ref ids) => Some(parse_ids(ids)?),
+                None => None,
+            },
+        };
+        match opt.output {
+            Some(ref path) => {
+                let mut file = io::BufWriter::new(File::create(path)?);
+                export_trajectories(&mut file, &particles, &died, &options)?;
+            }
+            None => {
+                let stdout = io::stdout();
+                let mut out = io::BufWriter::new(stdout.lock());
+                export_trajectories(&mut out, &particles, &died, &options)?;
+            }
+        }
+        return Ok(());
+    }
+
+    println!("{}", died.iter().filter(|tick| tick.is_none()).count());
+    if opt.deaths {
+        for (np, tick) in particles.iter().zip(died) {
+            if let Some(tick) = tick {
+                println!("{}: {}", np.id, tick);
+            }
+        }
+    }
+
+    Ok(())
+}
 
 fn main() {
     match run() {
@@ -16,6 +16,85 @@ p=< 3,0,0>, v=<-1,0,0>, a=< 0,0,0>",
 }
 
 
+#[test]
+fn sample1_deaths() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-d"])
+        .stdin(
+            "\
+p=<-6,0,0>, v=< 3,0,0>, a=< 0,0,0>
+p=<-4,0,0>, v=< 2,0,0>, a=< 0,0,0>
+p=<-2,0,0>, v=< 1,0,0>, a=< 0,0,0>
+p=< 3,0,0>, v=<-1,0,0>, a=< 0,0,0>",
+        )
+        .stdout()
+        .is("1\n0: 2\n1: 2\n2: 2")
+        .unwrap();
+}
+
+
+#[test]
+fn accelerating_collision() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-d"])
+        .stdin(
+            "\
+p=<0,0,0>, v=<0,0,0>, a=<2,0,0>
+p=<6,0,0>, v=<0,0,0>, a=<0,0,0>
+p=<6,1,0>, v=<0,0,0>, a=<0,0,0>",
+        )
+        .stdout()
+        .is("1\n0: 2\n1: 2")
+        .unwrap();
+}
+
+
+#[test]
+fn sample1_export_csv() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-e", "csv", "--from", "1", "--ids", "1,3"])
+        .stdin(
+            "\
+p=<-6,0,0>, v=< 3,0,0>, a=< 0,0,0>
+p=<-4,0,0>, v=< 2,0,0>, a=< 0,0,0>
+p=<-2,0,0>, v=< 1,0,0>, a=< 0,0,0>
+p=< 3,0,0>, v=<-1,0,0>, a=< 0,0,0>",
+        )
+        .stdout()
+        .is(
+            "\
+tick,id,px,py,pz,vx,vy,vz,ax,ay,az,state
+1,1,-2,0,0,2,0,0,0,0,0,alive
+1,3,2,0,0,-1,0,0,0,0,0,alive
+2,1,0,0,0,2,0,0,0,0,0,collided
+2,3,1,0,0,-1,0,0,0,0,0,alive",
+        )
+        .unwrap();
+}
+
+
+#[test]
+fn sample1_export_json_lines() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-e", "jsonl", "--ids", "3", "--to", "4", "--stride", "2"])
+        .stdin(
+            "\
+p=<-6,0,0>, v=< 3,0,0>, a=< 0,0,0>
+p=<-4,0,0>, v=< 2,0,0>, a=< 0,0,0>
+p=<-2,0,0>, v=< 1,0,0>, a=< 0,0,0>
+p=< 3,0,0>, v=<-1,0,0>, a=< 0,0,0>",
+        )
+        .stdout()
+        .is(
+            "\
+{\"tick\":0,\"id\":3,\"position\":[3,0,0],\"velocity\":[-1,0,0],\"acceleration\":[0,0,0],\"alive\":true}
+{\"tick\":2,\"id\":3,\"position\":[1,0,0],\"velocity\":[-1,0,0],\"acceleration\":[0,0,0],\"alive\":true}
+{\"tick\":4,\"id\":3,\"position\":[-1,0,0],\"velocity\":[-1,0,0],\"acceleration\":[0,0,0],\"alive\":true}",
+        )
+        .unwrap();
+}
+
+
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
@@ -24,3 +103,16 @@ fn puzzle1() {
         .is("404")
         .unwrap();
 }
+
+#[test]
+fn extreme_values() {
+    assert_cli::Assert::main_binary()
+        .stdin(
+            "\
+p=<9223372036854775807,0,0>, v=<-9223372036854775808,0,0>, a=<9223372036854775807,0,0>
+p=<-9223372036854775808,0,0>, v=<9223372036854775807,0,0>, a=<-9223372036854775808,0,0>",
+        )
+        .stdout()
+        .is("2")
+        .unwrap();
+}
@@ -12,30 +12,84 @@ use rayon::prelude::*;
 
 use std::{io, process};
 use std::io::BufRead;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::str::FromStr;
 use std::str;
 use std::char;
 use std::ops::*;
 use std::fmt;
+use std::cmp::min;
 
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
 extern crate aoc2017;
+
+use structopt::StructOpt;
+
 use aoc2017::matrix::*;
 
-fn read_input() -> Result<(usize, Vec<(Matrix, Matrix)>), Error> {
+/// A rule as given in the input, with the line it was given on.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Template {
+    line: usize,
+    source: Matrix,
+    target: Matrix,
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} => {}",
+            pattern_string(&self.source),
+            pattern_string(&self.target)
+        )
+    }
+}
+
+/// The pattern in the slash-separated form used in the input.
+fn pattern_string(pattern: &Matrix) -> String {
+    pattern.to_string().trim().replace('\n', "/")
+}
+
+/// All rotations and flips of pattern.
+fn symmetries(pattern: &Matrix) -> Vec<Matrix> {
+    let mut result = Vec::with_capacity(8);
+    let mut transformed = pattern.clone();
+    for _ in 0..4 {
+        let next_transformed = transformed.rot90();
+        result.push(transformed.flip());
+        result.push(transformed);
+        transformed = next_transformed;
+    }
+    result
+}
+
+/// The smallest of the symmetries of pattern, used to identify patterns that are
+/// equal up to symmetry.
+fn canonical(pattern: &Matrix) -> Matrix {
+    symmetries(pattern).into_iter().min().unwrap()
+}
+
+fn read_input() -> Result<(usize, Vec<Template>), Error> {
     let stdin = io::stdin();
     let mut lines = stdin.lock().lines();
 
     let iterations = lines.next().unwrap()?.parse()?;
 
     let mut rules = Vec::new();
-    for line in lines {
+    for (number, line) in lines.enumerate() {
         let line = line?;
         if !line.is_empty() {
             let mut parts = line.split(" => ");
             let source = parts.next().unwrap().parse()?;
             let target = parts.next().unwrap().parse()?;
-            rules.push((source, target));
+            rules.push(Template {
+                line: number + 2,
+                source,
+                target,
+            });
         }
     }
 
@@ -51,23 +105,183 @@ fn print_rule(s: &Matrix, t: &Matrix) {
 
 }
 
-fn run() -> Result<(), Error> {
-    let (iterations, rule_templates) = read_input()?;
+/// Which rules are used and which patterns lack a rule when enhancing from a
+/// start pattern.
+#[derive(Debug, Clone)]
+struct Coverage {
+    /// For each template, whether it is used.
+    used: Vec<bool>,
+    /// Patterns without a rule, up to symmetry, and the first iteration needing
+    /// them.
+    missing: BTreeMap<Matrix, usize>,
+}
+
+/// The enhancement rules, with every rotation and flip of each source pattern.
+///
+/// When several templates match the same pattern, the last one is used.
+struct Rules {
+    templates: Vec<Template>,
+    rules: HashMap<Matrix, usize>,
+    /// Pairs of templates whose sources are equal up to symmetry but whose
+    /// targets differ.
+    contradictions: Vec<(usize, usize)>,
+}
 
-    let mut rules: HashMap<Matrix, Matrix> = HashMap::with_capacity(8 * rule_templates.len());
-    for (source, target) in rule_templates {
-        let mut transformed_source = source;
-        for _ in 0..4 {
-            let next_transformed_source = transformed_source.rot90();
-            rules.insert(transformed_source.flip(), target.clone());
-            rules.insert(transformed_source, target.clone());
-            transformed_source = next_transformed_source;
+impl Rules {
+    fn new(templates: Vec<Template>) -> Rules {
+        let mut rules: HashMap<Matrix, usize> = HashMap::with_capacity(8 * templates.len());
+        let mut contradictions = Vec::new();
+        for (index, template) in templates.iter().enumerate() {
+            for transformed_source in symmetries(&template.source) {
+                if let Some(previous) = rules.insert(transformed_source, index) {
+                    if previous != index && templates[previous].target != template.target
+                        && !contradictions.contains(&(previous, index))
+                    {
+                        contradictions.push((previous, index));
+                    }
+                }
+            }
         }
+        Rules {
+            templates,
+            rules,
+            contradictions,
+        }
+    }
+
+    fn target(&self, pattern: &Matrix) -> Option<&Matrix> {
+        self.rules
+            .get(pattern)
+            .map(|&index| &self.templates[index].target)
     }
 
-    let mut pattern: Matrix = ".#./..#/###".parse()?;
+    /// Look up the target of pattern, which is enhanced in iteration, recording
+    /// the rule used or the pattern missing.
+    fn cover(&self, pattern: &Matrix, iteration: usize, coverage: &mut Coverage) -> Option<&Matrix> {
+        match self.rules.get(pattern) {
+            Some(&index) => {
+                coverage.used[index] = true;
+                Some(&self.templates[index].target)
+            }
+            None => {
+                let first = coverage.missing.entry(canonical(pattern)).or_insert(iteration);
+                *first = min(*first, iteration);
+                None
+            }
+        }
+    }
+
+    /// Enhance the square blocks of pattern, which is enhanced in iteration, into
+    /// a pattern of side size. Returns None if some block lacks a rule.
+    fn cover_blocks(
+        &self,
+        pattern: &Matrix,
+        stride: usize,
+        iteration: usize,
+        coverage: &mut Coverage,
+    ) -> Option<Matrix> {
+        let steps = pattern.width / stride;
+        let target_stride = stride + 1;
+        let mut next_pattern = Matrix::new(steps * target_stride, steps * target_stride);
+        let mut complete = true;
+        for square_x in 0..steps {
+            for square_y in 0..steps {
+                let square = pattern.slice(square_x * stride, square_y * stride, stride, stride);
+                match self.cover(&square, iteration, coverage) {
+                    Some(target) => {
+                        next_pattern.fill_from(square_x * target_stride, square_y * target_stride, target)
+                    }
+                    None => complete = false,
+                }
+            }
+        }
+        if complete {
+            Some(next_pattern)
+        } else {
+            None
+        }
+    }
+
+    /// Find the rules used and the patterns missing when enhancing the 3x3 start
+    /// pattern iterations times.
+    ///
+    /// As for `BlockCounter`, every third iteration the pattern consists of 3x3
+    /// blocks that evolve independently, so only the distinct blocks at each such
+    /// iteration are followed.
+    fn coverage(&self, start: &Matrix, iterations: usize) -> Coverage {
+        let mut coverage = Coverage {
+            used: vec![false; self.templates.len()],
+            missing: BTreeMap::new(),
+        };
+        let mut blocks: BTreeSet<Matrix> = BTreeSet::new();
+        blocks.insert(canonical(start));
+        let mut iteration = 0;
+        while iteration < iterations && !blocks.is_empty() {
+            let mut next_blocks = BTreeSet::new();
+            for block in &blocks {
+                let mut pattern = block.clone();
+                let mut stride = 3;
+                let mut complete = true;
+                for offset in 0..3 {
+                    if iteration + offset >= iterations {
+                        complete = false;
+                        break;
+                    }
+                    match self.cover_blocks(&pattern, stride, iteration + offset, &mut coverage) {
+                        Some(next_pattern) => pattern = next_pattern,
+                        None => {
+                            complete = false;
+                            break;
+                        }
+                    }
+                    stride = 2;
+                }
+                if complete {
+                    for square_x in 0..3 {
+                        for square_y in 0..3 {
+                            next_blocks.insert(canonical(&pattern.slice(square_x * 3, square_y * 3, 3, 3)));
+                        }
+                    }
+                }
+            }
+            blocks = next_blocks;
+            iteration += 3;
+        }
+        coverage
+    }
 
-    for _ in 0..iterations {
+    /// Check upfront that the rules are consistent and cover every pattern
+    /// needed for iterations.
+    fn validate(&self, start: &Matrix, iterations: usize) -> Result<(), Error> {
+        let mut problems: Vec<String> = self.contradictions
+            .iter()
+            .map(|&(first, second)| self.contradiction(first, second))
+            .collect();
+        let coverage = self.coverage(start, iterations);
+        problems.extend(
+            coverage
+                .missing
+                .iter()
+                .map(|(pattern, &iteration)| missing(pattern, iteration)),
+        );
+        ensure!(problems.is_empty(), "{}", problems.join("\n"));
+        Ok(())
+    }
+
+    fn contradiction(&self, first: usize, second: usize) -> String {
+        let (first, second) = (&self.templates[first], &self.templates[second]);
+        format!(
+            "Contradiction: rules on lines {} and {} match {} but give {} and {}",
+            first.line,
+            second.line,
+            pattern_string(&canonical(&second.source)),
+            pattern_string(&first.target),
+            pattern_string(&second.target)
+        )
+    }
+
+    /// Apply one iteration of the rules to pattern.
+    fn enhance(&self, pattern: &Matrix) -> Result<Matrix, Error> {
         assert_eq!(pattern.height, pattern.width, "Must be square patterns");
         let source_wh = pattern.width;
         let source_stride = if source_wh % 2 == 0 { 2 } else { 3 };
@@ -83,7 +297,7 @@ fn run() -> Result<(), Error> {
                     source_stride,
                     source_stride,
                 );
-                if let Some(target_square) = rules.get(&source_square) {
+                if let Some(target_square) = self.target(&source_square) {
                     next_pattern.fill_from(
                         square_x * target_stride,
                         square_y * target_stride,
@@ -94,8 +308,127 @@ fn run() -> Result<(), Error> {
                 }
             }
         }
-        pattern = next_pattern;
+        Ok(next_pattern)
+    }
+
+    fn simulate(&self, pattern: &Matrix, iterations: usize) -> Result<Matrix, Error> {
+        let mut pattern = pattern.clone();
+        for _ in 0..iterations {
+            pattern = self.enhance(&pattern)?;
+        }
+        Ok(pattern)
+    }
+}
+
+fn missing(pattern: &Matrix, iteration: usize) -> String {
+    format!(
+        "Missing: no rule matches {} (first needed in iteration {})",
+        pattern_string(pattern),
+        iteration + 1
+    )
+}
+
+/// Counts pixels that are on after a number of iterations, without building the
+/// full pattern.
+///
+/// A pattern with a side that is a power of three is split into 3x3 blocks, and
+/// the following three iterations take each block through 4x4 and 6x6 to 9x9
+/// without any rule spanning two blocks. The 9x9 result is again split into 3x3
+/// blocks, so the count for a 3x3 block only depends on the block and the number
+/// of iterations left, which is memoised.
+struct BlockCounter<'a> {
+    rules: &'a Rules,
+    memo: HashMap<(Matrix, usize), u128>,
+}
+
+impl<'a> BlockCounter<'a> {
+    fn new(rules: &'a Rules) -> BlockCounter<'a> {
+        BlockCounter {
+            rules,
+            memo: HashMap::new(),
+        }
+    }
+
+    /// The number of pixels on after iterations, starting from the 3x3 block.
+    fn count(&mut self, block: &Matrix, iterations: usize) -> Result<u128, Error> {
+        assert!(block.width == 3 && block.height == 3, "Must be 3x3 blocks");
+        if iterations < 3 {
+            return Ok(self.rules.simulate(block, iterations)?.count_true() as u128);
+        }
+        if let Some(&count) = self.memo.get(&(block.clone(), iterations)) {
+            return Ok(count);
+        }
+
+        let pattern = self.rules.simulate(block, 3)?;
+        let mut count: u128 = 0;
+        for square_x in 0..3 {
+            for square_y in 0..3 {
+                let square = pattern.slice(square_x * 3, square_y * 3, 3, 3);
+                let square_count = self.count(&square, iterations - 3)?;
+                count = match count.checked_add(square_count) {
+                    Some(count) => count,
+                    None => bail!("Too many pixels on after {} iterations to count", iterations),
+                };
+            }
+        }
+
+        self.memo.insert((block.clone(), iterations), count);
+        Ok(count)
     }
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "day21", about = "Solver for day 21.")]
+struct Opt {
+    #[structopt(short = "i", long = "iterations", help = "Number of iterations (default from input)")]
+    iterations: Option<usize>,
+    #[structopt(short = "m", long = "memoised",
+                help = "Count pixels over memoised 3x3 blocks instead of building the pattern")]
+    memoised: bool,
+    #[structopt(short = "v", long = "validate",
+                help = "Report contradictory, missing and unused rules instead of counting")]
+    validate: bool,
+}
+
+fn run() -> Result<(), Error> {
+    let opt = Opt::from_args();
+    let (iterations, rule_templates) = read_input()?;
+    let iterations = opt.iterations.unwrap_or(iterations);
+
+    let rules = Rules::new(rule_templates);
+
+    let pattern: Matrix = ".#./..#/###".parse()?;
+
+    if opt.validate {
+        for &(first, second) in &rules.contradictions {
+            println!("{}", rules.contradiction(first, second));
+        }
+        let coverage = rules.coverage(&pattern, iterations);
+        for (pattern, &iteration) in &coverage.missing {
+            println!("{}", missing(pattern, iteration));
+        }
+        for (template, &used) in rules.templates.iter().zip(coverage.used.iter()) {
+            if !used {
+                println!(
+                    "Unused: rule on line {} ({}) in {} iterations",
+                    template.line,
+                    template,
+                    iterations
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    rules.validate(&pattern, iterations)?;
+
+    if opt.memoised {
+        let mut counter = BlockCounter::new(&rules);
+        println!("{}", counter.count(&pattern, iterations)?);
+        return Ok(());
+    }
+
+    let pattern = rules.simulate(&pattern, iterations)?;
 
     //println!("{}", pattern);
     println!("{}", pattern.count_true());
@@ -32,3 +32,95 @@ fn puzzle2() {
         .is("2536879")
         .unwrap();
 }
+
+#[test]
+fn sample1_memoised() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-m"])
+        .stdin(
+            "\
+2
+../.# => ##./#../...
+.#./..#/### => #..#/..../..../#..#",
+        )
+        .stdout()
+        .is("12")
+        .unwrap();
+}
+
+#[test]
+fn puzzle2_memoised() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-m"])
+        .stdin(include_str!("../data/puzzle2.in"))
+        .stdout()
+        .is("2536879")
+        .unwrap();
+}
+
+#[test]
+fn puzzle1_simulated_12() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-i", "12"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("31339")
+        .unwrap();
+}
+
+#[test]
+fn puzzle1_memoised_12() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-m", "-i", "12"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("31339")
+        .unwrap();
+}
+
+#[test]
+fn puzzle1_memoised_100() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-m", "-i", "100"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("216712999407739349649123577552749")
+        .unwrap();
+}
+
+#[test]
+fn validate_report() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-v"])
+        .stdin(
+            "\
+3
+../.# => ##./#../...
+.#./..#/### => #..#/..../..../#..#
+#./.. => ##./##./...",
+        )
+        .stdout()
+        .is(
+            "\
+Contradiction: rules on lines 2 and 4 match ../.# but give ##./#../... and ##./##./...
+Missing: no rule matches ../## (first needed in iteration 3)
+Missing: no rule matches ##/## (first needed in iteration 3)
+Unused: rule on line 2 (../.# => ##./#../...) in 3 iterations",
+        )
+        .unwrap();
+}
+
+#[test]
+fn missing_rule_upfront() {
+    assert_cli::Assert::main_binary()
+        .stdin(
+            "\
+3
+../.# => ##./#../...
+.#./..#/### => #..#/..../..../#..#",
+        )
+        .fails()
+        .stderr()
+        .contains("Missing: no rule matches ../## (first needed in iteration 3)")
+        .unwrap();
+}
@@ -14,6 +14,82 @@ fn sample1() {
 }
 
 
+#[test]
+fn sample1_states() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-s"])
+        .stdin(
+            "..#
+#..
+...",
+        )
+        .stdout()
+        .is("clean: 4413
+infected: 5587")
+        .unwrap();
+}
+
+#[test]
+fn langtons_ant() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-a", "LLRR", "-s"])
+        .stdin("")
+        .stdout()
+        .is("0: 2361
+1: 2642
+2: 2577
+3: 2420")
+        .unwrap();
+}
+
+#[test]
+fn unknown_turn() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-r", "tests/bad_rules.txt"])
+        .stdin("#")
+        .fails()
+        .stderr()
+        .contains("Line 3: Unknown turn \"sideways\"")
+        .unwrap();
+}
+
+
+#[test]
+fn sample1_70_steps() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-n", "70"])
+        .stdin(
+            "..#
+#..
+...",
+        )
+        .stdout()
+        .is("41")
+        .unwrap();
+}
+
+
+#[test]
+fn sample1_playback() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-n", "1", "-e", "1", "-w", "1"])
+        .stdin(
+            "..#
+#..
+...",
+        )
+        .stdout()
+        .contains(
+            "\x1b[2J\x1b[H1 bursts, at (0, 1) facing West, clean 0, infected 1
+\x1b[90m.\x1b[0m \x1b[90m.\x1b[0m \x1b[90m.\x1b[0m 
+\x1b[90m.\x1b[0m \x1b[1;7;31m<\x1b[0m \x1b[31m#\x1b[0m 
+\x1b[90m.\x1b[0m \x1b[90m.\x1b[0m \x1b[90m.\x1b[0m 
+",
+        )
+        .unwrap();
+}
+
+
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
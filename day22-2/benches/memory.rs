@@ -0,0 +1,58 @@
+//! Memory use of the evolved carrier on the puzzle input, for 100M bursts.
+//!
+//! Run with `cargo bench`. Reports, at every checkpoint, the number of stored
+//! nodes, the capacity of the grid, an estimate of the memory used by the grid,
+//! and the peak resident set size of the process where available.
+
+extern crate aoc2017;
+
+use aoc2017::carrier::{Carrier, Grid, Position, Rules};
+
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::time::Instant;
+
+const STEPS: u64 = 100_000_000;
+const CHECKPOINTS: u64 = 10;
+
+/// Peak resident set size as reported by Linux, if available.
+fn peak_memory() -> Option<String> {
+    let mut status = String::new();
+    File::open("/proc/self/status")
+        .ok()?
+        .read_to_string(&mut status)
+        .ok()?;
+    status
+        .lines()
+        .find(|line| line.starts_with("VmHWM:"))
+        .map(|line| line["VmHWM:".len()..].trim().to_string())
+}
+
+fn main() {
+    let rules: Rules = include_str!("../data/rules.txt").parse().unwrap();
+    let (center, grid) = Grid::parse(include_str!("../data/puzzle1.in"), &rules).unwrap();
+    let mut carrier = Carrier::new(rules, grid, center);
+
+    // Key, value and one control byte per bucket.
+    let bucket_size = mem::size_of::<(Position, usize)>() + 1;
+
+    println!(
+        "{:>11} {:>10} {:>10} {:>10} {:>10} {:>8}",
+        "steps", "nodes", "capacity", "grid MiB", "peak RSS", "seconds"
+    );
+    let start = Instant::now();
+    for _ in 0..CHECKPOINTS {
+        carrier.run(STEPS / CHECKPOINTS).unwrap();
+        let elapsed = start.elapsed();
+        println!(
+            "{:>11} {:>10} {:>10} {:>10.1} {:>10} {:>8.1}",
+            carrier.steps,
+            carrier.grid.len(),
+            carrier.grid.capacity(),
+            (carrier.grid.capacity() * bucket_size) as f64 / (1024.0 * 1024.0),
+            peak_memory().unwrap_or_else(|| "-".to_string()),
+            elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9
+        );
+    }
+}
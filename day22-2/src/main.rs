@@ -9,9 +9,18 @@ extern crate strum_macros;
 
 extern crate rayon;
 use rayon::prelude::*;
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+extern crate aoc2017;
+
+use structopt::StructOpt;
+
+use aoc2017::carrier::{Carrier, Grid, Position, Rules};
 
 use std::{io, process};
-use std::io::BufRead;
+use std::io::{BufRead, BufWriter, Read, Write};
+use std::fs::File;
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::str;
@@ -20,191 +29,99 @@ use std::ops::*;
 use std::fmt;
 use std::cmp::{min, max};
 
-const STEPS: usize = 10_000_000;
+const STEPS: u64 = 10_000_000;
 
-type Scalar = i16;
-
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
-struct Position {
-    x: Scalar,
-    y: Scalar,
-}
+const RULES: &str = include_str!("../data/rules.txt");
 
-impl Position {
-    fn new(x: Scalar, y: Scalar) -> Position {
-        Position { x, y }
+fn read_rules(opt: &Opt) -> Result<Rules, Error> {
+    if let Some(ref turns) = opt.ant {
+        return Rules::langtons_ant(turns);
     }
-
-    fn step(&self, direction: Direction) -> Position {
-        let (x, y) = match direction {
-            North => (self.x, self.y - 1),
-            South => (self.x, self.y + 1),
-            West => (self.x - 1, self.y),
-            East => (self.x + 1, self.y),
-        };
-        Position { x, y }
+    if let Some(ref path) = opt.rules {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        return text.parse();
     }
+    RULES.parse()
 }
 
-#[derive(EnumString, Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
-enum Direction {
-    North,
-    South,
-    East,
-    West,
-}
-
-use Direction::*;
-
-#[derive(EnumString, Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
-enum Turn {
-    Left,
-    Right,
-    Same,
-    Back,
-}
-
-use Turn::*;
-
-impl Direction {
-    fn back(&self) -> Direction {
-        match *self {
-            North => South,
-            South => North,
-            East => West,
-            West => East,
-        }
-    }
-
-    fn turn(&self, turn: Turn) -> Direction {
-        match (*self, turn) {
-            (North, Left) => West,
-            (North, Right) => East,
-            (South, Left) => East,
-            (South, Right) => West,
-            (East, Left) => North,
-            (East, Right) => South,
-            (West, Left) => South,
-            (West, Right) => North,
-            (_, Same) => *self,
-            (_, Back) => self.back(),
-        }
-    }
+fn read_input(rules: &Rules) -> Result<(Position, Grid), Error> {
+    let mut map = String::new();
+    io::stdin().read_to_string(&mut map)?;
+    Grid::parse(&map, rules)
 }
 
-#[derive(EnumString, Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
-enum State {
-    Clean,
-    Weakened,
-    Infected,
-    Flagged,
+#[derive(StructOpt, Debug)]
+#[structopt(name = "day22-2", about = "Solver for day 22, part 2.")]
+struct Opt {
+    #[structopt(short = "r", long = "rules",
+                help = "Read the carrier rules from a file, one \"name symbol turn next\" per line")]
+    rules: Option<String>,
+    #[structopt(short = "a", long = "ant",
+                help = "Run a Langton's ant with one state per turn, e.g. RL or LLRR")]
+    ant: Option<String>,
+    #[structopt(short = "c", long = "count", help = "State to count infections into",
+                default_value = "infected")]
+    count: String,
+    #[structopt(short = "s", long = "states",
+                help = "Print the number of nodes changed into each state instead")]
+    states: bool,
+    #[structopt(short = "n", long = "steps", help = "Number of bursts to run (default 10000000)")]
+    steps: Option<u64>,
+    #[structopt(short = "e", long = "every",
+                help = "Render the grid around the carrier as ANSI text every n bursts")]
+    every: Option<u64>,
+    #[structopt(short = "w", long = "window", help = "Nodes shown on each side of the carrier",
+                default_value = "12")]
+    window: i32,
+    #[structopt(short = "o", long = "output",
+                help = "Write the rendered frames to a file instead of standard output")]
+    output: Option<String>,
 }
 
-use State::*;
-
-impl State {
-    fn step(&self) -> State {
-        match *self {
-            Clean => Weakened,
-            Weakened => Infected,
-            Infected => Flagged,
-            Flagged => Clean,
-        }
+/// Run the carrier, writing a frame before the first burst and after every
+/// every bursts.
+fn play(carrier: &mut Carrier, steps: u64, every: u64, window: i32, output: &mut Write) -> Result<(), Error> {
+    ensure!(every > 0, "Frames must be at least one burst apart");
+    output.write_all(carrier.render(window).as_bytes())?;
+    let mut remaining = steps;
+    while remaining > 0 {
+        let bursts = min(every, remaining);
+        carrier.run(bursts)?;
+        remaining -= bursts;
+        output.write_all(carrier.render(window).as_bytes())?;
     }
+    output.flush()?;
+    Ok(())
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-struct Grid {
-    states: HashMap<Position, State>,
-    min_x: Scalar,
-    min_y: Scalar,
-    max_x: Scalar,
-    max_y: Scalar,
-}
-
-impl Grid {
-    fn new() -> Grid {
-        let mut states = HashMap::with_capacity(STEPS);
-        states.insert(Position::new(0, 0), Clean);
-        Grid {
-            states,
-            min_x: 0,
-            min_y: 0,
-            max_x: 0,
-            max_y: 0,
+fn run() -> Result<(), Error> {
+    let opt = Opt::from_args();
+    let rules = read_rules(&opt)?;
+    let (center, grid) = read_input(&rules)?;
+
+    let mut carrier = Carrier::new(rules, grid, center);
+    let steps = opt.steps.unwrap_or(STEPS);
+    if let Some(every) = opt.every {
+        match opt.output {
+            Some(ref path) => play(&mut carrier, steps, every, opt.window, &mut BufWriter::new(File::create(path)?))?,
+            None => play(&mut carrier, steps, every, opt.window, &mut io::stdout())?,
         }
+    } else {
+        carrier.run(steps)?;
     }
 
-    fn update_bounds(&mut self, position: Position) {
-        self.min_x = min(self.min_x, position.x);
-        self.min_y = min(self.min_y, position.y);
-        self.max_x = max(self.max_x, position.x);
-        self.max_y = max(self.max_y, position.y);
-    }
-
-    fn set(&mut self, position: Position, state: State) {
-        self.update_bounds(position);
-        self.states.insert(position, state);
-    }
-
-    fn get(&mut self, position: Position) -> State {
-        self.update_bounds(position);
-        *self.states.get(&position).unwrap_or(&Clean)
-    }
-}
-
-fn read_input() -> Result<(Position, Grid), Error> {
-    let stdin = io::stdin();
-    let lines = stdin.lock().lines();
-
-    let mut max_col = 0;
-    let mut max_row = 0;
-    let mut grid = Grid::new();
-    for (row, line) in lines.enumerate() {
-        let line = line?;
-        for (col, ch) in line.chars().enumerate() {
-            let position = Position::new(col as Scalar, row as Scalar);
-            if ch == '#' {
-                grid.set(position, Infected);
-            } else {
-                grid.set(position, Clean);
-            }
-            max_col = max(max_col, col);
+    if opt.states {
+        for (rule, count) in carrier.rules.iter().zip(carrier.counts.iter()) {
+            println!("{}: {}", rule.name, count);
         }
-        max_row = max(max_row, row);
-    }
-
-    let center = Position::new(max_col as Scalar / 2, max_row as Scalar / 2);
-
-    Ok((center, grid))
-}
-
-
-
-fn run() -> Result<(), Error> {
-    let (center, start_grid) = read_input()?;
-
-    let mut infection_count = 0;
-    let mut grid = start_grid.clone();
-    let mut position = center;
-    let mut direction = North;
-    for _ in 0..STEPS {
-        let turn = match grid.get(position) {
-            Clean => Left,
-            Weakened => Same,
-            Infected => Right,
-            Flagged => Back,
-        };
-        let next_state = grid.get(position).step();
-        if next_state == Infected {
-            infection_count += 1;
+    } else {
+        match carrier.rules.state(&opt.count) {
+            Some(state) => println!("{}", carrier.counts[state]),
+            None => bail!("Unknown state {}", opt.count),
         }
-        grid.set(position, next_state);
-        direction = direction.turn(turn);
-        position = position.step(direction);
     }
 
-    println!("{}", infection_count);
     Ok(())
 }
 
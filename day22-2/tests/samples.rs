@@ -14,6 +14,39 @@ fn sample1() {
 }
 
 
+#[test]
+fn sample1_states() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-s"])
+        .stdin(
+            "..#
+#..
+...",
+        )
+        .stdout()
+        .is("clean: 2463157
+weakened: 2537219
+infected: 2511944
+flagged: 2487680")
+        .unwrap();
+}
+
+
+#[test]
+fn sample1_100_steps() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-n", "100"])
+        .stdin(
+            "..#
+#..
+...",
+        )
+        .stdout()
+        .is("26")
+        .unwrap();
+}
+
+
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
@@ -0,0 +1,35 @@
+//! The reference and the fast interpreter on the puzzle input.
+//!
+//! Run with `cargo bench`. Runs the interpreters in turn a number of times,
+//! checks that they count the same number of `mul` instructions and end in the
+//! same state, and reports the time of the fastest run and the speedup.
+
+extern crate aoc2017;
+
+mod timing;
+
+use aoc2017::vm::*;
+use aoc2017::vm::fast::FastMachine;
+
+fn main() {
+    let program = parse_program(include_str!("../data/puzzle1.in")).unwrap();
+
+    let ((reference_time, reference), (fast_time, fast)) = timing::race(
+        || {
+            let mut machine = Machine::new(program.clone(), Coprocessor);
+            machine.run().unwrap();
+            (machine.count(Opcode::Mul), machine.steps(), machine.registers.clone(), machine.pc)
+        },
+        || {
+            let mut machine = FastMachine::new(program.clone(), Coprocessor);
+            machine.run().unwrap();
+            (machine.count(Opcode::Mul), machine.steps(), machine.registers(), machine.pc())
+        },
+    );
+    assert_eq!(reference, fast);
+
+    println!("{:>10} {:>10} {:>10}", "engine", "mul", "ms/run");
+    println!("{:>10} {:>10} {:>10.3}", "reference", reference.0, reference_time * 1e3);
+    println!("{:>10} {:>10} {:>10.3}", "fast", fast.0, fast_time * 1e3);
+    println!("speedup {:.1}x", reference_time / fast_time);
+}
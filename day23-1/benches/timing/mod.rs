@@ -0,0 +1,40 @@
+//! Timing for the benchmarks of the fast interpreter against the reference,
+//! shared with the benchmark of day 18, part 2.
+
+use std::time::{Duration, Instant};
+
+/// The number of times each side is run.
+pub const RUNS: u32 = 100;
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+fn time<T, F: FnMut() -> T>(f: &mut F) -> (f64, T) {
+    let start = Instant::now();
+    let result = f();
+    (seconds(start.elapsed()), result)
+}
+
+/// The times of the fastest runs of first and of second, with their results.
+///
+/// The fastest run is the one least disturbed by the rest of the system. The
+/// runs alternate, so that a change in the speed of the system while they run
+/// affects both sides the same.
+pub fn race<A, B, F, G>(mut first: F, mut second: G) -> ((f64, A), (f64, B))
+where
+    F: FnMut() -> A,
+    G: FnMut() -> B,
+{
+    let (mut first_fastest, mut first_result) = time(&mut first);
+    let (mut second_fastest, mut second_result) = time(&mut second);
+    for _ in 1..RUNS {
+        let (elapsed, result) = time(&mut first);
+        first_fastest = first_fastest.min(elapsed);
+        first_result = result;
+        let (elapsed, result) = time(&mut second);
+        second_fastest = second_fastest.min(elapsed);
+        second_result = result;
+    }
+    ((first_fastest, first_result), (second_fastest, second_result))
+}
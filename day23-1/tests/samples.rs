@@ -21,11 +21,100 @@ extern crate assert_cli;
 //        .unwrap();
 //}
 
+// With a=0 the program runs its inner loops once for b=79, executing
+// `mul g d e` for every d and e in 2..79, 77 * 77 = 5929 times. The 1187
+// expected before the shared VM does not match this input.
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
         .stdin(include_str!("../data/puzzle1.in"))
         .stdout()
-        .is("1187")
+        .is("5929")
+        .unwrap();
+}
+
+#[test]
+fn puzzle1_fast() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--fast"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("5929")
+        .unwrap();
+}
+
+#[test]
+fn no_sound() {
+    assert_cli::Assert::main_binary()
+        .stdin(
+            "\
+set a 1
+snd a",
+        )
+        .fails()
+        .stderr()
+        .contains("The coprocessor does not support snd")
+        .unwrap();
+}
+
+#[test]
+fn no_sound_fast() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-f"])
+        .stdin(
+            "\
+set a 1
+snd a",
+        )
+        .fails()
+        .stderr()
+        .contains("The coprocessor does not support snd")
+        .unwrap();
+}
+
+#[test]
+fn profile_text() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-p", "text"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("5929")
+        .stderr()
+        .contains(
+            "\
+            77   0.2%   10: set e 2
+          5929  12.4%   11: set g d
+          5929  12.4%   12: mul g e",
+        )
+        .unwrap();
+}
+
+#[test]
+fn profile_trace() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-p", "text", "-t", "3"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stderr()
+        .contains(
+            "\
+trace:
+         47825   27: sub g c             g=0
+         47826   28: jnz g 2
+         47827   29: jnz 1 3
+",
+        )
+        .unwrap();
+}
+
+#[test]
+fn profile_json() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--profile", "json"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stderr()
+        .contains(
+            "{\"steps\":47828,\"opcodes\":{\"set\":12017,\"sub\":17942,\"mul\":5929,\"jnz\":11940},\
+             \"pcs\":[{\"pc\":0,\"instruction\":\"set b 79\",\"hits\":1},",
+        )
         .unwrap();
 }
@@ -0,0 +1,131 @@
+extern crate aoc2017;
+
+use aoc2017::vm::*;
+use aoc2017::vm::decompile::decompile;
+
+use std::{env, fs, process};
+use std::process::Command;
+
+/// Compile the decompiled program, run it with register a set, and give the
+/// registers and the last value sent when it stops.
+fn compiled(name: &str, program: &Program, a: Value) -> (Vec<Value>, Option<Value>) {
+    let directory = env::temp_dir().join(format!("day23-2-{}-{}", name, process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let source = directory.join("main.rs");
+    let binary = directory.join("main");
+    fs::write(
+        &source,
+        format!(
+            "{}
+fn main() {{
+    let mut registers = [0i64; 26];
+    registers[0] = {};
+    let mut sent = None;
+    program(&mut registers, |value| sent = Some(value), |current| if current != 0 {{ None }} else {{ Some(current) }});
+    println!(\"{{:?}}\", registers.iter().collect::<Vec<_>>());
+    match sent {{
+        Some(value) => println!(\"{{}}\", value),
+        None => println!(\"none\"),
+    }}
+}}
+",
+            decompile(program, "program"),
+            a
+        ),
+    ).unwrap();
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let status = Command::new(rustc)
+        .arg("-o")
+        .arg(&binary)
+        .arg(&source)
+        .status()
+        .unwrap();
+    assert!(status.success(), "Decompiled {} does not compile", name);
+    let output = Command::new(&binary).output().unwrap();
+    assert!(output.status.success(), "Decompiled {} failed", name);
+    fs::remove_dir_all(&directory).unwrap();
+
+    let output = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    let registers = lines[0]
+        .trim_matches(|ch| ch == '[' || ch == ']')
+        .split(", ")
+        .map(|value| value.parse().unwrap())
+        .collect();
+    let sent = match lines[1] {
+        "none" => None,
+        sent => Some(sent.parse().unwrap()),
+    };
+    (registers, sent)
+}
+
+/// Run the program in the interpreter, with the sound dialect.
+fn interpreted(program: &Program, a: Value) -> (Vec<Value>, Option<Value>) {
+    let mut machine = Machine::new(program.clone(), Sound::default());
+    machine.registers[RegisterId::from_name('a').unwrap()] = a;
+    machine.run().unwrap();
+    let registers = (0..REGISTERS)
+        .map(|index| machine.registers[RegisterId::from_index(index).unwrap()])
+        .collect();
+    (registers, machine.dialect.last_sound)
+}
+
+fn check(name: &str, text: &str, a: Value) {
+    let program = parse_program(text).unwrap();
+    assert_eq!(compiled(name, &program, a), interpreted(&program, a));
+}
+
+#[test]
+fn original_puzzle() {
+    check("original_puzzle", include_str!("../data/original_puzzle.in"), 0);
+}
+
+#[test]
+fn optimized_puzzle() {
+    check("optimized_puzzle", include_str!("../data/puzzle_optimized.in"), 0);
+}
+
+#[test]
+fn day18_puzzle() {
+    check("day18_puzzle", include_str!("../../day18-1/data/puzzle1.in"), 0);
+}
+
+#[test]
+fn day18_sample() {
+    check(
+        "day18_sample",
+        "\
+set a 1
+add a 2
+mul a a
+mod a 5
+snd a
+set a 0
+rcv a
+jgz a -1
+set a 1
+jgz a -2",
+        0,
+    );
+}
+
+#[test]
+fn countdown() {
+    check(
+        "countdown",
+        "\
+loop: jgz a 'body'
+jnz 1 'done'
+body: snd a
+mod b 3
+jnz b 'odd'
+add c a
+jnz 1 'next'
+odd: sub c a
+next: add b 1
+sub a 1
+jnz 1 'loop'
+done: set d c",
+        10,
+    );
+}
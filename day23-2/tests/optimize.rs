@@ -0,0 +1,193 @@
+extern crate aoc2017;
+
+use aoc2017::vm::*;
+use aoc2017::vm::optimize::{differential, execute, Optimizer, Outcome, Pass, Run};
+
+fn register(name: char) -> RegisterId {
+    RegisterId::from_name(name).unwrap()
+}
+
+fn run_with_a(a: Value) -> Run {
+    Run {
+        registers: vec![(register('a'), a)],
+        inputs: vec![],
+    }
+}
+
+fn uses(program: &Program, opcode: Opcode) -> bool {
+    program.iter().any(|instruction| instruction.opcode() == opcode)
+}
+
+/// The number of steps the program takes on the run.
+fn steps(program: &Program, run: &Run) -> u64 {
+    let mut machine = Machine::new(program.clone(), optimize::Recorder::default());
+    for &(id, value) in &run.registers {
+        machine.registers[id] = value;
+    }
+    machine.run().unwrap();
+    machine.steps()
+}
+
+/// Day 23 with the range of b shrunk, so that it runs quickly with a set.
+fn small(text: &str) -> String {
+    text.replace("mul b 100", "mul b 1")
+        .replace("sub b -100000", "sub b 0")
+        .replace("sub c -17000", "sub c -34")
+}
+
+#[test]
+fn original_puzzle() {
+    let program = parse_program(&small(include_str!("../data/original_puzzle.in"))).unwrap();
+    let runs = vec![run_with_a(0), run_with_a(1)];
+    let optimized = Optimizer::new(runs.clone()).optimize(&program).unwrap();
+    assert!(uses(&optimized, Opcode::Divides));
+    assert!(steps(&optimized, &runs[1]) * 10 < steps(&program, &runs[1]));
+}
+
+#[test]
+fn short_divides_loop() {
+    let program = parse_program(&small(include_str!("../data/puzzle2.in"))).unwrap();
+    let runs = vec![Run::default()];
+    let optimized = Optimizer::new(runs.clone()).optimize(&program).unwrap();
+    assert!(uses(&optimized, Opcode::Divides));
+    assert!(steps(&optimized, &runs[0]) * 10 < steps(&program, &runs[0]));
+}
+
+#[test]
+fn mul_add_loop() {
+    let program = parse_program(
+        "\
+set b 5
+loop: add c b
+sub a 1
+jnz a 'loop'
+snd c
+rcv d
+mul d 2
+mul d 1
+add d 0
+snd d",
+    ).unwrap();
+    let runs = vec![
+        Run {
+            registers: vec![(register('a'), 7)],
+            inputs: vec![3],
+        },
+        Run {
+            registers: vec![(register('a'), 1), (register('c'), -4)],
+            inputs: vec![-5],
+        },
+    ];
+    let optimized = Optimizer::new(runs.clone()).optimize(&program).unwrap();
+    assert!(uses(&optimized, Opcode::MulAdd));
+    assert_eq!(
+        execute(&optimized, &runs[0], 1000),
+        Outcome::Halted {
+            registers: vec![0, 5, 35, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            sent: vec![35, 6],
+        }
+    );
+}
+
+#[test]
+fn constant_folding() {
+    let program = parse_program(
+        "\
+set a 6
+set b a
+mul b 7
+jgz b 2
+snd 0
+snd b",
+    ).unwrap();
+    let optimized = Pass::ConstantFolding.apply(&program);
+    assert_eq!(
+        optimized,
+        parse_program("set a 6\nset b 6\nset b 42\njnz 1 2\nsnd 0\nsnd b").unwrap()
+    );
+    differential(&program, &optimized, &[Run::default()], 100).unwrap();
+}
+
+#[test]
+fn constant_folding_with_indirect_jumps() {
+    let program = parse_program("set b a\nadd b 1\njgz a b\nset h 5\nadd h 1").unwrap();
+    assert_eq!(Pass::ConstantFolding.apply(&program), program);
+    let optimized = Optimizer::new(vec![run_with_a(0)]).optimize(&program).unwrap();
+    assert_eq!(
+        execute(&optimized, &run_with_a(1), 100),
+        execute(&program, &run_with_a(1), 100)
+    );
+}
+
+#[test]
+fn strength_reduction_keeps_overflowing_mod() {
+    let program = parse_program("mod a -1\nmod b 1").unwrap();
+    assert_eq!(
+        Pass::StrengthReduction.apply(&program),
+        parse_program("mod a -1\nset b 0").unwrap()
+    );
+    let run = Run {
+        registers: vec![(register('a'), Value::min_value())],
+        inputs: vec![],
+    };
+    assert_eq!(execute(&program, &run, 100), Outcome::Failed);
+}
+
+#[test]
+fn dead_stores() {
+    let program = parse_program("set g 1\nset g 2\nsnd g\nset g 3\nset g 4").unwrap();
+    let optimized = Pass::DeadStores.apply(&program);
+    assert_eq!(optimized, parse_program("set g 2\nsnd g\nset g 4").unwrap());
+}
+
+#[test]
+fn indirect_jumps_unchanged() {
+    let program = parse_program(include_str!("../../day18-1/data/puzzle1.in")).unwrap();
+    let runs = vec![run_with_a(0)];
+    let optimized = Optimizer::new(runs).optimize(&program).unwrap();
+    assert_eq!(optimized.len(), program.len());
+}
+
+#[test]
+fn changed_behaviour_detected() {
+    let program = parse_program("set a 3\nloop: add b 2\nsub a 1\njnz a 'loop'").unwrap();
+    let broken = parse_program("set a 3\nmul-add b 3 a").unwrap();
+    assert!(differential(&program, &broken, &[Run::default()], 100).is_err());
+}
+
+#[test]
+fn parse_run() {
+    let run: Run = "a=1, c=-4".parse().unwrap();
+    assert_eq!(run.registers, vec![(register('a'), 1), (register('c'), -4)]);
+    assert!("a".parse::<Run>().is_err());
+    assert!("a=1=2".parse::<Run>().is_err());
+}
+
+#[test]
+fn nested_mul_add_loop() {
+    let program = parse_program(
+        "\
+outer: set c b
+inner: add a 2
+sub c 1
+jnz c 'inner'
+add d -1
+jnz d 'outer'
+snd a",
+    ).unwrap();
+    let run = |b: Value, d: Value| Run {
+        registers: vec![(register('b'), b), (register('d'), d)],
+        inputs: vec![],
+    };
+    let optimized = Optimizer::new(vec![run(3, 4), run(1, 1)]).optimize(&program).unwrap();
+    assert!(!uses(&optimized, Opcode::Jnz));
+    assert_eq!(
+        execute(&optimized, &run(5, 7), 100),
+        Outcome::Halted {
+            registers: vec![70, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            sent: vec![70],
+        }
+    );
+    assert_eq!(execute(&optimized, &run(0, 2), 100), Outcome::Failed);
+    assert_eq!(execute(&optimized, &run(2, 0), 100), Outcome::Failed);
+}
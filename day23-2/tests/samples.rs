@@ -1,5 +1,161 @@
 extern crate assert_cli;
 
+#[test]
+fn disassemble_original() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-d"])
+        .stdin(include_str!("../data/original_puzzle.in"))
+        .stdout()
+        .contains(
+            "\
+L5:
+    sub e -1
+    set g e
+    sub g b
+    jnz g 'L4'",
+        )
+        .unwrap();
+}
+
+
+#[test]
+fn pseudo_code_original() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-p"])
+        .stdin(include_str!("../data/original_puzzle.in"))
+        .stdout()
+        .contains(
+            "\
+    do {
+        e = 2
+        do {
+            g = d
+            g *= e
+            g -= b
+            if g == 0 {
+                f = 0
+            }
+            e += 1
+            g = e
+            g -= b
+        } while g != 0
+        d += 1
+        g = d
+        g -= b
+    } while g != 0
+    if f == 0 {
+        h += 1
+    }",
+        )
+        .unwrap();
+}
+
+#[test]
+fn graph_original() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-g"])
+        .stdin(include_str!("../data/original_puzzle.in"))
+        .stdout()
+        .contains(
+            "\
+    b7 -> b5 [color=green, style=dashed];
+    b7 -> b8 [color=red];
+    b8 -> b4 [color=green, style=dashed];",
+        )
+        .unwrap();
+}
+
+
+#[test]
+fn rust_optimized() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-r"])
+        .stdin(include_str!("../data/puzzle_optimized.in"))
+        .stdout()
+        .contains(
+            "\
+            loop {
+                m = b;
+                m %= d;
+                if m == 0 {
+                    f = 0;
+                }
+                d += 1;
+                if d == b {
+                    break;
+                }
+            }",
+        )
+        .unwrap();
+}
+
+
+#[test]
+fn optimize_original() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-O", "-d"])
+        .stdin(include_str!("../data/small_puzzle.in"))
+        .stdout()
+        .contains(
+            "\
+L3:
+    set e 2
+    divides f d b e
+    add d 1",
+        )
+        .unwrap();
+}
+
+#[test]
+fn optimize_small() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-O", "--check", "a=0"])
+        .stdin(include_str!("../data/small_puzzle.in"))
+        .stdout()
+        .is("1")
+        .unwrap();
+}
+
+#[test]
+fn optimize_checks_solved_run() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-O"])
+        .stdin(include_str!("../data/original_puzzle.in"))
+        .fails()
+        .stderr()
+        .contains("Run 1 does not end within 10000000 steps")
+        .unwrap();
+}
+
+#[test]
+fn optimize_checks_solved_run_with_other_runs() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-O", "--check", "a=0"])
+        .stdin(include_str!("../data/original_puzzle.in"))
+        .fails()
+        .stderr()
+        .contains("Run 1 does not end within 10000000 steps")
+        .unwrap();
+}
+
+#[test]
+fn optimize_indirect_jump() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-O", "--check", "a=0"])
+        .stdin(
+            "\
+set b a
+add b 1
+jgz a b
+set h 5
+add h 1",
+        )
+        .stdout()
+        .is("1")
+        .unwrap();
+}
+
+
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
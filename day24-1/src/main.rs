@@ -10,59 +10,17 @@ extern crate strum_macros;
 extern crate rayon;
 use rayon::prelude::*;
 
-use std::{io, process};
-use std::io::BufRead;
-use std::collections::{HashMap, HashSet};
-use std::str::FromStr;
-use std::str;
-use std::char;
-use std::ops::*;
-use std::fmt;
-use std::cmp::{min, max};
-
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
-struct Port {
-    side1: usize,
-    side2: usize,
-}
-
-impl Port {
-    fn value(&self) -> usize {
-        self.side1 + self.side2
-    }
-
-    fn other(&self, pin: usize) -> usize {
-        if self.side1 == pin {
-            self.side2
-        } else if self.side2 == pin {
-            self.side1
-        } else {
-            panic!("{} does not match {}", pin, self)
-        }
-    }
-
-    fn has_pin(&self, pin: usize) -> bool {
-        self.side1 == pin || self.side2 == pin
-    }
-}
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
 
-impl FromStr for Port {
-    type Err = Error;
+use structopt::StructOpt;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.trim();
-        let mut parts = s.split('/');
-        let side1 = parts.next().unwrap().parse()?;
-        let side2 = parts.next().unwrap().parse()?;
-        Ok(Port { side1, side2 })
-    }
-}
+extern crate aoc2017;
+use aoc2017::bridges::{find_bridge, pareto_frontier, Bridge, ComponentGraph, ObjectiveKind, Port};
 
-impl fmt::Display for Port {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}/{}", self.side1, self.side2)
-    }
-}
+use std::{io, process};
+use std::io::BufRead;
 
 fn read_input() -> Result<Vec<Port>, Error> {
     let stdin = io::stdin();
@@ -75,105 +33,50 @@ fn read_input() -> Result<Vec<Port>, Error> {
     Ok(ports)
 }
 
-#[derive(Debug, Clone)]
-struct Bridge {
-    ports: Vec<Port>,
-    used_pins: HashSet<Port>,
-    next_pin: usize,
-    value: usize,
+#[derive(StructOpt, Debug)]
+#[structopt(name = "day24-1", about = "Solver for day 24, part 1.")]
+struct Opt {
+    #[structopt(short = "o", long = "objective",
+                help = "What to maximise: strength, length or length-strength",
+                default_value = "strength")]
+    objective: ObjectiveKind,
+    #[structopt(short = "b", long = "bridge", help = "Also print the chosen bridge")]
+    show_bridge: bool,
+    #[structopt(short = "f", long = "frontier",
+                help = "Print the Pareto frontier over (length, strength) instead")]
+    frontier: bool,
+    #[structopt(short = "m", long = "maximal",
+                help = "Print every bridge that can not be extended instead")]
+    maximal: bool,
 }
 
-impl Bridge {
-    fn new(start: Port) -> Bridge {
-        let ports = vec![start];
-        let next_pin = start.other(0);
-        let used_pins = [start].iter().cloned().collect();
-        let value = Bridge::compute_value(&ports);
-        Bridge {
-            ports,
-            used_pins,
-            next_pin,
-            value,
-        }
-    }
-
-    fn compute_value(ports: &[Port]) -> usize {
-        ports.iter().map(Port::value).sum()
-    }
-
-    fn is_extension(&self, port: &Port) -> bool {
-        port.has_pin(self.next_pin) && !self.used_pins.contains(port)
-    }
-
-    fn extend(&self, port: &Port) -> Bridge {
-        assert!(
-            self.is_extension(port),
-            "Bridge {} can not be exended by {}",
-            self,
-            port
-        );
-        let mut ports = self.ports.clone();
-        ports.push(*port);
-        let next_pin = port.other(self.next_pin);
-        let mut used_pins = self.used_pins.clone();
-        used_pins.insert(*port);
-        let value = Bridge::compute_value(&ports);
-        Bridge {
-            ports,
-            used_pins,
-            next_pin,
-            value,
-        }
-    }
-}
+fn run() -> Result<(), Error> {
+    let opt = Opt::from_args();
+    let ports = read_input()?;
+    let graph = ComponentGraph::new(&ports)?;
 
-impl fmt::Display for Bridge {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (num, port) in self.ports.iter().enumerate() {
-            write!(f, "{}", port)?;
-            if num != self.ports.len() - 1 {
-                write!(f, "--")?;
-            }
+    if opt.frontier || opt.maximal {
+        let bridges = graph.bridges();
+        let bridges: Vec<Bridge> = if opt.frontier {
+            pareto_frontier(bridges.map(|(bridge, _)| bridge))
+        } else {
+            bridges
+                .filter(|&(_, maximal)| maximal)
+                .map(|(bridge, _)| bridge)
+                .collect()
+        };
+        for bridge in bridges {
+            println!("{} {} {}", bridge.length(), bridge.value, bridge);
         }
-        Ok(())
+        return Ok(());
     }
-}
-
-fn build(bridge: Bridge, ports: &[Port]) -> Bridge {
-    //    println!("Building on {}, extensions {:?}", bridge,
-    //        ports.iter().filter(|p| bridge.is_extension(p)).collect::<Vec<_>>()
-    //    );
-    ports
-        .iter()
-        .filter(|p| bridge.is_extension(p))
-        .map(|p| build(bridge.extend(p), ports))
-        .max_by_key(|b| b.value)
-        .unwrap_or(bridge)
-        .clone()
-}
-
-fn find_bridge(ports: &[Port]) -> Result<Bridge, Error> {
-    let start_bridges: Vec<Bridge> = ports
-        .iter()
-        .filter(|p| p.has_pin(0))
-        .map(|p| Bridge::new(*p))
-        .collect();
 
-    Ok(
-        start_bridges
-            .into_iter()
-            .map(|b| build(b, ports))
-            .max_by_key(|b| b.value)
-            .unwrap(),
-    )
-}
-
-fn run() -> Result<(), Error> {
-    let ports = read_input()?;
-
-    let bridge = find_bridge(&ports)?;
+    let bridge = find_bridge(&graph, opt.objective);
 
     println!("{}", bridge.value);
+    if opt.show_bridge {
+        println!("{}", bridge);
+    }
     Ok(())
 }
 
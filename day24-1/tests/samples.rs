@@ -19,6 +19,73 @@ fn sample1() {
 }
 
 
+#[test]
+fn sample1_length() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-o", "length", "-b"])
+        .stdin(
+            "0/2
+2/2
+2/3
+3/4
+3/5
+0/1
+10/1
+9/10",
+        )
+        .stdout()
+        .is("18\n0/2--2/2--2/3--3/4")
+        .unwrap();
+}
+
+
+#[test]
+fn sample1_frontier() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-f"])
+        .stdin(
+            "0/2
+2/2
+2/3
+3/4
+3/5
+0/1
+10/1
+9/10",
+        )
+        .stdout()
+        .is("3 31 0/1--1/10--10/9\n4 19 0/2--2/2--2/3--3/5")
+        .unwrap();
+}
+
+
+#[test]
+fn sample1_maximal() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-m"])
+        .stdin(
+            "0/2
+2/2
+2/3
+3/4
+3/5
+0/1
+10/1
+9/10",
+        )
+        .stdout()
+        .is(
+            "\
+4 18 0/2--2/2--2/3--3/4
+4 19 0/2--2/2--2/3--3/5
+3 14 0/2--2/3--3/4
+3 15 0/2--2/3--3/5
+3 31 0/1--1/10--10/9",
+        )
+        .unwrap();
+}
+
+
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
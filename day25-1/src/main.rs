@@ -10,14 +10,22 @@ extern crate strum_macros;
 extern crate rayon;
 use rayon::prelude::*;
 
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+
+use structopt::StructOpt;
+
 use std::{io, process};
-use std::io::BufRead;
+use std::io::{BufRead, Read};
 use std::collections::{HashMap, VecDeque};
 use std::str::FromStr;
 use std::str;
 use std::char;
 use std::ops::*;
 use std::fmt;
+use std::cmp::{max, min};
+use std::u64;
 
 extern crate aoc2017;
 use aoc2017::matrix::*;
@@ -78,15 +86,309 @@ impl Tape {
     }
 }
 
-type StateName = char;
+type StateName = String;
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 struct Action {
     write: Symbol,
     step: Direction,
     next: StateName,
 }
 
+/// A state of a blueprint, with the transitions in the order they were written.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct StateDefinition {
+    name: StateName,
+    transitions: Vec<(Symbol, Action)>,
+}
+
+/// A Turing machine blueprint.
+///
+/// States may have any name and any number of transitions. A transition to a
+/// state that is not defined, or a symbol that a state has no transition for,
+/// halts the machine.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Blueprint {
+    initial_state: StateName,
+    steps: usize,
+    states: Vec<StateDefinition>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum BlueprintErrorKind {
+    Expected { expected: &'static str, found: String },
+    UnexpectedEnd { expected: &'static str },
+    InvalidNumber(String),
+    InvalidDirection(String),
+    DuplicateState(StateName),
+    DuplicateCondition(Symbol),
+    DuplicateAction(&'static str),
+    MissingAction(&'static str),
+    UnknownInitialState(StateName),
+}
+
+use BlueprintErrorKind::*;
+
+impl fmt::Display for BlueprintErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expected {
+                expected,
+                ref found,
+            } => write!(f, "expected {}, found \"{}\"", expected, found),
+            UnexpectedEnd { expected } => write!(f, "expected {}, found end of input", expected),
+            InvalidNumber(ref s) => write!(f, "\"{}\" is not a valid number", s),
+            InvalidDirection(ref s) => write!(f, "\"{}\" is not a direction", s),
+            DuplicateState(ref name) => write!(f, "state {} is defined twice", name),
+            DuplicateCondition(symbol) => write!(f, "value {} is handled twice", symbol),
+            DuplicateAction(action) => write!(f, "{} is given twice", action),
+            MissingAction(action) => write!(f, "{} is missing", action),
+            UnknownInitialState(ref name) => write!(f, "initial state {} is not defined", name),
+        }
+    }
+}
+
+#[derive(Fail, Debug)]
+#[fail(display = "Line {}: {}", line, kind)]
+struct BlueprintError {
+    line: usize,
+    kind: BlueprintErrorKind,
+}
+
+impl BlueprintError {
+    fn new(line: usize, kind: BlueprintErrorKind) -> BlueprintError {
+        BlueprintError { line, kind }
+    }
+}
+
+/// The non-empty lines of a blueprint, trimmed and numbered from 1.
+struct BlueprintLines<'a> {
+    lines: Vec<(usize, &'a str)>,
+    next: usize,
+    last_line: usize,
+}
+
+impl<'a> BlueprintLines<'a> {
+    fn new(s: &'a str) -> BlueprintLines<'a> {
+        let lines: Vec<(usize, &str)> = s.lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|&(_, line)| !line.is_empty())
+            .collect();
+        BlueprintLines {
+            lines,
+            next: 0,
+            last_line: s.lines().count(),
+        }
+    }
+
+    fn peek(&self) -> Option<(usize, &'a str)> {
+        self.lines.get(self.next).cloned()
+    }
+
+    /// The part of the next line between prefix and suffix.
+    fn expect(
+        &mut self,
+        expected: &'static str,
+        prefix: &str,
+        suffix: &str,
+    ) -> Result<(usize, &'a str), BlueprintError> {
+        match self.peek() {
+            None => Err(BlueprintError::new(
+                self.last_line,
+                UnexpectedEnd { expected },
+            )),
+            Some((line, text)) => match between(text, prefix, suffix) {
+                Some(value) => {
+                    self.next += 1;
+                    Ok((line, value))
+                }
+                None => Err(BlueprintError::new(
+                    line,
+                    Expected {
+                        expected,
+                        found: text.to_string(),
+                    },
+                )),
+            },
+        }
+    }
+}
+
+/// The trimmed text between prefix and suffix, if s has them and the text is non-empty.
+fn between<'a>(s: &'a str, prefix: &str, suffix: &str) -> Option<&'a str> {
+    if s.len() >= prefix.len() + suffix.len() && s.starts_with(prefix) && s.ends_with(suffix) {
+        let value = s[prefix.len()..s.len() - suffix.len()].trim();
+        if !value.is_empty() && !value.contains(char::is_whitespace) {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn parse_number<T: FromStr>(line: usize, s: &str) -> Result<T, BlueprintError> {
+    s.parse()
+        .map_err(|_| BlueprintError::new(line, InvalidNumber(s.to_string())))
+}
+
+fn parse_action(lines: &mut BlueprintLines) -> Result<Action, BlueprintError> {
+    let mut write = None;
+    let mut step = None;
+    let mut next = None;
+    let mut first_line = None;
+    while let Some((line, text)) = lines.peek() {
+        if !text.starts_with('-') {
+            break;
+        }
+        first_line = first_line.or(Some(line));
+        if text.starts_with("- Write") {
+            let (line, value) = lines.expect("a write action", "- Write the value", ".")?;
+            ensure_unset(line, &write, "the value to write")?;
+            write = Some(parse_number(line, value)?);
+        } else if text.starts_with("- Move") {
+            let (line, value) = lines.expect("a move action", "- Move one slot to the", ".")?;
+            ensure_unset(line, &step, "the direction to move")?;
+            step = Some(value
+                .parse()
+                .map_err(|_| BlueprintError::new(line, InvalidDirection(value.to_string())))?);
+        } else if text.starts_with("- Continue") {
+            let (line, value) = lines.expect("a continue action", "- Continue with state", ".")?;
+            ensure_unset(line, &next, "the next state")?;
+            next = Some(value.to_string());
+        } else {
+            return Err(BlueprintError::new(
+                line,
+                Expected {
+                    expected: "a write, move or continue action",
+                    found: text.to_string(),
+                },
+            ));
+        }
+    }
+
+    let line = match first_line {
+        Some(line) => line,
+        None => lines.peek().map_or(lines.last_line, |(line, _)| line),
+    };
+    Ok(Action {
+        write: write.ok_or_else(|| BlueprintError::new(line, MissingAction("the value to write")))?,
+        step: step.ok_or_else(|| BlueprintError::new(line, MissingAction("the direction to move")))?,
+        next: next.ok_or_else(|| BlueprintError::new(line, MissingAction("the next state")))?,
+    })
+}
+
+fn ensure_unset<T>(line: usize, value: &Option<T>, action: &'static str) -> Result<(), BlueprintError> {
+    match *value {
+        Some(_) => Err(BlueprintError::new(line, DuplicateAction(action))),
+        None => Ok(()),
+    }
+}
+
+fn parse_state(lines: &mut BlueprintLines) -> Result<StateDefinition, BlueprintError> {
+    let (_, name) = lines.expect("a state definition", "In state", ":")?;
+    let mut transitions: Vec<(Symbol, Action)> = Vec::new();
+    while let Some((_, text)) = lines.peek() {
+        if !text.starts_with("If") {
+            break;
+        }
+        let (line, value) = lines.expect("a condition", "If the current value is", ":")?;
+        let symbol = parse_number(line, value)?;
+        if transitions.iter().any(|&(s, _)| s == symbol) {
+            return Err(BlueprintError::new(line, DuplicateCondition(symbol)));
+        }
+        transitions.push((symbol, parse_action(lines)?));
+    }
+    Ok(StateDefinition {
+        name: name.to_string(),
+        transitions,
+    })
+}
+
+impl Blueprint {
+    fn parse(s: &str) -> Result<Blueprint, BlueprintError> {
+        let mut lines = BlueprintLines::new(s);
+        let (initial_line, initial_state) =
+            lines.expect("the initial state", "Begin in state", ".")?;
+        let (steps_line, steps) = match lines.peek() {
+            Some((_, text)) if text.ends_with("step.") => lines.expect(
+                "the number of steps",
+                "Perform a diagnostic checksum after",
+                "step.",
+            )?,
+            _ => lines.expect(
+                "the number of steps",
+                "Perform a diagnostic checksum after",
+                "steps.",
+            )?,
+        };
+        let steps = parse_number(steps_line, steps)?;
+
+        let mut states: Vec<StateDefinition> = Vec::new();
+        while let Some((line, _)) = lines.peek() {
+            let state = parse_state(&mut lines)?;
+            if states.iter().any(|s| s.name == state.name) {
+                return Err(BlueprintError::new(line, DuplicateState(state.name)));
+            }
+            states.push(state);
+        }
+
+        if !states.iter().any(|s| s.name == initial_state) {
+            return Err(BlueprintError::new(
+                initial_line,
+                UnknownInitialState(initial_state.to_string()),
+            ));
+        }
+
+        Ok(Blueprint {
+            initial_state: initial_state.to_string(),
+            steps,
+            states,
+        })
+    }
+}
+
+impl FromStr for Blueprint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Blueprint::parse(s)?)
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Right => write!(f, "right"),
+            Left => write!(f, "left"),
+        }
+    }
+}
+
+impl fmt::Display for Blueprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Begin in state {}.", self.initial_state)?;
+        write!(
+            f,
+            "Perform a diagnostic checksum after {} step{}.",
+            self.steps,
+            if self.steps == 1 { "" } else { "s" }
+        )?;
+        for state in &self.states {
+            writeln!(f)?;
+            writeln!(f)?;
+            write!(f, "In state {}:", state.name)?;
+            for &(symbol, ref action) in &state.transitions {
+                writeln!(f)?;
+                writeln!(f, "  If the current value is {}:", symbol)?;
+                writeln!(f, "    - Write the value {}.", action.write)?;
+                writeln!(f, "    - Move one slot to the {}.", action.step)?;
+                write!(f, "    - Continue with state {}.", action.next)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Machine {
     tape: Tape,
@@ -95,70 +397,456 @@ struct Machine {
 }
 
 impl Machine {
-    fn step(&mut self) {
+    fn new(blueprint: &Blueprint) -> Machine {
+        let states = blueprint
+            .states
+            .iter()
+            .map(|state| {
+                (
+                    state.name.clone(),
+                    state.transitions.iter().cloned().collect(),
+                )
+            })
+            .collect();
+        Machine {
+            tape: Tape::new(),
+            state: blueprint.initial_state.clone(),
+            states,
+        }
+    }
+
+    /// Perform one step, returning false if the machine has halted.
+    fn step(&mut self) -> bool {
         let current_value: Symbol = self.tape.read();
-        let action: Action = self.states[&self.state][&current_value];
+        let action = match self.states
+            .get(&self.state)
+            .and_then(|transitions| transitions.get(&current_value))
+        {
+            Some(action) => action.clone(),
+            None => return false,
+        };
         self.tape.write(action.write);
         self.tape.step(action.step);
         self.state = action.next;
+        true
     }
 }
 
 
-fn is_extra_word(word: &str) -> bool {
-    match word.to_lowercase().as_ref() {
-        "" | "-" | "begin" | "in" | "state" | "perform" | "diagnostic" | "checksum" | "after" |
-        "steps" | "if" | "the" | "current" | "value" | "is" | "write" | "move" | "one" |
-        "slot" | "to" | "continue" | "with" => true,
-        _ => false,
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Transition {
+    write: Symbol,
+    step: Direction,
+    next: usize,
+}
+
+/// A maximal block of identical symbols on the tape.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Run {
+    symbol: Symbol,
+    count: u64,
+}
+
+/// One side of a run-length encoded tape, with the run next to the head last.
+///
+/// Beyond the stored runs, the tape is blank (all zeros).
+#[derive(Debug, Clone, Default)]
+struct HalfTape {
+    runs: Vec<Run>,
+}
+
+impl HalfTape {
+    fn push(&mut self, symbol: Symbol, count: u64) {
+        if let Some(run) = self.runs.last_mut() {
+            if run.symbol == symbol {
+                run.count += count;
+                return;
+            }
+        }
+        if symbol == 0 && self.runs.is_empty() {
+            // Blanks at the end are implicit
+            return;
+        }
+        self.runs.push(Run { symbol, count });
+    }
+
+    /// The number of cells next to the head that hold symbol.
+    fn leading(&self, symbol: Symbol) -> Option<u64> {
+        match self.runs.last() {
+            Some(run) if run.symbol == symbol => Some(run.count),
+            Some(_) => Some(0),
+            None if symbol == 0 => None,
+            None => Some(0),
+        }
+    }
+
+    /// Remove count cells next to the head, which must all hold the same symbol.
+    fn remove(&mut self, count: u64) {
+        if count == 0 {
+            return;
+        }
+        if let Some(run) = self.runs.last_mut() {
+            assert!(run.count >= count);
+            run.count -= count;
+            if run.count > 0 {
+                return;
+            }
+        } else {
+            return;
+        }
+        self.runs.pop();
     }
+
+    /// Take the cell next to the head.
+    fn pop(&mut self) -> Symbol {
+        match self.runs.last().map(|run| run.symbol) {
+            Some(symbol) => {
+                self.remove(1);
+                symbol
+            }
+            None => 0,
+        }
+    }
+
+    fn count(&self, symbol: Symbol) -> u64 {
+        self.runs
+            .iter()
+            .filter(|run| run.symbol == symbol)
+            .map(|run| run.count)
+            .sum()
+    }
+}
+
+/// A Turing machine compiled to a dense transition table, running on a
+/// run-length encoded tape.
+///
+/// When a state moves over a symbol and stays in the same state, it will sweep
+/// over the whole block of that symbol in the same way. Such sweeps are done as
+/// a single macro step, independent of the length of the block.
+#[derive(Debug, Clone)]
+struct CompiledMachine {
+    names: Vec<StateName>,
+    defined: usize,
+    symbols: usize,
+    table: Vec<Option<Transition>>,
+    state: usize,
+    head: Symbol,
+    left: HalfTape,
+    right: HalfTape,
+    steps: u64,
+    position: i64,
+    visits: Vec<u64>,
 }
 
-fn read_input() -> Result<(usize, Machine), Error> {
-    let stdin = io::stdin();
-    let mut words = Vec::new();
-    for line in stdin.lock().lines() {
-        let line = line?;
-        let line = line.trim().trim_matches('.').trim_matches(':');
-        for word in line.split_whitespace().filter(|w| !is_extra_word(w)) {
-            words.push(word.to_string())
+impl CompiledMachine {
+    fn new(blueprint: &Blueprint) -> CompiledMachine {
+        let names: Vec<StateName> = blueprint.states.iter().map(|s| s.name.clone()).collect();
+        let index: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.as_str(), index))
+            .collect();
+        let symbols = blueprint
+            .states
+            .iter()
+            .flat_map(|s| s.transitions.iter())
+            .map(|&(symbol, ref action)| max(symbol, action.write) as usize + 1)
+            .max()
+            .unwrap_or(1);
+
+        let mut table = vec![None; names.len() * symbols];
+        let mut halt_names = Vec::new();
+        for (state_index, state) in blueprint.states.iter().enumerate() {
+            for &(symbol, ref action) in &state.transitions {
+                let next = match index.get(action.next.as_str()) {
+                    Some(&next) => next,
+                    None => {
+                        // Undefined states halt, they get indices after the defined ones
+                        let position = halt_names.iter().position(|n| n == &action.next);
+                        names.len() + position.unwrap_or_else(|| {
+                            halt_names.push(action.next.clone());
+                            halt_names.len() - 1
+                        })
+                    }
+                };
+                table[state_index * symbols + symbol as usize] = Some(Transition {
+                    write: action.write,
+                    step: action.step,
+                    next,
+                });
+            }
+        }
+        let state = index[blueprint.initial_state.as_str()];
+        let defined = names.len();
+        let mut names = names;
+        names.extend(halt_names);
+        let visits = vec![0; names.len()];
+
+        CompiledMachine {
+            names,
+            defined,
+            symbols,
+            table,
+            state,
+            head: 0,
+            left: HalfTape::default(),
+            right: HalfTape::default(),
+            steps: 0,
+            position: 0,
+            visits,
+        }
+    }
+
+    fn transition(&self, state: usize, symbol: Symbol) -> Option<Transition> {
+        if state < self.defined && (symbol as usize) < self.symbols {
+            self.table[state * self.symbols + symbol as usize]
+        } else {
+            None
+        }
+    }
+
+    fn state_name(&self) -> &str {
+        &self.names[self.state]
+    }
+
+    fn halted(&self) -> bool {
+        self.transition(self.state, self.head).is_none()
+    }
+
+    /// Take one macro step, without going past max_steps steps in total.
+    ///
+    /// Returns false if no step was taken, because the machine has halted or
+    /// max_steps has been reached.
+    fn step(&mut self, max_steps: u64) -> bool {
+        if self.steps >= max_steps {
+            return false;
+        }
+        let state = self.state;
+        let transition = match self.transition(state, self.head) {
+            Some(transition) => transition,
+            None => return false,
+        };
+        let (behind, ahead) = match transition.step {
+            Right => (&mut self.left, &mut self.right),
+            Left => (&mut self.right, &mut self.left),
+        };
+
+        let remaining = max_steps - self.steps;
+        let cells = if transition.next == state {
+            // Sweep over the head and the identical cells ahead of it
+            let block = ahead.leading(self.head).map_or(u64::MAX, |count| count + 1);
+            min(block, remaining)
+        } else {
+            1
+        };
+        behind.push(transition.write, cells);
+        ahead.remove(cells - 1);
+        self.head = ahead.pop();
+        self.steps += cells;
+        self.visits[state] += cells;
+        self.position += match transition.step {
+            Right => cells as i64,
+            Left => -(cells as i64),
+        };
+        self.state = transition.next;
+        true
+    }
+
+    /// Run until max_steps steps have been taken in total or the machine halts.
+    ///
+    /// Returns false if the machine halted.
+    fn run(&mut self, max_steps: u64) -> bool {
+        while self.step(max_steps) {}
+        !self.halted()
+    }
+
+    /// The cells from radius cells left of the head to radius cells right of it.
+    fn window(&self, radius: usize) -> Vec<Symbol> {
+        fn side(half: &HalfTape, radius: usize) -> Vec<Symbol> {
+            let mut cells = Vec::with_capacity(radius);
+            for run in half.runs.iter().rev() {
+                let count = min(run.count, (radius - cells.len()) as u64);
+                cells.extend((0..count).map(|_| run.symbol));
+            }
+            while cells.len() < radius {
+                cells.push(0);
+            }
+            cells
         }
+        let mut window = side(&self.left, radius);
+        window.reverse();
+        window.push(self.head);
+        window.extend(side(&self.right, radius));
+        window
     }
-    let initial_state: StateName = words[0].parse()?;
-    let iterations: usize = words[2].parse()?;
-    let mut states = HashMap::new();
-    for state in words[3..].chunks(9) {
-        let name: StateName = state[0].parse()?;
-        let mut transitions = HashMap::new();
-        for transition_data in state[1..].chunks(4) {
-            let condition: Symbol = transition_data[0].parse()?;
-            let state = Action {
-                write: transition_data[1].parse()?,
-                step: transition_data[2].parse()?,
-                next: transition_data[3].parse()?,
-            };
-            transitions.insert(condition, state);
+
+    /// The tape around the head as text, with the head cell in brackets.
+    fn render(&self, radius: usize) -> String {
+        let window = self.window(radius);
+        let mut result = String::new();
+        for (offset, &symbol) in window.iter().enumerate() {
+            let symbol = char::from_digit(u32::from(symbol), 36).unwrap_or('?');
+            if offset == radius {
+                result.push('[');
+                result.push(symbol);
+                result.push(']');
+            } else {
+                result.push(symbol);
+            }
         }
-        states.insert(name, transitions);
+        result
+    }
+
+    /// The number of ones on the tape.
+    fn checksum(&self) -> u64 {
+        self.left.count(1) + self.right.count(1) + if self.head == 1 { 1 } else { 0 }
     }
+}
+
+/// Run machine while printing snapshots of the tape and looking for repeated configurations.
+///
+/// A configuration is the state together with the tape window around the head.
+/// When a configuration repeats, possibly with the head at another position, the
+/// machine is likely stuck in a (translated) cycle.
+fn inspect(machine: &mut CompiledMachine, steps: u64, opt: &Opt) -> Result<(), Error> {
+    let snapshot_every = opt.snapshot_every.unwrap_or(u64::MAX);
+    ensure!(snapshot_every > 0, "Snapshots must be at least one step apart");
 
-    let machine = Machine {
-        tape: Tape::new(),
-        state: initial_state,
-        states,
+    let mut seen: HashMap<(usize, Vec<Symbol>), (u64, i64)> = HashMap::new();
+    let mut check_cycles = opt.cycles;
+    let mut printed_at = None;
+    let mut print_snapshot = |machine: &CompiledMachine| {
+        if opt.snapshot_every.is_some() && printed_at != Some(machine.steps) {
+            println!(
+                "{:>10} {:>6} {:>+8} {}",
+                machine.steps,
+                machine.state_name(),
+                machine.position,
+                machine.render(opt.window)
+            );
+            printed_at = Some(machine.steps);
+        }
     };
-    Ok((iterations, machine))
+
+    print_snapshot(machine);
+    loop {
+        let next_stop = min(
+            steps,
+            machine.steps.saturating_add(snapshot_every - machine.steps % snapshot_every),
+        );
+        if machine.step(next_stop) {
+            if check_cycles {
+                let key = (machine.state, machine.window(opt.window));
+                if let Some(&(steps, position)) = seen.get(&key) {
+                    println!(
+                        "Repeated configuration in state {} after {} steps: first seen after {} \
+                         steps, period {}, shift {:+}",
+                        machine.state_name(),
+                        machine.steps,
+                        steps,
+                        machine.steps - steps,
+                        machine.position - position
+                    );
+                    check_cycles = false;
+                } else {
+                    seen.insert(key, (machine.steps, machine.position));
+                }
+            }
+            if machine.steps % snapshot_every == 0 {
+                print_snapshot(machine);
+            }
+        } else {
+            // Halted or out of steps
+            print_snapshot(machine);
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_input() -> Result<Blueprint, Error> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    input.parse()
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "day25-1", about = "Solver for day 25, part 1.")]
+struct Opt {
+    #[structopt(short = "p", long = "print",
+                help = "Print the parsed blueprint in canonical form instead of running it")]
+    print: bool,
+    #[structopt(short = "n", long = "naive",
+                help = "Run one transition at a time on a plain tape instead")]
+    naive: bool,
+    #[structopt(short = "s", long = "steps", help = "Number of steps to run (default from blueprint)")]
+    steps: Option<u64>,
+    #[structopt(short = "e", long = "snapshot-every",
+                help = "Print the tape around the head every n steps")]
+    snapshot_every: Option<u64>,
+    #[structopt(short = "w", long = "window", help = "Cells shown on each side of the head",
+                default_value = "8")]
+    window: usize,
+    #[structopt(short = "c", long = "cycles",
+                help = "Report the first repetition of state and tape window around the head")]
+    cycles: bool,
+    #[structopt(short = "H", long = "histogram", help = "Print the number of steps taken in each state")]
+    histogram: bool,
+    #[structopt(short = "t", long = "status", help = "Print whether and where the machine halted")]
+    status: bool,
 }
 
 
 fn run() -> Result<(), Error> {
-    let (iterations, mut machine) = read_input()?;
+    let opt = Opt::from_args();
+    let blueprint = read_input()?;
 
-    for _ in 0..iterations {
-        machine.step();
+    if opt.print {
+        println!("{}", blueprint);
+        return Ok(());
     }
 
-    println!("{}", machine.tape.checksum());
+    let steps = opt.steps.unwrap_or(blueprint.steps as u64);
+
+    if opt.naive {
+        let mut machine = Machine::new(&blueprint);
+        for _ in 0..steps {
+            if !machine.step() {
+                break;
+            }
+        }
+        println!("{}", machine.tape.checksum());
+        return Ok(());
+    }
+
+    let mut machine = CompiledMachine::new(&blueprint);
+    if opt.snapshot_every.is_none() && !opt.cycles {
+        machine.run(steps);
+    } else {
+        inspect(&mut machine, steps, &opt)?;
+    }
+
+    println!("{}", machine.checksum());
+    if opt.status {
+        if machine.halted() {
+            println!(
+                "Halted in state {} after {} steps",
+                machine.state_name(),
+                machine.steps
+            );
+        } else {
+            println!(
+                "Running in state {} after {} steps",
+                machine.state_name(),
+                machine.steps
+            );
+        }
+    }
+    if opt.histogram {
+        for (name, visits) in machine.names.iter().zip(&machine.visits) {
+            println!("{}: {}", name, visits);
+        }
+    }
     Ok(())
 }
 
@@ -34,6 +34,233 @@ In state B:
 }
 
 
+#[test]
+fn multi_symbol_named_states() {
+    assert_cli::Assert::main_binary()
+        .stdin(
+            "\
+Begin in state Start.
+Perform a diagnostic checksum after 100 steps.
+
+In state Start:
+  If the current value is 0:
+    - Write the value 2.
+    - Move one slot to the right.
+    - Continue with state Other.
+  If the current value is 2:
+    - Continue with state Start.
+    - Write the value 1.
+    - Move one slot to the left.
+
+In state Other:
+  If the current value is 0:
+    - Write the value 1.
+    - Move one slot to the left.
+    - Continue with state Halt.",
+        )
+        .stdout()
+        .is("1")
+        .unwrap();
+}
+
+
+#[test]
+fn print_blueprint() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-p"])
+        .stdin(
+            "\
+Begin in state Start.
+Perform a diagnostic checksum after 1 step.
+In state Start:
+    If the current value is 2:
+- Continue with state Start.
+- Move one slot to the left.
+- Write the value 1.",
+        )
+        .stdout()
+        .is(
+            "\
+Begin in state Start.
+Perform a diagnostic checksum after 1 step.
+
+In state Start:
+  If the current value is 2:
+    - Write the value 1.
+    - Move one slot to the left.
+    - Continue with state Start.",
+        )
+        .unwrap();
+}
+
+
+#[test]
+fn missing_clause() {
+    assert_cli::Assert::main_binary()
+        .stdin(
+            "\
+Begin in state A.
+Perform a diagnostic checksum after 6 steps.
+
+In state A:
+  If the current value is 0:
+    - Write the value 1.
+    - Continue with state A.",
+        )
+        .fails()
+        .stderr()
+        .contains("Line 6: the direction to move is missing")
+        .unwrap();
+}
+
+
+#[test]
+fn reworded_clause() {
+    assert_cli::Assert::main_binary()
+        .stdin(
+            "\
+Begin in state A.
+Perform a diagnostic checksum after 6 steps.
+
+In state A:
+  If the current value is 0:
+    - Write the value 1.
+    - Go one slot to the right.
+    - Continue with state A.",
+        )
+        .fails()
+        .stderr()
+        .contains("Line 7: expected a write, move or continue action")
+        .unwrap();
+}
+
+
+#[test]
+fn puzzle1_naive() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-n", "-s", "100000"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("377")
+        .unwrap();
+}
+
+
+#[test]
+fn puzzle1_macro_steps() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-s", "100000"])
+        .stdin(include_str!("../data/puzzle1.in"))
+        .stdout()
+        .is("377")
+        .unwrap();
+}
+
+
+#[test]
+fn busy_beaver5() {
+    assert_cli::Assert::main_binary()
+        .stdin(include_str!("../data/busy_beaver5.in"))
+        .stdout()
+        .is("4098")
+        .unwrap();
+}
+
+
+#[test]
+fn sample1_inspection() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-e", "2", "-w", "3", "-t", "-H"])
+        .stdin(
+            "\
+Begin in state A.
+Perform a diagnostic checksum after 6 steps.
+
+In state A:
+  If the current value is 0:
+    - Write the value 1.
+    - Move one slot to the right.
+    - Continue with state B.
+  If the current value is 1:
+    - Write the value 0.
+    - Move one slot to the left.
+    - Continue with state B.
+
+In state B:
+  If the current value is 0:
+    - Write the value 1.
+    - Move one slot to the left.
+    - Continue with state A.
+  If the current value is 1:
+    - Write the value 1.
+    - Move one slot to the right.
+    - Continue with state A.",
+        )
+        .stdout()
+        .is(
+            "         0      A       +0 000[0]000
+         2      A       +0 000[1]100
+         4      A       -2 000[0]101
+         6      A       +0 011[0]100
+3
+Running in state A after 6 steps
+A: 3
+B: 3",
+        )
+        .unwrap();
+}
+
+
+#[test]
+fn sample1_cycle() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-c", "-w", "2", "-s", "100"])
+        .stdin(
+            "\
+Begin in state A.
+Perform a diagnostic checksum after 6 steps.
+
+In state A:
+  If the current value is 0:
+    - Write the value 1.
+    - Move one slot to the right.
+    - Continue with state B.
+  If the current value is 1:
+    - Write the value 0.
+    - Move one slot to the left.
+    - Continue with state B.
+
+In state B:
+  If the current value is 0:
+    - Write the value 1.
+    - Move one slot to the left.
+    - Continue with state A.
+  If the current value is 1:
+    - Write the value 1.
+    - Move one slot to the right.
+    - Continue with state A.",
+        )
+        .stdout()
+        .is(
+            "\
+Repeated configuration in state A after 12 steps: first seen after 6 steps, period 6, shift +2
+36",
+        )
+        .unwrap();
+}
+
+
+#[test]
+fn busy_beaver5_halts() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-t"])
+        .stdin(include_str!("../data/busy_beaver5.in"))
+        .stdout()
+        .is("4098\nHalted in state H after 47176870 steps")
+        .unwrap();
+}
+
+
 #[test]
 fn puzzle1() {
     assert_cli::Assert::main_binary()
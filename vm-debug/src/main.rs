@@ -0,0 +1,56 @@
+extern crate failure;
+use failure::Error;
+
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+extern crate aoc2017;
+
+use structopt::StructOpt;
+
+use aoc2017::vm::assembler::assemble_with_labels;
+use aoc2017::vm::debugger::Debugger;
+
+use std::{io, process};
+use std::io::{BufRead, Read};
+use std::fs::File;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "vm-debug",
+            about = "Debugger for the programs of days 18 and 23, reading commands from standard input.")]
+struct Opt {
+    #[structopt(help = "The file with the program to debug")]
+    program: String,
+}
+
+fn run() -> Result<(), Error> {
+    let opt = Opt::from_args();
+    let mut text = String::new();
+    File::open(&opt.program)?.read_to_string(&mut text)?;
+    let (program, labels) = assemble_with_labels(&text)?;
+    let mut debugger = Debugger::new(program, labels);
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        match debugger.command(&line?) {
+            Ok(ref output) if output.is_empty() => {}
+            Ok(output) => println!("{}", output),
+            Err(error) => println!("error: {}", error),
+        }
+        if debugger.quit() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    match run() {
+        Ok(()) => process::exit(0),
+        Err(error) => {
+            for cause in error.causes() {
+                eprintln!("{}", cause)
+            }
+            process::exit(1)
+        }
+    }
+}
@@ -0,0 +1,130 @@
+extern crate assert_cli;
+
+#[test]
+fn breakpoint_by_label() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["../day23-2/data/symbolic_puzzle.in"])
+        .stdin(
+            "\
+break l
+continue
+registers
+step 3",
+        )
+        .stdout()
+        .is(
+            "\
+breakpoint at 11 (l): set g d
+breakpoint at 11 (l): set g d
+a=0 b=79 c=79 d=2 e=2 f=1 g=0 h=0
+at 14 (o): jnz g 2",
+        )
+        .unwrap();
+}
+
+#[test]
+fn breakpoint_by_pc() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["../day23-2/data/symbolic_puzzle.in"])
+        .stdin(
+            "\
+b 23
+c
+c
+delete x
+c",
+        )
+        .stdout()
+        .is(
+            "\
+breakpoint at 23 (x): jnz g -13
+breakpoint at 23 (x): jnz g -13
+breakpoint at 23 (x): jnz g -13
+deleted breakpoint at 23 (x): jnz g -13
+halted at 32",
+        )
+        .unwrap();
+}
+
+#[test]
+fn generated_labels() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["../day23-2/data/original_puzzle.in"])
+        .stdin("break L5")
+        .stdout()
+        .is("breakpoint at 16 (L5): sub e -1")
+        .unwrap();
+}
+
+#[test]
+fn watchpoint() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["../day23-2/data/symbolic_puzzle.in"])
+        .stdin(
+            "\
+watch f
+continue
+continue",
+        )
+        .stdout()
+        .is(
+            "\
+watching f=0
+f changed from 0 to 1
+at 9 (j): set d 2
+halted at 32",
+        )
+        .unwrap();
+}
+
+#[test]
+fn messages() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["data/duet_sample.in"])
+        .stdin(
+            "\
+io
+io
+io
+io
+send 7
+step
+registers",
+        )
+        .stdout()
+        .is(
+            "\
+at 1: snd 2
+at 2: snd p
+at 3: rcv a
+waiting for a value at 3: rcv a
+inbox: [7]
+outbox: [1 2 0]
+sent: 3
+at 4: rcv b
+a=7 b=0 c=0 d=0 p=0",
+        )
+        .unwrap();
+}
+
+#[test]
+fn errors() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["../day23-2/data/symbolic_puzzle.in"])
+        .stdin(
+            "\
+jump
+break nowhere
+step x
+quit
+step",
+        )
+        .stdout()
+        .is(
+            "\
+error: Unknown command \"jump\"
+error: Unknown label nowhere
+error: Not a number: x",
+        )
+        .unwrap();
+}